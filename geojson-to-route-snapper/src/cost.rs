@@ -0,0 +1,171 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+
+/// Calculates the cost of an edge in one direction from its geometry and properties. Written as
+/// terms multiplied together, like `length * factor` or
+/// `length * road_class[primary=1,residential=1.5,*=2]`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CostExpression {
+    /// The length of the edge in meters
+    Length,
+    Constant(f64),
+    /// A numeric property. If it's missing or null, the edge isn't routable.
+    Property(String),
+    /// Looks up the value of a property in a table. Values not in the table use the default, if
+    /// there is one. Otherwise the edge isn't routable.
+    Lookup {
+        property: String,
+        table: Vec<(String, f64)>,
+        default: Option<f64>,
+    },
+    Product(Vec<CostExpression>),
+}
+
+impl CostExpression {
+    /// Returns `None` if the edge isn't routable, or an error if a property has the wrong type.
+    pub fn evaluate(
+        &self,
+        length_meters: f64,
+        properties: &Map<String, Value>,
+    ) -> Result<Option<f64>> {
+        match self {
+            CostExpression::Length => Ok(Some(length_meters)),
+            CostExpression::Constant(x) => Ok(Some(*x)),
+            CostExpression::Property(key) => match properties.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::Number(x)) => Ok(x.as_f64()),
+                Some(Value::String(x)) => match x.parse::<f64>() {
+                    Ok(x) => Ok(Some(x)),
+                    Err(_) => bail!("{key} isn't a number: {x}"),
+                },
+                Some(x) => bail!("{key} isn't a number: {x}"),
+            },
+            CostExpression::Lookup {
+                property,
+                table,
+                default,
+            } => {
+                let value = match properties.get(property) {
+                    None | Some(Value::Null) => return Ok(*default),
+                    Some(Value::String(x)) => x.clone(),
+                    Some(x) => x.to_string(),
+                };
+                Ok(table
+                    .iter()
+                    .find(|(key, _)| *key == value)
+                    .map(|(_, cost)| *cost)
+                    .or(*default))
+            }
+            CostExpression::Product(terms) => {
+                let mut total = 1.0;
+                for term in terms {
+                    let Some(x) = term.evaluate(length_meters, properties)? else {
+                        return Ok(None);
+                    };
+                    total *= x;
+                }
+                Ok(Some(total))
+            }
+        }
+    }
+}
+
+impl FromStr for CostExpression {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        // Split on top-level `*`, not the default inside a lookup table
+        let mut terms = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+        for (idx, c) in input.char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '*' if depth == 0 => {
+                    terms.push(parse_term(&input[start..idx])?);
+                    start = idx + 1;
+                }
+                _ => {}
+            }
+        }
+        terms.push(parse_term(&input[start..])?);
+
+        if terms.len() == 1 {
+            Ok(terms.pop().unwrap())
+        } else {
+            Ok(CostExpression::Product(terms))
+        }
+    }
+}
+
+fn parse_term(term: &str) -> Result<CostExpression> {
+    let term = term.trim();
+    if term.is_empty() {
+        bail!("Cost expression has an empty term");
+    }
+    if term == "length" {
+        return Ok(CostExpression::Length);
+    }
+    if let Ok(x) = term.parse::<f64>() {
+        return Ok(CostExpression::Constant(x));
+    }
+    let Some((property, rest)) = term.split_once('[') else {
+        return Ok(CostExpression::Property(term.to_string()));
+    };
+    let Some(entries) = rest.strip_suffix(']') else {
+        bail!("Lookup table {term} must end with ]");
+    };
+    let mut table = Vec::new();
+    let mut default = None;
+    for entry in entries
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+    {
+        let Some((key, value)) = entry.split_once('=') else {
+            bail!("Lookup table entry {entry} must look like value=cost");
+        };
+        let Ok(cost) = value.trim().parse::<f64>() else {
+            bail!("Lookup table entry {entry} doesn't have a numeric cost");
+        };
+        match key.trim() {
+            "*" => default = Some(cost),
+            key => table.push((key.to_string(), cost)),
+        }
+    }
+    Ok(CostExpression::Lookup {
+        property: property.trim().to_string(),
+        table,
+        default,
+    })
+}
+
+impl fmt::Display for CostExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostExpression::Length => write!(f, "length"),
+            CostExpression::Constant(x) => write!(f, "{x}"),
+            CostExpression::Property(key) => write!(f, "{key}"),
+            CostExpression::Lookup {
+                property,
+                table,
+                default,
+            } => {
+                let mut entries: Vec<String> =
+                    table.iter().map(|(k, v)| format!("{k}={v}")).collect();
+                if let Some(x) = default {
+                    entries.push(format!("*={x}"));
+                }
+                write!(f, "{property}[{}]", entries.join(","))
+            }
+            CostExpression::Product(terms) => {
+                let terms: Vec<String> = terms.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}", terms.join(" * "))
+            }
+        }
+    }
+}
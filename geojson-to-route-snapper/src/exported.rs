@@ -0,0 +1,130 @@
+//! Rebuilds a graph written by `route_snapper_graph::export`. Edges and nodes keep their IDs where
+//! possible, and turns and other settings come from the metadata, so re-importing an unedited file
+//! gives the same graph.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use geo::{Coord, LineString};
+use serde_json::{Map, Value};
+
+use route_snapper_graph::{EdgeID, NodeID, RouteSnapperMap, TileInfo, Turn};
+
+pub use route_snapper_graph::export::ExportMetadata;
+
+/// A Point feature describing a node.
+pub struct ExportedNode {
+    pub id: u32,
+    pub pt: Coord,
+    pub elevation: Option<f32>,
+    pub osm_node_id: Option<i64>,
+}
+
+/// The IDs on an edge feature. Any of these may be missing from edges drawn by hand.
+#[derive(Clone, Default)]
+pub struct ExportedEdge {
+    pub edge_id: Option<u32>,
+    pub node1: Option<u32>,
+    pub node2: Option<u32>,
+    pub osm_way_id: Option<i64>,
+}
+
+pub fn read_node(
+    pt: Coord,
+    properties: &Map<String, Value>,
+    reprojection: Option<&crate::reproject::Reprojection>,
+) -> Result<ExportedNode> {
+    let Some(id) = get_u32(properties, "node_id") else {
+        bail!("a Point doesn't have a node_id");
+    };
+    let mut line = LineString::new(vec![pt]);
+    if let Some(reprojection) = reprojection {
+        reprojection.transform(&mut line)?;
+    }
+    Ok(ExportedNode {
+        id,
+        pt: line.0[0],
+        elevation: properties
+            .get("elevation")
+            .and_then(|x| x.as_f64())
+            .map(|x| x as f32),
+        osm_node_id: properties.get("osm_node_id").and_then(|x| x.as_i64()),
+    })
+}
+
+pub fn read_edge(properties: &Map<String, Value>) -> ExportedEdge {
+    ExportedEdge {
+        edge_id: get_u32(properties, "edge_id"),
+        node1: get_u32(properties, "node1"),
+        node2: get_u32(properties, "node2"),
+        osm_way_id: properties.get("osm_way_id").and_then(|x| x.as_i64()),
+    }
+}
+
+fn get_u32(properties: &Map<String, Value>, key: &str) -> Option<u32> {
+    properties
+        .get(key)
+        .and_then(|x| x.as_u64())
+        .and_then(|x| u32::try_from(x).ok())
+}
+
+/// Adds the turns from the metadata, using `edge_ids` and `node_ids` to map the exported IDs to
+/// new ones. Turns involving deleted edges, or that no longer make sense after editing, are
+/// dropped.
+pub fn add_turns(
+    map: &mut RouteSnapperMap,
+    metadata: &ExportMetadata,
+    edge_ids: &HashMap<u32, EdgeID>,
+    node_ids: &HashMap<u32, NodeID>,
+) -> usize {
+    let mut dropped = 0;
+    for (from, via, to, cost) in &metadata.turns {
+        let turn = match (edge_ids.get(from), node_ids.get(via), edge_ids.get(to)) {
+            (Some(from), Some(via), Some(to)) => Turn {
+                from: *from,
+                via: *via,
+                to: *to,
+                cost: *cost,
+            },
+            _ => {
+                dropped += 1;
+                continue;
+            }
+        };
+        let touches = |edge: EdgeID| {
+            let edge = map.edge(edge);
+            edge.node1 == turn.via || edge.node2 == turn.via
+        };
+        if touches(turn.from) && touches(turn.to) {
+            map.turns.push(turn);
+        } else {
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+/// Restores the tile the graph was exported from. This only works if every edge and node still
+/// matches exactly one from the export, so nothing was split or drawn.
+pub fn restore_tile(
+    map: &RouteSnapperMap,
+    tile: &TileInfo,
+    edge_ids: &HashMap<u32, EdgeID>,
+    node_ids: &HashMap<u32, NodeID>,
+) -> Option<TileInfo> {
+    let mut tile_edge_ids = vec![None; map.edges.len()];
+    for (old, new) in edge_ids {
+        tile_edge_ids[new.0 as usize] = Some(*tile.edge_ids.get(*old as usize)?);
+    }
+    let mut tile_node_ids = vec![None; map.nodes.len()];
+    for (old, new) in node_ids {
+        tile_node_ids[new.0 as usize] = Some(*tile.node_ids.get(*old as usize)?);
+    }
+    Some(TileInfo {
+        size_degrees: tile.size_degrees,
+        x: tile.x,
+        y: tile.y,
+        node_ids: tile_node_ids.into_iter().collect::<Option<Vec<_>>>()?,
+        edge_ids: tile_edge_ids.into_iter().collect::<Option<Vec<_>>>()?,
+    })
+}
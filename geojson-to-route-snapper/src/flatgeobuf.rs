@@ -0,0 +1,695 @@
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+
+use anyhow::{bail, Result};
+use geo::{Coord, LineString, MultiLineString};
+use serde_json::{Map, Value};
+
+use crate::source::{FeatureSource, RawFeature};
+
+// See https://github.com/flatgeobuf/flatgeobuf/blob/master/src/fbs for the schema. Only the few
+// parts needed for lines are decoded here, without generated flatbuffers code.
+
+const LINE_STRING: u8 = 2;
+const MULTI_LINE_STRING: u8 = 5;
+
+/// Reads a FlatGeobuf file one feature at a time, skipping the spatial index.
+pub struct FlatGeobufSource {
+    reader: BufReader<File>,
+    geometry_type: u8,
+    /// (name, type)
+    columns: Vec<(String, u8)>,
+    crs: Option<String>,
+}
+
+impl FlatGeobufSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[0..3] != b"fgb" || &magic[4..7] != b"fgb" {
+            bail!("{path} isn't a FlatGeobuf file");
+        }
+        let header_bytes = read_size_prefixed(&mut reader)?;
+        let header = Table::root(&header_bytes)?;
+
+        let geometry_type = header.u8(2)?.unwrap_or(0);
+        if !matches!(geometry_type, 0 | LINE_STRING | MULTI_LINE_STRING) {
+            bail!("{path} has geometry type {geometry_type}, but only lines are supported");
+        }
+        let mut columns = Vec::new();
+        for column in header.tables(7)? {
+            let name = column.string(0)?.unwrap_or_default().to_string();
+            columns.push((name, column.u8(1)?.unwrap_or(0)));
+        }
+        let crs = match header.table(10)? {
+            Some(crs) => {
+                let org = crs.string(0)?.unwrap_or("EPSG");
+                let code = crs.i32(1)?.unwrap_or(0);
+                (org.eq_ignore_ascii_case("EPSG") && code > 0).then(|| format!("EPSG:{code}"))
+            }
+            None => None,
+        };
+
+        let features_count = header.u64(8)?.unwrap_or(0);
+        let index_node_size = header.u16(9)?.unwrap_or(16);
+        if index_node_size > 0 && features_count > 0 {
+            let index_bytes = index_size(features_count, index_node_size as u64);
+            std::io::copy(&mut (&mut reader).take(index_bytes), &mut std::io::sink())?;
+        }
+
+        Ok(Self {
+            reader,
+            geometry_type,
+            columns,
+            crs,
+        })
+    }
+
+    fn parse_feature(&self, bytes: &[u8]) -> Result<RawFeature> {
+        let feature = Table::root(bytes)?;
+        let geometry = match feature.table(0)? {
+            Some(geometry) => parse_geometry(&geometry, self.geometry_type)?,
+            None => None,
+        };
+
+        let mut properties = Map::new();
+        if let Some(mut bytes) = feature.vector(1, 1)? {
+            while !bytes.is_empty() {
+                let column = u16::from_le_bytes(take(&mut bytes)?) as usize;
+                let Some((name, column_type)) = self.columns.get(column) else {
+                    bail!("property refers to missing column {column}");
+                };
+                let value = match column_type {
+                    0 => i8::from_le_bytes(take(&mut bytes)?).into(),
+                    1 => u8::from_le_bytes(take(&mut bytes)?).into(),
+                    2 => Value::Bool(take::<1>(&mut bytes)?[0] != 0),
+                    3 => i16::from_le_bytes(take(&mut bytes)?).into(),
+                    4 => u16::from_le_bytes(take(&mut bytes)?).into(),
+                    5 => i32::from_le_bytes(take(&mut bytes)?).into(),
+                    6 => u32::from_le_bytes(take(&mut bytes)?).into(),
+                    7 => i64::from_le_bytes(take(&mut bytes)?).into(),
+                    8 => u64::from_le_bytes(take(&mut bytes)?).into(),
+                    9 => f32::from_le_bytes(take(&mut bytes)?).into(),
+                    10 => f64::from_le_bytes(take(&mut bytes)?).into(),
+                    // String, JSON, date-time, and binary all have a length first
+                    11..=14 => {
+                        let length = u32::from_le_bytes(take(&mut bytes)?) as usize;
+                        if bytes.len() < length {
+                            bail!("property {name} is truncated");
+                        }
+                        let (value, rest) = bytes.split_at(length);
+                        bytes = rest;
+                        match column_type {
+                            12 => serde_json::from_slice(value)?,
+                            14 => continue,
+                            _ => String::from_utf8_lossy(value).into(),
+                        }
+                    }
+                    x => bail!("column {name} has unknown type {x}"),
+                };
+                properties.insert(name.clone(), value);
+            }
+        }
+
+        Ok(RawFeature {
+            geometry,
+            properties,
+        })
+    }
+}
+
+impl FeatureSource for FlatGeobufSource {
+    fn crs(&self) -> Option<String> {
+        self.crs.clone()
+    }
+
+    fn for_each_feature(
+        &mut self,
+        f: &mut dyn FnMut(Result<RawFeature>) -> Result<()>,
+    ) -> Result<()> {
+        loop {
+            let bytes = match read_size_prefixed(&mut self.reader) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+                        if err.kind() == ErrorKind::UnexpectedEof {
+                            return Ok(());
+                        }
+                    }
+                    return Err(err);
+                }
+            };
+            f(self.parse_feature(&bytes))?;
+        }
+    }
+}
+
+fn parse_geometry(geometry: &Table, default_type: u8) -> Result<Option<geo::Geometry>> {
+    let geometry_type = match geometry.u8(6)? {
+        Some(x) if x != 0 => x,
+        _ => default_type,
+    };
+    let coords: Vec<Coord> = match geometry.vector(1, 8)? {
+        Some(xy) => xy
+            .chunks_exact(16)
+            .map(|pair| Coord {
+                x: f64::from_le_bytes(pair[0..8].try_into().unwrap()),
+                y: f64::from_le_bytes(pair[8..16].try_into().unwrap()),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    match geometry_type {
+        LINE_STRING => {
+            if coords.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(LineString::new(coords).into()))
+        }
+        MULTI_LINE_STRING => {
+            let parts = geometry.tables(7)?;
+            if !parts.is_empty() {
+                let mut lines = Vec::new();
+                for part in parts {
+                    match parse_geometry(&part, LINE_STRING)? {
+                        Some(geo::Geometry::LineString(line)) => lines.push(line),
+                        _ => bail!("MultiLineString contains something else"),
+                    }
+                }
+                return Ok(Some(MultiLineString::new(lines).into()));
+            }
+
+            // Each end is the number of coordinates up to the end of that line
+            let ends: Vec<usize> = match geometry.vector(0, 4)? {
+                Some(ends) => ends
+                    .chunks_exact(4)
+                    .map(|x| u32::from_le_bytes(x.try_into().unwrap()) as usize)
+                    .collect(),
+                None => vec![coords.len()],
+            };
+            let mut lines = Vec::new();
+            let mut start = 0;
+            for end in ends {
+                if end < start || end > coords.len() {
+                    bail!("MultiLineString has invalid ends");
+                }
+                lines.push(LineString::new(coords[start..end].to_vec()));
+                start = end;
+            }
+            Ok(Some(MultiLineString::new(lines).into()))
+        }
+        x => bail!("geometry type {x} isn't a LineString or MultiLineString"),
+    }
+}
+
+/// The size in bytes of the packed Hilbert R-tree written after the header
+fn index_size(num_items: u64, node_size: u64) -> u64 {
+    let node_size = node_size.clamp(2, 65535);
+    let mut n = num_items;
+    let mut num_nodes = n;
+    loop {
+        n = n.div_ceil(node_size);
+        num_nodes = num_nodes.saturating_add(n);
+        if n == 1 {
+            break;
+        }
+    }
+    num_nodes.saturating_mul(40)
+}
+
+fn read_size_prefixed(reader: &mut BufReader<File>) -> Result<Vec<u8>> {
+    let mut size = [0; 4];
+    reader.read_exact(&mut size)?;
+    let size = u32::from_le_bytes(size) as u64;
+    // Don't allocate a huge buffer up-front for a corrupt size
+    let mut bytes = Vec::new();
+    reader.by_ref().take(size).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != size {
+        bail!("FlatGeobuf data is truncated");
+    }
+    Ok(bytes)
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N]> {
+    if bytes.len() < N {
+        bail!("FlatGeobuf data is truncated");
+    }
+    let (value, rest) = bytes.split_at(N);
+    *bytes = rest;
+    Ok(value.try_into().unwrap())
+}
+
+/// A flatbuffers table at some position in a buffer
+struct Table<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Table<'a> {
+    fn root(buf: &'a [u8]) -> Result<Self> {
+        let pos = read_u32(buf, 0)? as usize;
+        Ok(Self { buf, pos })
+    }
+
+    /// Where a field is stored, or `None` if it has the default value
+    fn field(&self, id: usize) -> Result<Option<usize>> {
+        let vtable = self.pos as i64 - read_u32(self.buf, self.pos)? as i32 as i64;
+        if vtable < 0 {
+            bail!("FlatGeobuf data is corrupt");
+        }
+        let vtable = vtable as usize;
+        let vtable_size = read_u16(self.buf, vtable)? as usize;
+        let entry = 4 + 2 * id;
+        if entry + 2 > vtable_size {
+            return Ok(None);
+        }
+        match read_u16(self.buf, vtable + entry)? {
+            0 => Ok(None),
+            offset => Ok(Some(self.pos + offset as usize)),
+        }
+    }
+
+    /// Follows the offset stored in a field
+    fn indirect(&self, id: usize) -> Result<Option<usize>> {
+        match self.field(id)? {
+            Some(pos) => Ok(Some(pos + read_u32(self.buf, pos)? as usize)),
+            None => Ok(None),
+        }
+    }
+
+    fn u8(&self, id: usize) -> Result<Option<u8>> {
+        match self.field(id)? {
+            Some(pos) => Ok(Some(read::<1>(self.buf, pos)?[0])),
+            None => Ok(None),
+        }
+    }
+
+    fn u16(&self, id: usize) -> Result<Option<u16>> {
+        self.field(id)?
+            .map(|pos| read_u16(self.buf, pos))
+            .transpose()
+    }
+
+    fn i32(&self, id: usize) -> Result<Option<i32>> {
+        self.field(id)?
+            .map(|pos| Ok(i32::from_le_bytes(read(self.buf, pos)?)))
+            .transpose()
+    }
+
+    fn u64(&self, id: usize) -> Result<Option<u64>> {
+        self.field(id)?
+            .map(|pos| Ok(u64::from_le_bytes(read(self.buf, pos)?)))
+            .transpose()
+    }
+
+    fn table(&self, id: usize) -> Result<Option<Table<'a>>> {
+        Ok(self.indirect(id)?.map(|pos| Table { buf: self.buf, pos }))
+    }
+
+    /// The raw contents of a vector of scalars, or a string
+    fn vector(&self, id: usize, element_size: usize) -> Result<Option<&'a [u8]>> {
+        let Some(pos) = self.indirect(id)? else {
+            return Ok(None);
+        };
+        let length = read_u32(self.buf, pos)? as usize * element_size;
+        match self.buf.get(pos + 4..pos + 4 + length) {
+            Some(bytes) => Ok(Some(bytes)),
+            None => bail!("FlatGeobuf data is truncated"),
+        }
+    }
+
+    fn string(&self, id: usize) -> Result<Option<&'a str>> {
+        match self.vector(id, 1)? {
+            Some(bytes) => Ok(Some(std::str::from_utf8(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn tables(&self, id: usize) -> Result<Vec<Table<'a>>> {
+        let Some(pos) = self.indirect(id)? else {
+            return Ok(Vec::new());
+        };
+        let length = read_u32(self.buf, pos)? as usize;
+        if length > self.buf.len() / 4 {
+            bail!("FlatGeobuf data is truncated");
+        }
+        let mut tables = Vec::with_capacity(length);
+        for idx in 0..length {
+            let element = pos + 4 + 4 * idx;
+            tables.push(Table {
+                buf: self.buf,
+                pos: element + read_u32(self.buf, element)? as usize,
+            });
+        }
+        Ok(tables)
+    }
+}
+
+fn read<const N: usize>(buf: &[u8], pos: usize) -> Result<[u8; N]> {
+    match buf.get(pos..pos + N) {
+        Some(bytes) => Ok(bytes.try_into().unwrap()),
+        None => bail!("FlatGeobuf data is truncated"),
+    }
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(read(buf, pos)?))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(read(buf, pos)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A flatbuffers table to encode, with fields by ID
+    #[derive(Default)]
+    struct Obj(Vec<Field>);
+
+    enum Field {
+        Missing,
+        Scalar(Vec<u8>),
+        /// A vector of scalars or a string, with the number of elements
+        Vector(usize, Vec<u8>),
+        Table(Obj),
+        Tables(Vec<Obj>),
+    }
+
+    impl Obj {
+        fn set(mut self, id: usize, field: Field) -> Self {
+            while self.0.len() <= id {
+                self.0.push(Field::Missing);
+            }
+            self.0[id] = field;
+            self
+        }
+
+        /// Writes the vtable, then the table, then everything it refers to. Returns the position
+        /// of the table.
+        fn write(&self, buf: &mut Vec<u8>) -> usize {
+            let size = |field: &Field| match field {
+                Field::Missing => 0,
+                Field::Scalar(bytes) => bytes.len(),
+                _ => 4,
+            };
+            let vtable = buf.len();
+            buf.extend((4 + 2 * self.0.len() as u16).to_le_bytes());
+            buf.extend((4 + self.0.iter().map(size).sum::<usize>() as u16).to_le_bytes());
+            let mut offset = 4;
+            for field in &self.0 {
+                let field_offset = if size(field) == 0 { 0 } else { offset };
+                buf.extend((field_offset as u16).to_le_bytes());
+                offset += size(field);
+            }
+
+            let table = buf.len();
+            buf.extend(((table - vtable) as i32).to_le_bytes());
+            let mut pointers = Vec::new();
+            for field in &self.0 {
+                match field {
+                    Field::Missing => {}
+                    Field::Scalar(bytes) => buf.extend(bytes),
+                    _ => {
+                        pointers.push((buf.len(), field));
+                        buf.extend([0; 4]);
+                    }
+                }
+            }
+            for (pointer, field) in pointers {
+                let target = match field {
+                    Field::Vector(count, bytes) => {
+                        let target = buf.len();
+                        buf.extend((*count as u32).to_le_bytes());
+                        buf.extend(bytes);
+                        target
+                    }
+                    Field::Table(obj) => obj.write(buf),
+                    Field::Tables(objs) => {
+                        let target = buf.len();
+                        buf.extend((objs.len() as u32).to_le_bytes());
+                        let elements = buf.len();
+                        buf.extend(vec![0; 4 * objs.len()]);
+                        for (idx, obj) in objs.iter().enumerate() {
+                            let element = elements + 4 * idx;
+                            let pos = obj.write(buf);
+                            patch(buf, element, pos);
+                        }
+                        target
+                    }
+                    _ => unreachable!(),
+                };
+                patch(buf, pointer, target);
+            }
+            table
+        }
+
+        /// A buffer with this as the root table
+        fn finish(&self) -> Vec<u8> {
+            let mut buf = vec![0; 4];
+            let pos = self.write(&mut buf);
+            patch(&mut buf, 0, pos);
+            buf
+        }
+    }
+
+    fn patch(buf: &mut [u8], pointer: usize, target: usize) {
+        buf[pointer..pointer + 4].copy_from_slice(&((target - pointer) as u32).to_le_bytes());
+    }
+
+    fn string(x: &str) -> Field {
+        Field::Vector(x.len(), x.as_bytes().to_vec())
+    }
+
+    fn xy(pts: &[(f64, f64)]) -> Field {
+        Field::Vector(
+            pts.len() * 2,
+            pts.iter()
+                .flat_map(|(x, y)| [x.to_le_bytes(), y.to_le_bytes()])
+                .flatten()
+                .collect(),
+        )
+    }
+
+    fn feature(geometry: Obj, properties: Vec<u8>) -> Obj {
+        Obj::default()
+            .set(0, Field::Table(geometry))
+            .set(1, Field::Vector(properties.len(), properties))
+    }
+
+    /// Writes a file with this header and features, without a spatial index
+    fn write_file(name: &str, header: Obj, features: &[Obj]) -> String {
+        let header = header.set(9, Field::Scalar(0_u16.to_le_bytes().to_vec()));
+        let mut bytes = b"fgb\x03fgb\x00".to_vec();
+        for table in std::iter::once(&header).chain(features) {
+            let buf = table.finish();
+            bytes.extend((buf.len() as u32).to_le_bytes());
+            bytes.extend(buf);
+        }
+        let path = std::env::temp_dir().join(format!("{name}_{}.fgb", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn read_all(path: &str) -> Result<Vec<RawFeature>> {
+        let mut source = FlatGeobufSource::open(path)?;
+        let mut features = Vec::new();
+        source.for_each_feature(&mut |feature| {
+            features.push(feature?);
+            Ok(())
+        })?;
+        Ok(features)
+    }
+
+    #[test]
+    fn test_geometries() {
+        let line = [(0.0, 0.0), (1.0, 1.0)];
+        let other = [(2.0, 2.0), (3.0, 3.0)];
+        let both = [line, other].concat();
+        let header = Obj::default().set(2, Field::Scalar(vec![0])).set(
+            10,
+            Field::Table(Obj::default().set(1, Field::Scalar(27700_i32.to_le_bytes().to_vec()))),
+        );
+        let geometries = [
+            Obj::default()
+                .set(1, xy(&line))
+                .set(6, Field::Scalar(vec![LINE_STRING])),
+            // Ends split the coordinates into lines
+            Obj::default()
+                .set(
+                    0,
+                    Field::Vector(2, [2_u32, 4].iter().flat_map(|x| x.to_le_bytes()).collect()),
+                )
+                .set(1, xy(&both))
+                .set(6, Field::Scalar(vec![MULTI_LINE_STRING])),
+            // Or without ends, it's one line
+            Obj::default()
+                .set(1, xy(&line))
+                .set(6, Field::Scalar(vec![MULTI_LINE_STRING])),
+            // Or each line is a part
+            Obj::default()
+                .set(6, Field::Scalar(vec![MULTI_LINE_STRING]))
+                .set(
+                    7,
+                    Field::Tables(vec![
+                        Obj::default().set(1, xy(&line)),
+                        Obj::default().set(1, xy(&other)),
+                    ]),
+                ),
+            // Empty
+            Obj::default().set(6, Field::Scalar(vec![LINE_STRING])),
+        ];
+        let path = write_file(
+            "test_geometries",
+            header,
+            &geometries
+                .into_iter()
+                .map(|geometry| feature(geometry, Vec::new()))
+                .collect::<Vec<_>>(),
+        );
+
+        let source = FlatGeobufSource::open(&path).unwrap();
+        assert_eq!(source.crs(), Some("EPSG:27700".to_string()));
+        let features = read_all(&path).unwrap();
+        let line = LineString::from(line.to_vec());
+        let other = LineString::from(other.to_vec());
+        let geometries: Vec<_> = features.into_iter().map(|f| f.geometry).collect();
+        assert_eq!(
+            geometries,
+            vec![
+                Some(line.clone().into()),
+                Some(MultiLineString::new(vec![line.clone(), other.clone()]).into()),
+                Some(MultiLineString::new(vec![line.clone()]).into()),
+                Some(MultiLineString::new(vec![line, other]).into()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_property_types() {
+        let columns: Vec<(&str, u8, Vec<u8>)> = vec![
+            ("byte", 0, (-1_i8).to_le_bytes().to_vec()),
+            ("ubyte", 1, 200_u8.to_le_bytes().to_vec()),
+            ("bool", 2, vec![1]),
+            ("short", 3, (-300_i16).to_le_bytes().to_vec()),
+            ("ushort", 4, 60000_u16.to_le_bytes().to_vec()),
+            ("int", 5, (-70000_i32).to_le_bytes().to_vec()),
+            ("uint", 6, 3_000_000_000_u32.to_le_bytes().to_vec()),
+            ("long", 7, (-5_000_000_000_i64).to_le_bytes().to_vec()),
+            ("ulong", 8, 10_000_000_000_u64.to_le_bytes().to_vec()),
+            ("float", 9, 1.5_f32.to_le_bytes().to_vec()),
+            ("double", 10, 2.25_f64.to_le_bytes().to_vec()),
+            ("string", 11, [&5_u32.to_le_bytes()[..], b"hello"].concat()),
+            (
+                "json",
+                12,
+                [&7_u32.to_le_bytes()[..], b"{\"a\":1}"].concat(),
+            ),
+            (
+                "datetime",
+                13,
+                [&10_u32.to_le_bytes()[..], b"2024-01-01"].concat(),
+            ),
+            (
+                "binary",
+                14,
+                [&2_u32.to_le_bytes()[..], &[0xFF, 0xFE]].concat(),
+            ),
+        ];
+        let header = Obj::default().set(2, Field::Scalar(vec![LINE_STRING])).set(
+            7,
+            Field::Tables(
+                columns
+                    .iter()
+                    .map(|(name, column_type, _)| {
+                        Obj::default()
+                            .set(0, string(name))
+                            .set(1, Field::Scalar(vec![*column_type]))
+                    })
+                    .collect(),
+            ),
+        );
+        let mut properties = Vec::new();
+        for (idx, (_, _, value)) in columns.iter().enumerate() {
+            properties.extend((idx as u16).to_le_bytes());
+            properties.extend(value);
+        }
+        let geometry = Obj::default().set(1, xy(&[(0.0, 0.0), (1.0, 1.0)]));
+        let path = write_file(
+            "test_property_types",
+            header,
+            &[feature(geometry, properties)],
+        );
+
+        let features = read_all(&path).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(
+            Value::Object(features[0].properties.clone()),
+            json!({
+                "byte": -1,
+                "ubyte": 200,
+                "bool": true,
+                "short": -300,
+                "ushort": 60000,
+                "int": -70000,
+                "uint": 3_000_000_000_u32,
+                "long": -5_000_000_000_i64,
+                "ulong": 10_000_000_000_u64,
+                "float": 1.5,
+                "double": 2.25,
+                "string": "hello",
+                "json": {"a": 1},
+                "datetime": "2024-01-01",
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let header = Obj::default().set(2, Field::Scalar(vec![LINE_STRING]));
+        let geometry = || Obj::default().set(1, xy(&[(0.0, 0.0), (1.0, 1.0)]));
+        let path = write_file(
+            "test_invalid",
+            header,
+            &[
+                feature(geometry(), Vec::new()),
+                feature(geometry(), Vec::new()),
+            ],
+        );
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(read_all(&path).unwrap().len(), 2);
+
+        // Cutting off the last feature, or giving it a huge size, is an error
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read_all(&path).is_err());
+        let last = bytes.len() - 4 - feature(geometry(), Vec::new()).finish().len();
+        let mut huge = bytes.clone();
+        huge[last..last + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, huge).unwrap();
+        assert!(read_all(&path).is_err());
+
+        // A property for a column that doesn't exist
+        let path = write_file(
+            "test_invalid_column",
+            Obj::default(),
+            &[feature(geometry(), 3_u16.to_le_bytes().to_vec())],
+        );
+        let mut source = FlatGeobufSource::open(&path).unwrap();
+        let mut results = Vec::new();
+        source
+            .for_each_feature(&mut |feature| {
+                results.push(feature.is_ok());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(results, vec![false]);
+
+        assert!(index_size(u64::MAX, 2) > 0);
+    }
+}
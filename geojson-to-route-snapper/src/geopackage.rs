@@ -0,0 +1,251 @@
+use anyhow::{bail, Result};
+use log::info;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde_json::{Map, Value};
+
+use crate::source::{FeatureSource, RawFeature};
+
+/// Reads one table of lines from a GeoPackage, one row at a time.
+pub struct GeoPackageSource {
+    conn: Connection,
+    table: String,
+    geometry_column: String,
+    crs: Option<String>,
+}
+
+impl GeoPackageSource {
+    /// If `layer` isn't specified, the GeoPackage must have exactly one table of lines.
+    pub fn open(path: &str, layer: Option<&str>) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        // (table, geometry column, geometry type, SRS ID)
+        let mut layers: Vec<(String, String, String, i64)> = Vec::new();
+        {
+            let mut stmt = conn.prepare(
+                "SELECT table_name, column_name, geometry_type_name, srs_id FROM gpkg_geometry_columns",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                layers.push((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?));
+            }
+        }
+        let names = || {
+            layers
+                .iter()
+                .map(|(table, _, _, _)| table.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let chosen = if let Some(layer) = layer {
+            let Some(chosen) = layers.iter().find(|(table, _, _, _)| table == layer) else {
+                bail!("{path} has no layer {layer}. Layers are: {}", names());
+            };
+            chosen
+        } else {
+            let lines: Vec<_> = layers
+                .iter()
+                .filter(|(_, _, geometry_type, _)| {
+                    matches!(
+                        geometry_type.to_uppercase().as_str(),
+                        "LINESTRING" | "MULTILINESTRING" | "GEOMETRY"
+                    )
+                })
+                .collect();
+            match lines.len() {
+                0 => bail!("{path} has no layers with lines. Layers are: {}", names()),
+                1 => lines[0],
+                _ => bail!(
+                    "{path} has more than one layer with lines; choose one of: {}",
+                    names()
+                ),
+            }
+        };
+        let (table, geometry_column, _, srs_id) = chosen.clone();
+
+        let crs = conn
+            .query_row(
+                "SELECT organization, organization_coordsys_id FROM gpkg_spatial_ref_sys WHERE srs_id = ?1",
+                [srs_id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?
+            .and_then(|(organization, code)| {
+                // -1 and 0 mean undefined Cartesian and geographic systems
+                if organization.eq_ignore_ascii_case("EPSG") && code > 0 {
+                    Some(format!("EPSG:{code}"))
+                } else {
+                    None
+                }
+            });
+        info!("Reading layer {table} from {path}");
+
+        Ok(Self {
+            conn,
+            table,
+            geometry_column,
+            crs,
+        })
+    }
+}
+
+impl FeatureSource for GeoPackageSource {
+    fn crs(&self) -> Option<String> {
+        self.crs.clone()
+    }
+
+    fn for_each_feature(
+        &mut self,
+        f: &mut dyn FnMut(Result<RawFeature>) -> Result<()>,
+    ) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT * FROM {}", quote(&self.table)))?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let mut geometry = Ok(None);
+            let mut properties = Map::new();
+            for (idx, column) in columns.iter().enumerate() {
+                let value = row.get_ref(idx)?;
+                if *column == self.geometry_column {
+                    geometry = match value {
+                        ValueRef::Null => Ok(None),
+                        ValueRef::Blob(bytes) => parse_geometry(bytes),
+                        _ => Err(anyhow::anyhow!("geometry isn't a blob")),
+                    };
+                    continue;
+                }
+                let value = match value {
+                    ValueRef::Null => Value::Null,
+                    ValueRef::Integer(x) => x.into(),
+                    ValueRef::Real(x) => x.into(),
+                    ValueRef::Text(x) => String::from_utf8_lossy(x).into(),
+                    ValueRef::Blob(_) => continue,
+                };
+                properties.insert(column.clone(), value);
+            }
+            f(geometry.map(|geometry| RawFeature {
+                geometry,
+                properties,
+            }))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the GeoPackage header, then the WKB geometry after it.
+fn parse_geometry(bytes: &[u8]) -> Result<Option<geo::Geometry>> {
+    if bytes.len() < 8 || &bytes[0..2] != b"GP" {
+        bail!("geometry isn't a GeoPackage blob");
+    }
+    let flags = bytes[3];
+    if flags & 0b1_0000 != 0 {
+        return Ok(None);
+    }
+    let envelope_bytes = match (flags >> 1) & 0b111 {
+        0 => 0,
+        1 => 32,
+        2 | 3 => 48,
+        4 => 64,
+        x => bail!("invalid envelope type {x}"),
+    };
+    let start = 8 + envelope_bytes;
+    if bytes.len() < start {
+        bail!("geometry is truncated");
+    }
+    crate::wkb::parse(&bytes[start..]).map(Some)
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::LineString;
+    use serde_json::json;
+
+    use super::*;
+
+    /// A GeoPackage blob for a 2D line, with an envelope of this type
+    fn blob(envelope_type: u8) -> Vec<u8> {
+        let mut bytes = b"GP\0".to_vec();
+        bytes.push(1 | (envelope_type << 1));
+        bytes.extend(27700_i32.to_le_bytes());
+        let envelope_values = [0, 4, 6, 6, 8][envelope_type as usize];
+        bytes.extend(vec![0; 8 * envelope_values]);
+        bytes.push(1);
+        bytes.extend(2_u32.to_le_bytes());
+        bytes.extend(2_u32.to_le_bytes());
+        for value in [0.0_f64, 0.0, 1.0, 1.0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_read() {
+        let path = std::env::temp_dir().join(format!("test_read_{}.gpkg", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE gpkg_spatial_ref_sys (srs_id INTEGER, organization TEXT, organization_coordsys_id INTEGER);
+             INSERT INTO gpkg_spatial_ref_sys VALUES (1, 'EPSG', 27700);
+             CREATE TABLE gpkg_geometry_columns (table_name TEXT, column_name TEXT, geometry_type_name TEXT, srs_id INTEGER);
+             INSERT INTO gpkg_geometry_columns VALUES ('roads', 'geom', 'LINESTRING', 1);
+             INSERT INTO gpkg_geometry_columns VALUES ('points', 'geom', 'POINT', 1);
+             CREATE TABLE roads (fid INTEGER, geom BLOB, name TEXT, width REAL, extra BLOB);",
+        )
+        .unwrap();
+        for envelope_type in 0..5 {
+            conn.execute(
+                "INSERT INTO roads VALUES (?1, ?2, 'Main St', 3.5, x'00')",
+                rusqlite::params![envelope_type, blob(envelope_type as u8)],
+            )
+            .unwrap();
+        }
+        // An empty geometry, a missing one, and a bad one
+        let mut empty = blob(0);
+        empty[3] |= 0b1_0000;
+        conn.execute(
+            "INSERT INTO roads VALUES (5, ?1, NULL, NULL, NULL)",
+            [empty],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO roads VALUES (6, NULL, NULL, NULL, NULL)", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO roads VALUES (7, x'4750', NULL, NULL, NULL)",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut source = GeoPackageSource::open(path.to_str().unwrap(), None).unwrap();
+        assert_eq!(source.crs(), Some("EPSG:27700".to_string()));
+        let mut features = Vec::new();
+        source
+            .for_each_feature(&mut |feature| {
+                features.push(feature);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(features.len(), 8);
+        let line = LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]);
+        for feature in &features[0..5] {
+            let feature = feature.as_ref().unwrap();
+            assert_eq!(feature.geometry, Some(line.clone().into()));
+        }
+        assert_eq!(
+            Value::Object(features[0].as_ref().unwrap().properties.clone()),
+            json!({"fid": 0, "name": "Main St", "width": 3.5})
+        );
+        assert_eq!(features[5].as_ref().unwrap().geometry, None);
+        assert_eq!(features[6].as_ref().unwrap().geometry, None);
+        assert!(features[7].is_err());
+
+        assert!(GeoPackageSource::open(path.to_str().unwrap(), Some("points")).is_ok());
+        assert!(GeoPackageSource::open(path.to_str().unwrap(), Some("nope")).is_err());
+    }
+}
@@ -1,42 +1,276 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use anyhow::{bail, Result};
-use geo::{Coord, LineString};
-use geojson::de::deserialize_geometry;
-use serde::Deserialize;
+use geo::{Coord, HaversineLength, LineString};
+use log::{info, warn};
+use rstar::primitives::GeomWithData;
+use rstar::RTree;
 
-use route_snapper_graph::{Edge, NodeID, RouteSnapperMap};
+use route_snapper_graph::{
+    components, dem, AttributeTable, CostProfile, Edge, EdgeID, NodeID, RouteSnapperMap,
+};
+
+pub use cost::CostExpression;
+use exported::{ExportedEdge, ExportedNode};
+use source::{FeatureSource, GeoJsonSource, RawFeature};
+
+mod cost;
+mod exported;
+#[cfg(not(target_arch = "wasm32"))]
+mod flatgeobuf;
+#[cfg(not(target_arch = "wasm32"))]
+mod geopackage;
+mod noding;
+mod reproject;
+#[cfg(not(target_arch = "wasm32"))]
+mod shapefile;
+mod source;
+#[cfg(not(target_arch = "wasm32"))]
+mod wkb;
+
+/// Controls how GeoJSON is turned into a graph.
+pub struct Options {
+    /// The property with each edge's name
+    pub name_property: String,
+    /// How to calculate the cost of going along each LineString forwards and backwards. If the
+    /// result is missing, the edge isn't routable in that direction.
+    pub forward_cost: CostExpression,
+    pub backward_cost: CostExpression,
+    /// If a cost expression is missing for some edge, use its length instead
+    pub default_to_length: bool,
+    /// The values of these properties are kept as edge attributes
+    pub attribute_properties: Vec<String>,
+    /// For every name, the `forward_cost:name` and `backward_cost:name` properties become a named
+    /// cost profile
+    pub cost_profiles: Vec<String>,
+    /// The contents of a GeoTIFF or ESRI ASCII grid file, to sample node heights from
+    pub dem: Option<Vec<u8>>,
+    /// If set, uphill directions cost more. See `route_snapper_graph::dem::apply_grade_costs`.
+    pub grade_penalty: Option<f64>,
+    /// Split lines where they cross or touch other lines. Bridges and tunnels crossing other roads
+    /// will wrongly be connected.
+    pub noding: bool,
+    /// If set, line ends within this many meters of each other become one node. With `noding`,
+    /// ends this close to the middle of another line are also moved onto it.
+    pub snap_tolerance_meters: Option<f64>,
+    /// If set, parts of the graph with fewer edges than this that aren't connected to the largest
+    /// part are removed. Otherwise they're only reported.
+    pub min_component_edges: Option<usize>,
+    /// Fail on the first invalid feature, instead of skipping it with a warning
+    pub strict: bool,
+    /// The coordinate system of the input, like `EPSG:27700`. If unset, the coordinate system
+    /// declared by the file is used, or WGS84 if there isn't one. See
+    /// `reproject::Reprojection::new` for what's supported.
+    pub source_crs: Option<String>,
+    /// Which table to read from a GeoPackage. Only needed if it has more than one layer of lines.
+    pub layer: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            name_property: "name".to_string(),
+            forward_cost: CostExpression::Property("forward_cost".to_string()),
+            backward_cost: CostExpression::Property("backward_cost".to_string()),
+            default_to_length: false,
+            attribute_properties: Vec::new(),
+            cost_profiles: Vec::new(),
+            dem: None,
+            grade_penalty: None,
+            noding: false,
+            snap_tolerance_meters: None,
+            min_component_edges: None,
+            strict: false,
+            source_crs: None,
+            layer: None,
+        }
+    }
+}
 
 /// Converts GeoJSON into a graph for use with the route snapper. See the user guide for
 /// requirements about the GeoJSON file.
-pub fn convert_geojson(input_string: String) -> Result<RouteSnapperMap> {
-    let input: Vec<InputEdge> =
-        geojson::de::deserialize_feature_collection_str_to_vec(&input_string)?;
+pub fn convert_geojson(input_string: String, options: Options) -> Result<RouteSnapperMap> {
+    let mut source = GeoJsonSource::new(&input_string)?;
+    build_graph(&mut source, options)
+}
+
+/// Converts a GeoJSON, GeoPackage, FlatGeobuf, or Shapefile into a graph, picking the format from
+/// the file extension. Features are read one at a time, except for GeoJSON. Properties are
+/// handled the same way for every format.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn convert_file(path: &str, options: Options) -> Result<RouteSnapperMap> {
+    let mut source = source::open_file(path, options.layer.as_deref())?;
+    build_graph(source.as_mut(), options)
+}
+
+fn build_graph(source: &mut dyn FeatureSource, mut options: Options) -> Result<RouteSnapperMap> {
+    // If the input was exported from a graph, rebuild that graph as closely as possible
+    let metadata = source.exported_metadata()?;
+    let mut attributes: Vec<(String, String)> = options
+        .attribute_properties
+        .iter()
+        .map(|key| (key.clone(), key.clone()))
+        .collect();
+    if let Some(metadata) = &metadata {
+        if options.noding || options.snap_tolerance_meters.is_some() {
+            bail!("Noding and snapping can't be used on a graph exported to GeoJSON");
+        }
+        info!(
+            "Rebuilding a graph exported from format version {}",
+            metadata.format_version
+        );
+        let defaults = Options::default();
+        options.name_property = defaults.name_property;
+        options.forward_cost = defaults.forward_cost;
+        options.backward_cost = defaults.backward_cost;
+        options.default_to_length = false;
+        for name in &metadata.cost_profiles {
+            if !options.cost_profiles.contains(name) {
+                options.cost_profiles.push(name.clone());
+            }
+        }
+        for (key, property) in &metadata.attributes {
+            if !attributes.iter().any(|(k, _)| k == key) {
+                attributes.push((key.clone(), property.clone()));
+            }
+        }
+    }
+
+    let (mut input, mut exported_nodes) = read_features(source, &options, metadata.is_some())?;
+    if options.noding {
+        (input, _) = noding::node_lines(
+            input,
+            options.snap_tolerance_meters.unwrap_or(0.0),
+            &options.cost_profiles,
+        );
+    }
 
     let mut map = RouteSnapperMap {
         nodes: Vec::new(),
         edges: Vec::new(),
         override_forward_costs: Vec::new(),
         override_backward_costs: Vec::new(),
+        attributes: AttributeTable::default(),
+        cost_profiles: options
+            .cost_profiles
+            .iter()
+            .map(|name| CostProfile {
+                name: name.clone(),
+                forward_costs: Vec::new(),
+                backward_costs: Vec::new(),
+            })
+            .collect(),
+        turns: Vec::new(),
+        u_turn_cost: 0.0,
+        costs_in_seconds: false,
+        node_elevations: Vec::new(),
+        osm_way_ids: Vec::new(),
+        osm_node_ids: Vec::new(),
+        tile: None,
+        contraction_hierarchy: None,
     };
 
     let mut node_to_id: HashMap<(isize, isize), NodeID> = HashMap::new();
+    // Only used with a snap tolerance
+    let mut node_rtree: RTree<GeomWithData<[f64; 2], NodeID>> = RTree::new();
+    let mut merged_endpoints = 0;
+    let mut dropped_edges = 0;
 
-    for edge in input {
-        let first_pt = *edge.geometry.coords().next().unwrap();
-        let last_pt = *edge.geometry.coords().last().unwrap();
+    // Only used for exported graphs. Nodes keep their order, and the old IDs are mapped to new
+    // ones, in case some were deleted.
+    let mut exported_node_ids: HashMap<u32, NodeID> = HashMap::new();
+    let mut exported_edge_ids: HashMap<u32, EdgeID> = HashMap::new();
+    let mut elevations: Vec<Option<f32>> = Vec::new();
+    let mut osm_node_ids: Vec<Option<i64>> = Vec::new();
+    let mut osm_way_ids: Vec<Option<i64>> = Vec::new();
+    exported_nodes.sort_by_key(|node| node.id);
+    for node in exported_nodes {
+        if exported_node_ids.contains_key(&node.id) {
+            warn!("Ignoring a second Point with node_id {}", node.id);
+            continue;
+        }
+        let id = NodeID(map.nodes.len() as u32);
+        exported_node_ids.insert(node.id, id);
+        node_to_id.entry(hashify_point(node.pt)).or_insert(id);
+        map.nodes.push(node.pt);
+        elevations.push(node.elevation);
+        osm_node_ids.push(node.osm_node_id);
+    }
+
+    // Lines split while editing keep the same IDs on every piece, so their node IDs can't be
+    // trusted
+    let mut edge_id_counts: HashMap<u32, usize> = HashMap::new();
+    for edge in &input {
+        if let Some(id) = edge.exported.edge_id {
+            *edge_id_counts.entry(id).or_insert(0) += 1;
+        }
+    }
 
-        for pt in [first_pt, last_pt] {
+    for mut edge in input {
+        let unsplit = edge
+            .exported
+            .edge_id
+            .is_some_and(|id| edge_id_counts[&id] == 1);
+        let mut endpoints = Vec::new();
+        for (pt, exported_node) in [
+            (edge.geometry.0[0], edge.exported.node1),
+            (*edge.geometry.0.last().unwrap(), edge.exported.node2),
+        ] {
             let key = hashify_point(pt);
-            if !node_to_id.contains_key(&key) {
-                node_to_id.insert(key, NodeID(node_to_id.len() as u32));
+            let exported_node =
+                exported_node.and_then(|node| exported_node_ids.get(&node).copied());
+            // Some graphs have lines that don't end exactly at their nodes, so trust the exported
+            // node even if it's elsewhere, unless the line was split
+            let id = if let Some(id) =
+                exported_node.filter(|id| unsplit || hashify_point(map.nodes[id.0 as usize]) == key)
+            {
+                id
+            } else if let Some(id) = node_to_id.get(&key) {
+                *id
+            } else if let Some(id) = options
+                .snap_tolerance_meters
+                .and_then(|tolerance| find_nearby_node(&node_rtree, &map.nodes, pt, tolerance))
+            {
+                merged_endpoints += 1;
+                node_to_id.insert(key, id);
+                id
+            } else {
+                let id = NodeID(map.nodes.len() as u32);
+                node_to_id.insert(key, id);
+                node_rtree.insert(GeomWithData::new([pt.x, pt.y], id));
                 map.nodes.push(pt);
+                id
+            };
+            endpoints.push(id);
+        }
+        let (node1, node2) = (endpoints[0], endpoints[1]);
+        if options.snap_tolerance_meters.is_some() {
+            // Make the geometry line up with merged nodes
+            edge.geometry.0[0] = map.nodes[node1.0 as usize];
+            *edge.geometry.0.last_mut().unwrap() = map.nodes[node2.0 as usize];
+            // Tiny lines shorter than the tolerance collapse to nothing
+            if node1 == node2 && edge.geometry.0.iter().all(|pt| *pt == edge.geometry.0[0]) {
+                dropped_edges += 1;
+                continue;
+            }
+        }
+
+        let edge_id = EdgeID(map.edges.len() as u32);
+        if let Some(old_id) = edge.exported.edge_id {
+            exported_edge_ids.entry(old_id).or_insert(edge_id);
+        }
+        osm_way_ids.push(edge.exported.osm_way_id);
+        for (key, property) in &attributes {
+            match edge.other_properties.remove(property) {
+                None | Some(serde_json::Value::Null) => {}
+                Some(serde_json::Value::String(value)) => map.attributes.set(edge_id, key, &value),
+                Some(value) => map.attributes.set(edge_id, key, &value.to_string()),
             }
         }
 
         map.edges.push(Edge {
-            node1: node_to_id[&hashify_point(first_pt)],
-            node2: node_to_id[&hashify_point(last_pt)],
+            node1,
+            node2,
             geometry: edge.geometry,
             name: edge.name,
 
@@ -46,31 +280,369 @@ pub fn convert_geojson(input_string: String) -> Result<RouteSnapperMap> {
         });
         map.override_forward_costs.push(edge.forward_cost);
         map.override_backward_costs.push(edge.backward_cost);
+        for profile in &mut map.cost_profiles {
+            let forward_key = format!("forward_cost:{}", profile.name);
+            let backward_key = format!("backward_cost:{}", profile.name);
+            profile.forward_costs.push(
+                edge.other_properties
+                    .get(&forward_key)
+                    .and_then(|x| x.as_f64()),
+            );
+            profile.backward_costs.push(
+                edge.other_properties
+                    .get(&backward_key)
+                    .and_then(|x| x.as_f64()),
+            );
+        }
+    }
+
+    if let Some(tolerance) = options.snap_tolerance_meters {
+        info!(
+            "Merged {merged_endpoints} line ends into another node within {tolerance}m, dropping {dropped_edges} lines that collapsed to a point"
+        );
+    }
+
+    if let Some(metadata) = metadata {
+        finish_exported_graph(
+            &mut map,
+            &metadata,
+            &exported_edge_ids,
+            &exported_node_ids,
+            elevations,
+            osm_node_ids,
+            osm_way_ids,
+        );
+    } else {
+        check_costs(&map, &options)?;
+    }
+
+    prune_components(&mut map, options.min_component_edges);
+    if let Some(dem) = options.dem {
+        let missing = dem::add_elevation(&mut map, &dem, options.grade_penalty)?;
+        if missing > 0 {
+            warn!("{missing} nodes have no elevation data in the DEM");
+        }
+    }
+
+    Ok(map)
+}
+
+/// Restores everything about an exported graph not set from the features.
+fn finish_exported_graph(
+    map: &mut RouteSnapperMap,
+    metadata: &exported::ExportMetadata,
+    edge_ids: &HashMap<u32, EdgeID>,
+    node_ids: &HashMap<u32, NodeID>,
+    mut elevations: Vec<Option<f32>>,
+    mut osm_node_ids: Vec<Option<i64>>,
+    osm_way_ids: Vec<Option<i64>>,
+) {
+    if !metadata.override_costs {
+        map.override_forward_costs.clear();
+        map.override_backward_costs.clear();
+    }
+    map.u_turn_cost = metadata.u_turn_cost;
+    map.costs_in_seconds = metadata.costs_in_seconds;
+
+    let dropped = exported::add_turns(map, metadata, edge_ids, node_ids);
+    if dropped > 0 {
+        warn!("Dropped {dropped} turns involving edited or deleted edges");
+    }
+
+    // Nodes created for new edges have no elevation or OSM ID
+    if elevations.iter().any(|x| x.is_some()) {
+        elevations.resize(map.nodes.len(), None);
+        map.node_elevations = elevations
+            .into_iter()
+            .map(|x| x.unwrap_or(f32::NAN))
+            .collect();
+    }
+    if metadata.osm_ids {
+        if let Some(osm_way_ids) = osm_way_ids.into_iter().collect::<Option<Vec<_>>>() {
+            osm_node_ids.resize(map.nodes.len(), None);
+            map.osm_way_ids = osm_way_ids;
+            map.osm_node_ids = osm_node_ids;
+        } else {
+            warn!("Some edges have no osm_way_id, so OSM IDs are dropped from the graph");
+        }
+    }
+    if let Some(tile) = &metadata.tile {
+        map.tile = exported::restore_tile(map, tile, edge_ids, node_ids);
+        if map.tile.is_none() {
+            warn!("Edges were split or added, so the graph is no longer a tile");
+        }
     }
 
+    // Remove nodes no longer used by any edge
+    map.remove_edges(&HashSet::new());
+}
+
+fn check_costs(map: &RouteSnapperMap, options: &Options) -> Result<()> {
     if map.override_forward_costs.iter().all(|x| x.is_none()) {
-        bail!("No edges set forward_cost. The input is probably incorrect.");
+        bail!(
+            "No edges have a forward cost from {}. The input is probably incorrect.",
+            options.forward_cost
+        );
     }
     if map.override_backward_costs.iter().all(|x| x.is_none()) {
-        bail!("No edges set backward_cost. The input is probably incorrect.");
+        bail!(
+            "No edges have a backward cost from {}. The input is probably incorrect.",
+            options.backward_cost
+        );
+    }
+    for profile in &map.cost_profiles {
+        if profile.forward_costs.iter().all(|x| x.is_none()) {
+            bail!(
+                "No edges set forward_cost:{}. The input is probably incorrect.",
+                profile.name
+            );
+        }
+        if profile.backward_costs.iter().all(|x| x.is_none()) {
+            bail!(
+                "No edges set backward_cost:{}. The input is probably incorrect.",
+                profile.name
+            );
+        }
     }
+    Ok(())
+}
 
-    Ok(map)
+fn prune_components(map: &mut RouteSnapperMap, min_edges: Option<usize>) {
+    if let Some(min_edges) = min_edges {
+        let removed = components::prune_components(map, min_edges);
+        if removed.is_empty() {
+            return;
+        }
+        info!(
+            "Removed {} edges in {} disconnected parts with fewer than {min_edges} edges",
+            removed.iter().map(|c| c.edges.len()).sum::<usize>(),
+            removed.len()
+        );
+        for component in removed.iter().take(20) {
+            info!("  {component}");
+        }
+        if removed.len() > 20 {
+            info!("  ... and {} more", removed.len() - 20);
+        }
+    } else {
+        let disconnected = components::find_components(map).len().saturating_sub(1);
+        if disconnected > 0 {
+            warn!("{disconnected} parts of the graph aren't connected to the largest part");
+        }
+    }
+}
+
+/// Reads every feature, splitting MultiLineStrings into separate edges and calculating names and
+/// costs. Invalid features are skipped with a warning, or fail everything if `options.strict`. For
+/// an exported graph, the IDs on each edge are read, and Points are returned as nodes.
+fn read_features(
+    source: &mut dyn FeatureSource,
+    options: &Options,
+    exported_graph: bool,
+) -> Result<(Vec<InputEdge>, Vec<ExportedNode>)> {
+    let declared_crs = source.crs();
+    let reprojection = match options.source_crs.as_deref().or(declared_crs.as_deref()) {
+        Some(crs) => {
+            let reprojection = reproject::Reprojection::new(crs)?;
+            if reprojection.is_some() {
+                info!("Reprojecting from {crs} to WGS84");
+            }
+            reprojection
+        }
+        None => None,
+    };
+
+    let mut input = Vec::new();
+    let mut nodes = Vec::new();
+    let mut num_features = 0;
+    let mut skipped = 0;
+    source.for_each_feature(&mut |feature| {
+        let idx = num_features;
+        num_features += 1;
+        let result = feature.and_then(|feature| match feature.geometry {
+            Some(geo::Geometry::Point(pt)) if exported_graph => {
+                nodes.push(exported::read_node(
+                    pt.0,
+                    &feature.properties,
+                    reprojection.as_ref(),
+                )?);
+                Ok(Vec::new())
+            }
+            _ => read_feature(feature, reprojection.as_ref(), options),
+        });
+        match result {
+            Ok(mut edges) => {
+                // Don't warn about every feature; the whole file is probably wrong
+                if reprojection.is_none()
+                    && edges.iter().flat_map(|e| e.geometry.coords()).any(|pt| {
+                        !(-180.0..=180.0).contains(&pt.x) || !(-90.0..=90.0).contains(&pt.y)
+                    })
+                {
+                    bail!(
+                        "Feature {idx} doesn't use longitude and latitude. Set the source coordinate system, like EPSG:27700."
+                    );
+                }
+                if exported_graph {
+                    for edge in &mut edges {
+                        edge.exported = exported::read_edge(&edge.other_properties);
+                    }
+                }
+                input.extend(edges);
+            }
+            Err(err) => {
+                if options.strict {
+                    bail!("Feature {idx} is invalid: {err}");
+                }
+                warn!("Skipping feature {idx}: {err}");
+                skipped += 1;
+            }
+        }
+        Ok(())
+    })?;
+    if skipped > 0 {
+        warn!("Skipped {skipped} invalid features out of {num_features}");
+    }
+    Ok((input, nodes))
+}
+
+fn read_feature(
+    feature: RawFeature,
+    reprojection: Option<&reproject::Reprojection>,
+    options: &Options,
+) -> Result<Vec<InputEdge>> {
+    let Some(geometry) = feature.geometry else {
+        bail!("it has no geometry");
+    };
+    let mut lines = match geometry {
+        geo::Geometry::LineString(line) => vec![line],
+        geo::Geometry::MultiLineString(lines) => lines.0,
+        _ => bail!("it isn't a LineString or MultiLineString"),
+    };
+    if lines.is_empty() {
+        bail!("it has empty geometry");
+    }
+    if lines.iter().any(|line| line.0.len() < 2) {
+        bail!("a LineString has fewer than two points");
+    }
+    if let Some(reprojection) = reprojection {
+        for line in &mut lines {
+            reprojection.transform(line)?;
+        }
+    }
+    let other_properties = feature.properties;
+
+    let name = match other_properties.get(&options.name_property) {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(x)) => Some(x.clone()),
+        Some(x) => Some(x.to_string()),
+    };
+    // Costs are for the whole feature, then split between the parts by length
+    let total_length: f64 = lines.iter().map(|line| line.haversine_length()).sum();
+    let mut costs = [None, None];
+    for (expression, cost) in [&options.forward_cost, &options.backward_cost]
+        .into_iter()
+        .zip(&mut costs)
+    {
+        *cost = expression.evaluate(total_length, &other_properties)?;
+        if cost.is_none() && options.default_to_length {
+            *cost = Some(total_length);
+        }
+    }
+    for profile in &options.cost_profiles {
+        for key in [
+            format!("forward_cost:{profile}"),
+            format!("backward_cost:{profile}"),
+        ] {
+            CostExpression::Property(key).evaluate(total_length, &other_properties)?;
+        }
+    }
+
+    let edge = InputEdge {
+        geometry: LineString::new(Vec::new()),
+        name,
+        forward_cost: costs[0],
+        backward_cost: costs[1],
+        other_properties,
+        exported: ExportedEdge::default(),
+    };
+    if lines.len() == 1 {
+        return Ok(vec![InputEdge {
+            geometry: lines.into_iter().next().unwrap(),
+            ..edge
+        }]);
+    }
+    let num_lines = lines.len();
+    Ok(lines
+        .into_iter()
+        .map(|line| {
+            let ratio = if total_length == 0.0 {
+                1.0 / num_lines as f64
+            } else {
+                line.haversine_length() / total_length
+            };
+            edge.piece(line, ratio, &options.cost_profiles)
+        })
+        .collect())
 }
 
-#[derive(Deserialize)]
 pub struct InputEdge {
-    #[serde(deserialize_with = "deserialize_geometry")]
     geometry: LineString,
+    // These are calculated from the properties, using `Options`
     name: Option<String>,
     forward_cost: Option<f64>,
     backward_cost: Option<f64>,
+    other_properties: serde_json::Map<String, serde_json::Value>,
+    // Only set for exported graphs
+    exported: ExportedEdge,
+}
+
+impl InputEdge {
+    /// Makes an edge for part of this one, with every cost multiplied by `ratio`.
+    fn piece(&self, geometry: LineString, ratio: f64, cost_profiles: &[String]) -> InputEdge {
+        let mut other_properties = self.other_properties.clone();
+        for name in cost_profiles {
+            for key in [
+                format!("forward_cost:{name}"),
+                format!("backward_cost:{name}"),
+            ] {
+                if let Some(cost) = other_properties.get(&key).and_then(|x| x.as_f64()) {
+                    other_properties.insert(key, (cost * ratio).into());
+                }
+            }
+        }
+        InputEdge {
+            geometry,
+            name: self.name.clone(),
+            forward_cost: self.forward_cost.map(|x| x * ratio),
+            backward_cost: self.backward_cost.map(|x| x * ratio),
+            other_properties,
+            exported: ExportedEdge {
+                osm_way_id: self.exported.osm_way_id,
+                ..Default::default()
+            },
+        }
+    }
 }
 
 fn hashify_point(pt: Coord) -> (isize, isize) {
     ((pt.x * 1_000_000.0) as isize, (pt.y * 1_000_000.0) as isize)
 }
 
+fn find_nearby_node(
+    rtree: &RTree<GeomWithData<[f64; 2], NodeID>>,
+    nodes: &[Coord],
+    pt: Coord,
+    tolerance_meters: f64,
+) -> Option<NodeID> {
+    let radius = noding::degrees_for_meters(tolerance_meters, pt.y);
+    rtree
+        .locate_within_distance([pt.x, pt.y], radius * radius)
+        .map(|obj| (noding::distance(pt, nodes[obj.data.0 as usize]), obj.data))
+        .filter(|(dist, _)| *dist <= tolerance_meters)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, id)| id)
+}
+
 #[cfg(target_arch = "wasm32")]
 use std::sync::Once;
 #[cfg(target_arch = "wasm32")]
@@ -81,12 +653,282 @@ static START: Once = Once::new();
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen()]
-pub fn convert(input_string: String) -> Result<Vec<u8>, JsValue> {
+pub fn convert(
+    input_string: String,
+    dem: Option<Vec<u8>>,
+    grade_penalty: Option<f64>,
+    min_component_edges: Option<usize>,
+    noding: Option<bool>,
+    snap_tolerance_meters: Option<f64>,
+    forward_cost: Option<String>,
+    backward_cost: Option<String>,
+    default_to_length: Option<bool>,
+    strict: Option<bool>,
+    source_crs: Option<String>,
+    compress: Option<bool>,
+) -> Result<Vec<u8>, JsValue> {
     START.call_once(|| {
         console_error_panic_hook::set_once();
     });
 
-    let snapper =
-        convert_geojson(input_string).map_err(|err| JsValue::from_str(&err.to_string()))?;
-    Ok(bincode::serialize(&snapper).unwrap())
+    let mut options = Options {
+        dem,
+        grade_penalty,
+        min_component_edges,
+        noding: noding.unwrap_or(false),
+        snap_tolerance_meters,
+        default_to_length: default_to_length.unwrap_or(false),
+        strict: strict.unwrap_or(false),
+        source_crs,
+        ..Default::default()
+    };
+    if let Some(expression) = forward_cost {
+        options.forward_cost = expression
+            .parse()
+            .map_err(|err: anyhow::Error| JsValue::from_str(&err.to_string()))?;
+    }
+    if let Some(expression) = backward_cost {
+        options.backward_cost = expression
+            .parse()
+            .map_err(|err: anyhow::Error| JsValue::from_str(&err.to_string()))?;
+    }
+    let snapper = convert_geojson(input_string, options)
+        .map_err(|err| JsValue::from_str(&err.to_string()))?;
+    let compression = if compress.unwrap_or(false) {
+        route_snapper_graph::Compression::Deflate
+    } else {
+        route_snapper_graph::Compression::None
+    };
+    snapper
+        .to_bytes_with(compression)
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub fn feature_collection(features: &[&str]) -> String {
+        format!(
+            r#"{{"type": "FeatureCollection", "features": [{}]}}"#,
+            features.join(", ")
+        )
+    }
+
+    pub fn line(coordinates: &str, properties: &str) -> String {
+        format!(
+            r#"{{"type": "Feature", "geometry": {{"type": "LineString", "coordinates": {coordinates}}}, "properties": {properties}}}"#
+        )
+    }
+
+    #[test]
+    fn test_multilinestring() {
+        let input = feature_collection(&[
+            r#"{"type": "Feature", "geometry": {"type": "MultiLineString", "coordinates": [[[0.0, 0.0], [0.001, 0.0]], [[0.001, 0.0], [0.003, 0.0]]]}, "properties": {"forward_cost": 30, "backward_cost": 60}}"#,
+        ]);
+        let map = convert_geojson(input, Options::default()).unwrap();
+        assert_eq!(map.edges.len(), 2);
+        assert_eq!(map.nodes.len(), 3);
+        // Costs are split by length
+        let costs: Vec<(f64, f64)> = map
+            .override_forward_costs
+            .iter()
+            .zip(&map.override_backward_costs)
+            .map(|(f, b)| (f.unwrap(), b.unwrap()))
+            .collect();
+        assert!((costs[0].0 - 10.0).abs() < 0.01);
+        assert!((costs[0].1 - 20.0).abs() < 0.01);
+        assert!((costs[1].0 - 20.0).abs() < 0.01);
+        assert!((costs[1].1 - 40.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_invalid_features() {
+        let costs = r#"{"forward_cost": 1, "backward_cost": 1}"#;
+        let invalid = [
+            // Empty geometry
+            line("[]", costs),
+            r#"{"type": "Feature", "geometry": {"type": "MultiLineString", "coordinates": []}, "properties": {}}"#.to_string(),
+            // A single coordinate
+            line("[[0.0, 0.0]]", costs),
+            // A cost that isn't a number
+            line(
+                "[[0.0, 0.0], [0.001, 0.0]]",
+                r#"{"forward_cost": "fast", "backward_cost": 1}"#,
+            ),
+            // Coordinates that aren't numbers
+            line(r#"[["a", "b"], [0.001, 0.0]]"#, costs),
+            // Not a line
+            r#"{"type": "Feature", "geometry": {"type": "Point", "coordinates": [0.0, 0.0]}, "properties": {}}"#.to_string(),
+            r#"{"type": "Feature", "geometry": null, "properties": {}}"#.to_string(),
+        ];
+        let valid = line("[[0.0, 0.0], [0.001, 0.0]]", costs);
+
+        for bad in &invalid {
+            // Skipped otherwise
+            let input = feature_collection(&[&valid, bad, &valid]);
+            let map = convert_geojson(input.clone(), Options::default()).unwrap();
+            assert_eq!(map.edges.len(), 2, "for {bad}");
+
+            let err = convert_geojson(
+                input,
+                Options {
+                    strict: true,
+                    ..Default::default()
+                },
+            )
+            .err()
+            .unwrap();
+            assert!(
+                err.to_string().starts_with("Feature 1 is invalid"),
+                "for {bad}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_not_a_feature_collection() {
+        for input in [
+            "[]",
+            r#"{"type": "Feature", "geometry": null, "properties": {}}"#,
+            r#"{"type": "FeatureCollection"}"#,
+        ] {
+            assert!(convert_geojson(input.to_string(), Options::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        use route_snapper_graph::{TileInfo, Turn};
+
+        let nodes = vec![
+            Coord { x: 0.1, y: 51.5 },
+            Coord { x: 0.101, y: 51.5 },
+            Coord {
+                x: 0.101,
+                y: 51.501,
+            },
+        ];
+        let mut map = RouteSnapperMap {
+            edges: vec![
+                Edge {
+                    node1: NodeID(0),
+                    node2: NodeID(1),
+                    geometry: LineString::from(vec![
+                        nodes[0],
+                        Coord {
+                            x: 0.1005,
+                            y: 51.4999,
+                        },
+                        nodes[1],
+                    ]),
+                    name: Some("Main St".to_string()),
+                    length_meters: 0.0,
+                    forward_cost: None,
+                    backward_cost: None,
+                },
+                Edge {
+                    node1: NodeID(1),
+                    node2: NodeID(2),
+                    geometry: LineString::from(vec![nodes[1], nodes[2]]),
+                    name: None,
+                    length_meters: 0.0,
+                    forward_cost: None,
+                    backward_cost: None,
+                },
+            ],
+            nodes,
+            override_forward_costs: vec![Some(10.0), Some(20.5)],
+            override_backward_costs: vec![None, Some(30.0)],
+            attributes: AttributeTable::default(),
+            cost_profiles: vec![CostProfile {
+                name: "bike".to_string(),
+                forward_costs: vec![Some(1.0), None],
+                backward_costs: vec![Some(2.0), Some(3.0)],
+            }],
+            turns: vec![
+                Turn {
+                    from: EdgeID(0),
+                    via: NodeID(1),
+                    to: EdgeID(1),
+                    cost: Some(7.0),
+                },
+                Turn {
+                    from: EdgeID(1),
+                    via: NodeID(1),
+                    to: EdgeID(0),
+                    cost: None,
+                },
+            ],
+            u_turn_cost: 5.0,
+            costs_in_seconds: true,
+            node_elevations: vec![10.5, f32::NAN, 12.0],
+            osm_way_ids: vec![100, 101],
+            osm_node_ids: vec![Some(1), None, Some(3)],
+            tile: Some(TileInfo {
+                size_degrees: 0.05,
+                x: 2,
+                y: 1030,
+                node_ids: vec![40, 41, 42],
+                edge_ids: vec![50, 51],
+            }),
+            contraction_hierarchy: None,
+        };
+        map.attributes.set(EdgeID(0), "highway", "primary");
+        // Clashes with the name property
+        map.attributes.set(EdgeID(0), "name", "attribute name");
+        map.attributes.set(EdgeID(1), "highway", "residential");
+
+        let geojson = route_snapper_graph::export::to_geojson(&map).to_string();
+        let copy = convert_geojson(geojson, Options::default()).unwrap();
+
+        assert_eq!(copy.nodes, map.nodes);
+        for (edge, copy) in map.edges.iter().zip(&copy.edges) {
+            assert_eq!(copy.node1, edge.node1);
+            assert_eq!(copy.node2, edge.node2);
+            assert_eq!(copy.geometry, edge.geometry);
+            assert_eq!(copy.name, edge.name);
+        }
+        assert_eq!(copy.edges.len(), map.edges.len());
+        assert_eq!(copy.override_forward_costs, map.override_forward_costs);
+        assert_eq!(copy.override_backward_costs, map.override_backward_costs);
+        for idx in 0..map.edges.len() {
+            let attributes = |map: &RouteSnapperMap| {
+                map.attributes
+                    .get(EdgeID(idx as u32))
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(attributes(&copy), attributes(&map));
+        }
+        assert_eq!(copy.cost_profiles.len(), 1);
+        assert_eq!(copy.cost_profiles[0].name, "bike");
+        assert_eq!(
+            copy.cost_profiles[0].forward_costs,
+            map.cost_profiles[0].forward_costs
+        );
+        assert_eq!(
+            copy.cost_profiles[0].backward_costs,
+            map.cost_profiles[0].backward_costs
+        );
+        let turns = |map: &RouteSnapperMap| {
+            map.turns
+                .iter()
+                .map(|t| (t.from, t.via, t.to, t.cost))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(turns(&copy), turns(&map));
+        assert_eq!(copy.u_turn_cost, map.u_turn_cost);
+        assert_eq!(copy.costs_in_seconds, map.costs_in_seconds);
+        // Unknown heights stay unknown
+        assert_eq!(copy.node_elevations[0], 10.5);
+        assert!(copy.node_elevations[1].is_nan());
+        assert_eq!(copy.node_elevations[2], 12.0);
+        assert_eq!(copy.osm_way_ids, map.osm_way_ids);
+        assert_eq!(copy.osm_node_ids, map.osm_node_ids);
+        assert_eq!(copy.tile, map.tile);
+
+        // The whole file is the same too
+        assert_eq!(copy.to_bytes().unwrap(), map.to_bytes().unwrap());
+    }
 }
@@ -1,24 +1,147 @@
-use std::fs::File;
-use std::io::BufWriter;
-
 use clap::Parser;
-use geojson_to_route_snapper::convert_geojson;
+use geojson_to_route_snapper::{convert_file, CostExpression, Options};
+use route_snapper_graph::{ch, tiles, Compression};
 
 #[derive(Parser)]
 struct Args {
-    /// Path to a .geojson file to convert
+    /// Path to a .geojson, .gpkg (GeoPackage), .fgb (FlatGeobuf), or .shp (Shapefile) file to
+    /// convert
     #[arg(long)]
     input: String,
 
-    /// Output file to write
+    /// Which layer to read from a GeoPackage with more than one table of lines
+    #[clap(long)]
+    layer: Option<String>,
+
+    /// Output file to write, or a directory with `--tile-size`
     #[arg(long, default_value = "snap.bin")]
     output: String,
+
+    /// Split the graph into square tiles this many degrees wide, writing each to `{x}_{y}.bin`
+    /// in the output directory along with an `index.json`. The route snapper can load tiles as
+    /// they come into view.
+    #[clap(long)]
+    tile_size: Option<f64>,
+
+    /// Precompute a contraction hierarchy, so the route snapper finds paths much faster on large
+    /// graphs. It makes the file about a third bigger and only helps with the default costs,
+    /// without cost profiles or turn restrictions.
+    #[clap(long, conflicts_with = "tile_size")]
+    contraction_hierarchy: bool,
+
+    /// Compress the output, usually to about half the size. The route snapper decompresses it
+    /// when loading.
+    #[clap(long)]
+    compress: bool,
+
+    /// The property with each edge's name
+    #[clap(long, default_value = "name")]
+    name_property: String,
+
+    /// How to calculate the cost of going forwards along each edge. This is terms multiplied
+    /// together. A term can be `length` in meters, a number, a numeric property, or a lookup table
+    /// on a property like `road_class[primary=1,residential=1.5,*=2]`, where `*` is the default.
+    #[clap(long, default_value = "forward_cost")]
+    forward_cost: CostExpression,
+
+    /// How to calculate the cost of going backwards along each edge, like `--forward-cost`
+    #[clap(long, default_value = "backward_cost")]
+    backward_cost: CostExpression,
+
+    /// If a cost is missing for some edge, use its length instead. Otherwise the edge isn't
+    /// routable in that direction.
+    #[clap(long)]
+    default_to_length: bool,
+
+    /// Fail on the first invalid feature, instead of skipping it with a warning
+    #[clap(long)]
+    strict: bool,
+
+    /// The coordinate system of the input, like `EPSG:27700` or a proj string. By default, the
+    /// coordinate system declared by the file is used, or WGS84 if there isn't one.
+    #[clap(long)]
+    source_crs: Option<String>,
+
+    /// A comma-separated list of properties to keep as edge attributes
+    #[clap(long, value_delimiter = ',')]
+    attributes: Vec<String>,
+
+    /// A comma-separated list of cost profile names. For each `name`, the `forward_cost:name` and
+    /// `backward_cost:name` properties are used.
+    #[clap(long, value_delimiter = ',')]
+    cost_profiles: Vec<String>,
+
+    /// Path to a GeoTIFF or ESRI ASCII grid (.asc) file with heights in meters, using WGS84
+    /// coordinates. Each node's elevation is stored in the graph.
+    #[clap(long)]
+    dem: Option<String>,
+
+    /// With `--dem`, multiply the cost of going uphill by `1 + penalty * grade`, where grade is
+    /// like 0.05 for a 5% slope
+    #[clap(long)]
+    grade_penalty: Option<f64>,
+
+    /// Remove parts of the graph with fewer than this many edges that aren't connected to the
+    /// largest part. Without this, they're only reported.
+    #[clap(long)]
+    min_component_edges: Option<usize>,
+
+    /// Split lines where they cross or touch other lines. Bridges and tunnels will wrongly be
+    /// connected to roads they cross.
+    #[clap(long)]
+    noding: bool,
+
+    /// Merge line ends within this many meters of each other. With `--noding`, ends this close to
+    /// the middle of another line are also connected to it.
+    #[clap(long)]
+    snap_tolerance: Option<f64>,
 }
 
 fn main() {
+    simple_logger::init_with_level(log::Level::Info).unwrap();
     let args = Args::parse();
-    let snapper = convert_geojson(std::fs::read_to_string(&args.input).unwrap()).unwrap();
+    let options = Options {
+        name_property: args.name_property,
+        forward_cost: args.forward_cost,
+        backward_cost: args.backward_cost,
+        default_to_length: args.default_to_length,
+        attribute_properties: args.attributes,
+        cost_profiles: args.cost_profiles,
+        dem: args.dem.map(|path| std::fs::read(path).unwrap()),
+        grade_penalty: args.grade_penalty,
+        min_component_edges: args.min_component_edges,
+        noding: args.noding,
+        snap_tolerance_meters: args.snap_tolerance,
+        strict: args.strict,
+        source_crs: args.source_crs,
+        layer: args.layer,
+    };
+    let mut snapper = convert_file(&args.input, options).unwrap();
+
+    if args.contraction_hierarchy {
+        let ch = ch::build(&snapper);
+        log::info!(
+            "Built a contraction hierarchy with {} shortcuts",
+            ch.num_shortcuts()
+        );
+        snapper.contraction_hierarchy = Some(ch);
+    }
 
-    let output = BufWriter::new(File::create(args.output).unwrap());
-    bincode::serialize_into(output, &snapper).unwrap();
+    let compression = if args.compress {
+        Compression::Deflate
+    } else {
+        Compression::None
+    };
+    if let Some(tile_size) = args.tile_size {
+        let tiles = tiles::write_tiles(
+            &snapper,
+            tile_size,
+            compression,
+            std::path::Path::new(&args.output),
+        )
+        .unwrap();
+        log::info!("Wrote {tiles} tiles to {}", args.output);
+    } else {
+        std::fs::write(args.output, snapper.to_bytes_with(compression).unwrap()).unwrap();
+    }
 }
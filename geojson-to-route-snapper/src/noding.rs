@@ -0,0 +1,386 @@
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geo::{
+    Closest, ClosestPoint, Coord, HaversineDistance, HaversineLength, Line, LineString, Point,
+};
+use log::info;
+use rstar::primitives::{GeomWithData, Line as RLine};
+use rstar::{RTree, AABB};
+
+use crate::{hashify_point, InputEdge};
+
+/// A segment of a line, with the line and segment index
+type Segment = GeomWithData<RLine<[f64; 2]>, (usize, usize)>;
+
+/// What `node_lines` changed
+#[derive(Debug, PartialEq)]
+pub struct NodingSummary {
+    pub lines_before: usize,
+    pub lines_after: usize,
+    /// Places where lines were split because they cross, touch, or overlap
+    pub crossings: usize,
+    /// Line ends moved onto the interior of another line
+    pub snapped_ends: usize,
+}
+
+/// Splits lines wherever they cross or touch another line, and at both ends of where they overlap.
+/// If `tolerance_meters` is positive, the end of a line that stops short of (or overshoots) another
+/// line's interior by at most this much is moved onto that line first. Costs are split in
+/// proportion to length.
+pub fn node_lines(
+    mut input: Vec<InputEdge>,
+    tolerance_meters: f64,
+    cost_profiles: &[String],
+) -> (Vec<InputEdge>, NodingSummary) {
+    let mut segments = Vec::new();
+    for (line_idx, edge) in input.iter().enumerate() {
+        for (seg_idx, line) in edge.geometry.lines().enumerate() {
+            segments.push(GeomWithData::new(
+                RLine::new([line.start.x, line.start.y], [line.end.x, line.end.y]),
+                (line_idx, seg_idx),
+            ));
+        }
+    }
+    let rtree = RTree::bulk_load(segments);
+
+    // Per line, the segment index and point to split at
+    let mut cuts: Vec<Vec<(usize, Coord)>> = vec![Vec::new(); input.len()];
+
+    let mut snapped_ends = 0;
+    if tolerance_meters > 0.0 {
+        for line_idx in 0..input.len() {
+            let last_idx = input[line_idx].geometry.0.len() - 1;
+            for pt_idx in [0, last_idx] {
+                let pt = input[line_idx].geometry.0[pt_idx];
+                let Some((other_line, other_seg, snap_to)) =
+                    closest_other_line(&input, &rtree, line_idx, pt, tolerance_meters)
+                else {
+                    continue;
+                };
+                // If it's close to the other line's end, merging endpoints handles it
+                let other_pts = &input[other_line].geometry.0;
+                if [other_pts[0], *other_pts.last().unwrap()]
+                    .into_iter()
+                    .any(|end| distance(end, snap_to) <= tolerance_meters)
+                {
+                    continue;
+                }
+                input[line_idx].geometry.0[pt_idx] = snap_to;
+                add_cut(
+                    &mut cuts[other_line],
+                    &input[other_line].geometry,
+                    other_seg,
+                    snap_to,
+                );
+                snapped_ends += 1;
+            }
+        }
+    }
+
+    let mut crossings = 0;
+    for (line_idx, edge) in input.iter().enumerate() {
+        for (seg_idx, line) in edge.geometry.lines().enumerate() {
+            let envelope = AABB::from_corners(
+                [line.start.x.min(line.end.x), line.start.y.min(line.end.y)],
+                [line.start.x.max(line.end.x), line.start.y.max(line.end.y)],
+            );
+            for obj in rtree.locate_in_envelope_intersecting(&envelope) {
+                let (other_line, other_seg) = obj.data;
+                // Each pair is only checked once. Lines crossing themselves aren't split.
+                if other_line <= line_idx {
+                    continue;
+                }
+                let other = Line::new(
+                    input[other_line].geometry.0[other_seg],
+                    input[other_line].geometry.0[other_seg + 1],
+                );
+                let pts = match line_intersection(line, other) {
+                    Some(LineIntersection::SinglePoint { intersection, .. }) => vec![intersection],
+                    // Where lines overlap, split at both ends of the overlap
+                    Some(LineIntersection::Collinear { intersection }) => {
+                        vec![intersection.start, intersection.end]
+                    }
+                    None => Vec::new(),
+                };
+                for pt in pts {
+                    let new1 = add_cut(&mut cuts[line_idx], &edge.geometry, seg_idx, pt);
+                    let new2 = add_cut(
+                        &mut cuts[other_line],
+                        &input[other_line].geometry,
+                        other_seg,
+                        pt,
+                    );
+                    if new1 || new2 {
+                        crossings += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let num_lines = input.len();
+    let mut output = Vec::new();
+    for (edge, mut cuts) in input.into_iter().zip(cuts) {
+        if cuts.is_empty() {
+            output.push(edge);
+            continue;
+        }
+        let pts = &edge.geometry.0;
+        cuts.sort_by(|(seg1, pt1), (seg2, pt2)| {
+            seg1.cmp(seg2).then_with(|| {
+                distance_squared(pts[*seg1], *pt1).total_cmp(&distance_squared(pts[*seg2], *pt2))
+            })
+        });
+
+        let mut pieces = Vec::new();
+        let mut current = vec![pts[0]];
+        let mut cuts = cuts.into_iter().peekable();
+        for seg_idx in 0..pts.len() - 1 {
+            while let Some((_, pt)) = cuts.next_if(|(seg, _)| *seg == seg_idx) {
+                current.push(pt);
+                pieces.push(std::mem::replace(&mut current, vec![pt]));
+            }
+            current.push(pts[seg_idx + 1]);
+        }
+        pieces.push(current);
+
+        let total_length = edge.geometry.haversine_length();
+        let num_pieces = pieces.len();
+        for mut piece in pieces {
+            piece.dedup();
+            if piece.len() < 2 {
+                continue;
+            }
+            let geometry = LineString::new(piece);
+            let ratio = if total_length == 0.0 {
+                1.0 / num_pieces as f64
+            } else {
+                geometry.haversine_length() / total_length
+            };
+            output.push(edge.piece(geometry, ratio, cost_profiles));
+        }
+    }
+
+    info!(
+        "Noding split {num_lines} lines into {} at {crossings} crossings, after moving {snapped_ends} ends within {tolerance_meters}m onto other lines",
+        output.len()
+    );
+    let summary = NodingSummary {
+        lines_before: num_lines,
+        lines_after: output.len(),
+        crossings,
+        snapped_ends,
+    };
+    (output, summary)
+}
+
+/// Remembers where to split a line, unless it's already split there or it's at an end. Returns
+/// true if this is a new cut.
+fn add_cut(cuts: &mut Vec<(usize, Coord)>, line: &LineString, seg_idx: usize, pt: Coord) -> bool {
+    let key = hashify_point(pt);
+    if key == hashify_point(line.0[0]) || key == hashify_point(*line.0.last().unwrap()) {
+        return false;
+    }
+    if cuts
+        .iter()
+        .any(|(_, existing)| hashify_point(*existing) == key)
+    {
+        return false;
+    }
+    cuts.push((seg_idx, pt));
+    true
+}
+
+/// Finds the closest point on a different line within the tolerance, returning that line, the
+/// segment, and the point.
+fn closest_other_line(
+    input: &[InputEdge],
+    rtree: &RTree<Segment>,
+    line_idx: usize,
+    pt: Coord,
+    tolerance_meters: f64,
+) -> Option<(usize, usize, Coord)> {
+    let radius = degrees_for_meters(tolerance_meters, pt.y);
+    let envelope = AABB::from_corners(
+        [pt.x - radius, pt.y - radius],
+        [pt.x + radius, pt.y + radius],
+    );
+    let mut best: Option<(f64, usize, usize, Coord)> = None;
+    for obj in rtree.locate_in_envelope_intersecting(&envelope) {
+        let (other_line, other_seg) = obj.data;
+        if other_line == line_idx {
+            continue;
+        }
+        let segment = Line::new(
+            input[other_line].geometry.0[other_seg],
+            input[other_line].geometry.0[other_seg + 1],
+        );
+        let closest = match segment.closest_point(&Point::from(pt)) {
+            Closest::Intersection(x) | Closest::SinglePoint(x) => x.0,
+            Closest::Indeterminate => continue,
+        };
+        let dist = distance(pt, closest);
+        if dist > 0.0
+            && dist <= tolerance_meters
+            && !best.as_ref().is_some_and(|(d, _, _, _)| dist >= *d)
+        {
+            best = Some((dist, other_line, other_seg, closest));
+        }
+    }
+    best.map(|(_, line, seg, pt)| (line, seg, pt))
+}
+
+fn distance_squared(pt1: Coord, pt2: Coord) -> f64 {
+    (pt1.x - pt2.x).powi(2) + (pt1.y - pt2.y).powi(2)
+}
+
+/// A generous approximation of how many degrees of longitude or latitude cover some distance.
+pub fn degrees_for_meters(meters: f64, latitude: f64) -> f64 {
+    meters / (111_320.0 * latitude.to_radians().cos().max(0.01))
+}
+
+pub fn distance(pt1: Coord, pt2: Coord) -> f64 {
+    Point::from(pt1).haversine_distance(&Point::from(pt2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exported::ExportedEdge;
+    use crate::tests::{feature_collection, line};
+    use crate::{convert_geojson, Options};
+
+    fn edge(pts: &[(f64, f64)], cost: f64) -> InputEdge {
+        InputEdge {
+            geometry: LineString::from(pts.to_vec()),
+            name: None,
+            forward_cost: Some(cost),
+            backward_cost: Some(cost),
+            other_properties: serde_json::Map::new(),
+            exported: ExportedEdge::default(),
+        }
+    }
+
+    fn summary(
+        lines_before: usize,
+        lines_after: usize,
+        crossings: usize,
+        snapped_ends: usize,
+    ) -> NodingSummary {
+        NodingSummary {
+            lines_before,
+            lines_after,
+            crossings,
+            snapped_ends,
+        }
+    }
+
+    const HORIZONTAL: [(f64, f64); 2] = [(0.0, 51.5), (0.002, 51.5)];
+
+    #[test]
+    fn test_gap_to_middle() {
+        // Stops about 4.5cm short of the middle of the other line
+        let input = || {
+            vec![
+                edge(&HORIZONTAL, 20.0),
+                edge(&[(0.001, 51.501), (0.001, 51.500_000_4)], 10.0),
+            ]
+        };
+        let (output, result) = node_lines(input(), 0.1, &[]);
+        assert_eq!(result, summary(2, 3, 0, 1));
+        assert_eq!(
+            output[0].geometry.0.last(),
+            Some(&Coord { x: 0.001, y: 51.5 })
+        );
+        // Split evenly
+        for piece in &output[1..] {
+            assert!((piece.forward_cost.unwrap() - 10.0).abs() < 0.01);
+        }
+
+        // Without a tolerance, nothing changes
+        let (_, result) = node_lines(input(), 0.0, &[]);
+        assert_eq!(result, summary(2, 2, 0, 0));
+    }
+
+    #[test]
+    fn test_gap_between_ends() {
+        // About 5cm apart
+        let input = feature_collection(&[
+            &line(
+                "[[0.0, 51.5], [0.0009997, 51.5]]",
+                r#"{"forward_cost": 1, "backward_cost": 1}"#,
+            ),
+            &line(
+                "[[0.0010004, 51.5], [0.002, 51.5]]",
+                r#"{"forward_cost": 1, "backward_cost": 1}"#,
+            ),
+        ]);
+        let map = convert_geojson(input.clone(), Options::default()).unwrap();
+        assert_eq!(map.nodes.len(), 4);
+        let map = convert_geojson(
+            input,
+            Options {
+                noding: true,
+                snap_tolerance_meters: Some(0.1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(map.nodes.len(), 3);
+        assert_eq!(map.edges.len(), 2);
+    }
+
+    #[test]
+    fn test_t_junction() {
+        let (output, result) = node_lines(
+            vec![
+                edge(&HORIZONTAL, 20.0),
+                edge(&[(0.001, 51.501), (0.001, 51.5)], 10.0),
+            ],
+            0.0,
+            &[],
+        );
+        assert_eq!(result, summary(2, 3, 1, 0));
+        assert_eq!(
+            output[0].geometry.0,
+            vec![Coord { x: 0.0, y: 51.5 }, Coord { x: 0.001, y: 51.5 }]
+        );
+    }
+
+    #[test]
+    fn test_x_crossing() {
+        let (output, result) = node_lines(
+            vec![
+                edge(&HORIZONTAL, 20.0),
+                edge(&[(0.001, 51.501), (0.001, 51.499)], 10.0),
+            ],
+            0.0,
+            &[],
+        );
+        assert_eq!(result, summary(2, 4, 1, 0));
+        for piece in output {
+            assert!(piece.geometry.0.contains(&Coord { x: 0.001, y: 51.5 }));
+        }
+    }
+
+    #[test]
+    fn test_collinear_overlap() {
+        let (output, result) = node_lines(
+            vec![
+                edge(&HORIZONTAL, 20.0),
+                edge(&[(0.001, 51.5), (0.003, 51.5)], 20.0),
+            ],
+            0.0,
+            &[],
+        );
+        // Split at both ends of the overlap
+        assert_eq!(result, summary(2, 4, 2, 0));
+        let xs: Vec<(f64, f64)> = output
+            .iter()
+            .map(|e| (e.geometry.0[0].x, e.geometry.0.last().unwrap().x))
+            .collect();
+        assert_eq!(
+            xs,
+            vec![(0.0, 0.001), (0.001, 0.002), (0.001, 0.002), (0.002, 0.003)]
+        );
+    }
+}
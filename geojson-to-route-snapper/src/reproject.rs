@@ -0,0 +1,144 @@
+use anyhow::{anyhow, bail, Result};
+use geo::{Coord, LineString};
+use proj4rs::adaptors::transform_xy;
+use proj4rs::Proj;
+
+/// Transforms coordinates from some projected coordinate system into WGS84 longitude and latitude.
+pub struct Reprojection {
+    name: String,
+    src: Proj,
+    dst: Proj,
+}
+
+impl Reprojection {
+    /// Understands a few common coordinate systems by EPSG code, like `EPSG:27700` or
+    /// `urn:ogc:def:crs:EPSG::32630`, or any proj string starting with `+proj`. Returns `None` for
+    /// WGS84, which needs no reprojection.
+    pub fn new(crs: &str) -> Result<Option<Self>> {
+        let crs = crs.trim();
+        let definition = if crs.starts_with("+proj") {
+            crs.to_string()
+        } else {
+            let Some(code) = epsg_code(crs) else {
+                bail!("Unknown coordinate system {crs}; use an EPSG code or a proj string");
+            };
+            if code == 4326 {
+                return Ok(None);
+            }
+            proj_string(code).ok_or_else(|| {
+                anyhow!("EPSG:{code} isn't supported; pass a proj string for it instead")
+            })?
+        };
+
+        let src = Proj::from_proj_string(&definition)
+            .map_err(|err| anyhow!("Couldn't understand {definition}: {err}"))?;
+        let dst = Proj::from_proj_string("+proj=longlat +datum=WGS84 +no_defs").unwrap();
+        Ok(Some(Self {
+            name: crs.to_string(),
+            src,
+            dst,
+        }))
+    }
+
+    pub fn transform(&self, line: &mut LineString) -> Result<()> {
+        for pt in &mut line.0 {
+            let (x, y) = transform_xy(&self.src, &self.dst, pt.x, pt.y)
+                .map_err(|err| anyhow!("Couldn't reproject {pt:?} from {}: {err}", self.name))?;
+            *pt = Coord {
+                x: x.to_degrees(),
+                y: y.to_degrees(),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Parses forms like `EPSG:27700`, `urn:ogc:def:crs:EPSG::27700`, or just `27700`.
+fn epsg_code(crs: &str) -> Option<u32> {
+    if crs.eq_ignore_ascii_case("urn:ogc:def:crs:OGC:1.3:CRS84") {
+        return Some(4326);
+    }
+    let upper = crs.to_uppercase();
+    let code = if upper.contains("EPSG") {
+        upper.rsplit(':').next()?
+    } else {
+        &upper
+    };
+    code.parse().ok()
+}
+
+fn proj_string(code: u32) -> Option<String> {
+    let definition = match code {
+        // British National Grid
+        27700 => "+proj=tmerc +lat_0=49 +lon_0=-2 +k=0.9996012717 +x_0=400000 +y_0=-100000 +ellps=airy +towgs84=446.448,-125.157,542.06,0.15,0.247,0.842,-20.489 +units=m +no_defs".to_string(),
+        // Irish Grid and Irish Transverse Mercator
+        29903 => "+proj=tmerc +lat_0=53.5 +lon_0=-8 +k=1.000035 +x_0=200000 +y_0=250000 +ellps=mod_airy +towgs84=482.5,-130.6,564.6,-1.042,-0.214,-0.631,8.15 +units=m +no_defs".to_string(),
+        2157 => "+proj=tmerc +lat_0=53.5 +lon_0=-8 +k=0.99982 +x_0=600000 +y_0=750000 +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +units=m +no_defs".to_string(),
+        // France, Lambert-93
+        2154 => "+proj=lcc +lat_0=46.5 +lon_0=3 +lat_1=49 +lat_2=44 +x_0=700000 +y_0=6600000 +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +units=m +no_defs".to_string(),
+        // Netherlands, RD New
+        28992 => "+proj=sterea +lat_0=52.1561605555556 +lon_0=5.38763888888889 +k=0.9999079 +x_0=155000 +y_0=463000 +ellps=bessel +towgs84=565.4171,50.3319,465.5524,1.9342,-1.6677,9.1019,4.0725 +units=m +no_defs".to_string(),
+        // Switzerland, LV95
+        2056 => "+proj=somerc +lat_0=46.9524055555556 +lon_0=7.43958333333333 +k_0=1 +x_0=2600000 +y_0=1200000 +ellps=bessel +towgs84=674.374,15.056,405.346,0,0,0,0 +units=m +no_defs".to_string(),
+        // Web Mercator
+        3857 => "+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m +no_defs".to_string(),
+        // WGS84 UTM zones, north and south
+        32601..=32660 => format!("+proj=utm +zone={} +datum=WGS84 +units=m +no_defs", code - 32600),
+        32701..=32760 => format!(
+            "+proj=utm +zone={} +south +datum=WGS84 +units=m +no_defs",
+            code - 32700
+        ),
+        // ETRS89 UTM zones
+        25828..=25838 => format!(
+            "+proj=utm +zone={} +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +units=m +no_defs",
+            code - 25800
+        ),
+        _ => return None,
+    };
+    Some(definition)
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{HaversineDistance, Point};
+
+    use super::*;
+
+    // Expected values are from independent implementations of the projection formulas and datum
+    // shift
+    fn check(crs: &str, input: (f64, f64), expected: (f64, f64)) {
+        let reprojection = Reprojection::new(crs).unwrap().unwrap();
+        let mut line = LineString::from(vec![input]);
+        reprojection.transform(&mut line).unwrap();
+        let error = Point::from(line.0[0]).haversine_distance(&Point::from(expected));
+        assert!(error < 1.0, "{crs} is {error}m off");
+    }
+
+    #[test]
+    fn test_known_points() {
+        // The Ordnance Survey's worked example
+        check(
+            "EPSG:27700",
+            (651409.903, 313177.270),
+            (1.716052, 52.657979),
+        );
+        // London in UTM zone 30 north
+        check(
+            "urn:ogc:def:crs:EPSG::32630",
+            (701277.665, 5709417.125),
+            (-0.1, 51.5),
+        );
+        // Sydney in UTM zone 56 south
+        check("32756", (334900.570, 6252288.753), (151.2153, -33.8568));
+    }
+
+    #[test]
+    fn test_crs_names() {
+        for wgs84 in ["EPSG:4326", "urn:ogc:def:crs:OGC:1.3:CRS84", "4326"] {
+            assert!(Reprojection::new(wgs84).unwrap().is_none());
+        }
+        assert!(Reprojection::new("+proj=utm +zone=30 +datum=WGS84").is_ok_and(|x| x.is_some()));
+        assert!(Reprojection::new("EPSG:1234").is_err());
+        assert!(Reprojection::new("somewhere").is_err());
+    }
+}
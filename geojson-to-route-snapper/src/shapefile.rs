@@ -0,0 +1,486 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use geo::{Coord, LineString, MultiLineString};
+use log::warn;
+use serde_json::{Map, Value};
+
+use crate::source::{FeatureSource, RawFeature};
+
+/// Reads a Shapefile, one record at a time from the `.shp` and `.dbf` files together. The
+/// coordinate system is guessed from the `.prj` file, if there is one.
+pub struct ShapefileSource {
+    shp: BufReader<File>,
+    dbf: BufReader<File>,
+    fields: Vec<Field>,
+    num_records: u32,
+    crs: Option<String>,
+}
+
+struct Field {
+    name: String,
+    field_type: u8,
+    length: usize,
+    decimals: u8,
+}
+
+impl ShapefileSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let path = Path::new(path);
+        let mut shp = BufReader::new(File::open(path)?);
+        let mut header = [0; 100];
+        shp.read_exact(&mut header)?;
+        if i32::from_be_bytes(header[0..4].try_into().unwrap()) != 9994 {
+            bail!("{} isn't a Shapefile", path.display());
+        }
+        let shape_type = i32::from_le_bytes(header[32..36].try_into().unwrap());
+        if !matches!(shape_type, 3 | 13 | 23) {
+            bail!(
+                "{} has shape type {shape_type}, but only PolyLines are supported",
+                path.display()
+            );
+        }
+
+        let dbf_path = path.with_extension("dbf");
+        let mut dbf = BufReader::new(
+            File::open(&dbf_path).with_context(|| format!("opening {}", dbf_path.display()))?,
+        );
+        let mut header = [0; 32];
+        dbf.read_exact(&mut header)?;
+        let num_records = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let header_length = u16::from_le_bytes(header[8..10].try_into().unwrap()) as usize;
+        let mut descriptors = vec![0; header_length.saturating_sub(32)];
+        dbf.read_exact(&mut descriptors)?;
+        let mut fields = Vec::new();
+        for descriptor in descriptors.chunks_exact(32) {
+            if descriptor[0] == 0x0D {
+                break;
+            }
+            let name_end = descriptor[0..11].iter().position(|b| *b == 0).unwrap_or(11);
+            fields.push(Field {
+                name: String::from_utf8_lossy(&descriptor[0..name_end]).to_string(),
+                field_type: descriptor[11],
+                length: descriptor[16] as usize,
+                decimals: descriptor[17],
+            });
+        }
+
+        let crs = match std::fs::read_to_string(path.with_extension("prj")) {
+            Ok(wkt) => {
+                let crs = crs_from_wkt(&wkt);
+                if crs.is_none() {
+                    warn!("Couldn't work out the coordinate system from the .prj file: {wkt}");
+                }
+                crs
+            }
+            Err(_) => None,
+        };
+
+        Ok(Self {
+            shp,
+            dbf,
+            fields,
+            num_records,
+            crs,
+        })
+    }
+
+    fn read_properties(&mut self) -> Result<Option<Map<String, Value>>> {
+        let mut deleted = [0];
+        self.dbf.read_exact(&mut deleted)?;
+        let mut properties = Map::new();
+        for field in &self.fields {
+            let mut raw = vec![0; field.length];
+            self.dbf.read_exact(&mut raw)?;
+            let raw = String::from_utf8_lossy(&raw);
+            let raw = raw.trim_matches(|c: char| c == ' ' || c == '\0');
+            let value = match field.field_type {
+                b'N' | b'F' => match raw.parse::<i64>() {
+                    Ok(x) if field.decimals == 0 => Value::from(x),
+                    _ => raw.parse::<f64>().map(Value::from).unwrap_or(Value::Null),
+                },
+                b'L' => match raw {
+                    "T" | "t" | "Y" | "y" => Value::Bool(true),
+                    "F" | "f" | "N" | "n" => Value::Bool(false),
+                    _ => Value::Null,
+                },
+                _ => {
+                    if raw.is_empty() {
+                        Value::Null
+                    } else {
+                        Value::String(raw.to_string())
+                    }
+                }
+            };
+            properties.insert(field.name.clone(), value);
+        }
+        Ok((deleted[0] != b'*').then_some(properties))
+    }
+}
+
+impl FeatureSource for ShapefileSource {
+    fn crs(&self) -> Option<String> {
+        self.crs.clone()
+    }
+
+    fn for_each_feature(
+        &mut self,
+        f: &mut dyn FnMut(Result<RawFeature>) -> Result<()>,
+    ) -> Result<()> {
+        for _ in 0..self.num_records {
+            let mut header = [0; 8];
+            self.shp.read_exact(&mut header)?;
+            // In 16-bit words
+            let content_length = i32::from_be_bytes(header[4..8].try_into().unwrap());
+            if content_length < 0 {
+                bail!("Shapefile record has a negative length");
+            }
+            let content_length = 2 * content_length as u64;
+            // Don't allocate a huge buffer up-front for a corrupt length
+            let mut content = Vec::new();
+            (&mut self.shp)
+                .take(content_length)
+                .read_to_end(&mut content)?;
+            if content.len() as u64 != content_length {
+                bail!("Shapefile is truncated");
+            }
+
+            let Some(properties) = self.read_properties()? else {
+                continue;
+            };
+            f(parse_polyline(&content).map(|geometry| RawFeature {
+                geometry,
+                properties,
+            }))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses one PolyLine record, ignoring any Z and M values after the points.
+fn parse_polyline(content: &[u8]) -> Result<Option<geo::Geometry>> {
+    let i32_at = |pos: usize| -> Result<i32> {
+        match content.get(pos..pos + 4) {
+            Some(bytes) => Ok(i32::from_le_bytes(bytes.try_into().unwrap())),
+            None => bail!("record is truncated"),
+        }
+    };
+    let f64_at = |pos: usize| -> Result<f64> {
+        match content.get(pos..pos + 8) {
+            Some(bytes) => Ok(f64::from_le_bytes(bytes.try_into().unwrap())),
+            None => bail!("record is truncated"),
+        }
+    };
+
+    match i32_at(0)? {
+        0 => return Ok(None),
+        3 | 13 | 23 => {}
+        x => bail!("shape type {x} isn't a PolyLine"),
+    }
+    // Skip the bounding box
+    let (num_parts, num_points) = (i32_at(36)?, i32_at(40)?);
+    if num_parts < 0 || num_points < 0 {
+        bail!("record has a negative number of parts or points");
+    }
+    let (num_parts, num_points) = (num_parts as usize, num_points as usize);
+    let points_start = 44 + 4 * num_parts;
+    if points_start + 16 * num_points > content.len() {
+        bail!("record is truncated");
+    }
+    let mut starts = Vec::with_capacity(num_parts + 1);
+    for idx in 0..num_parts {
+        starts.push(i32_at(44 + 4 * idx)? as usize);
+    }
+    starts.push(num_points);
+    if starts.windows(2).any(|pair| pair[0] > pair[1]) {
+        bail!("record has invalid parts");
+    }
+
+    let mut lines = Vec::new();
+    for pair in starts.windows(2) {
+        let mut pts = Vec::new();
+        for idx in pair[0]..pair[1] {
+            let pos = points_start + 16 * idx;
+            pts.push(Coord {
+                x: f64_at(pos)?,
+                y: f64_at(pos + 8)?,
+            });
+        }
+        lines.push(LineString::new(pts));
+    }
+    if lines.len() == 1 {
+        Ok(Some(lines.pop().unwrap().into()))
+    } else {
+        Ok(Some(MultiLineString::new(lines).into()))
+    }
+}
+
+/// Recognizes a few common coordinate systems from the WKT in a `.prj` file. Only some writers
+/// include the EPSG code, so otherwise it looks for well-known names.
+fn crs_from_wkt(wkt: &str) -> Option<String> {
+    let normalized = wkt.trim().replace(' ', "_").to_uppercase();
+    if normalized.starts_with("GEOGCS") {
+        // Longitude and latitude already
+        return None;
+    }
+    // The last authority belongs to the outermost PROJCS
+    if let Some(idx) = normalized.rfind("AUTHORITY[\"EPSG\",\"") {
+        let code: String = normalized[idx + 18..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        if !code.is_empty() {
+            return Some(format!("EPSG:{code}"));
+        }
+    }
+    if normalized.contains("BRITISH_NATIONAL_GRID") {
+        return Some("EPSG:27700".to_string());
+    }
+    if normalized.contains("MERCATOR_AUXILIARY_SPHERE") || normalized.contains("PSEUDO-MERCATOR") {
+        return Some("EPSG:3857".to_string());
+    }
+    if let Some(idx) = normalized.find("UTM_ZONE_") {
+        let rest = &normalized[idx + 9..];
+        let zone: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let zone: u32 = zone.parse().ok()?;
+        let north = rest.chars().find(|c| !c.is_ascii_digit()) == Some('N');
+        let prefix = &normalized[..idx];
+        if prefix.contains("ETRS") && north {
+            return Some(format!("EPSG:{}", 25800 + zone));
+        }
+        if prefix.contains("WGS_1984") || prefix.contains("WGS_84") {
+            let base = if north { 32600 } else { 32700 };
+            return Some(format!("EPSG:{}", base + zone));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// The content of a PolyLine record. Shape type 13 adds Z values, and 23 adds M values.
+    fn polyline(shape_type: i32, parts: &[&[(f64, f64)]]) -> Vec<u8> {
+        let num_points: usize = parts.iter().map(|part| part.len()).sum();
+        let mut bytes = shape_type.to_le_bytes().to_vec();
+        bytes.extend([0; 32]);
+        bytes.extend((parts.len() as i32).to_le_bytes());
+        bytes.extend((num_points as i32).to_le_bytes());
+        let mut start = 0;
+        for part in parts {
+            bytes.extend((start as i32).to_le_bytes());
+            start += part.len();
+        }
+        for (x, y) in parts.iter().flat_map(|part| part.iter()) {
+            bytes.extend(x.to_le_bytes());
+            bytes.extend(y.to_le_bytes());
+        }
+        if shape_type != 3 {
+            // The range, then a value per point
+            for _ in 0..2 + num_points {
+                bytes.extend(99.0_f64.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Writes a `.shp` and `.dbf` with these records and (name, type, length, decimals) fields.
+    /// Rows starting with `*` are deleted.
+    fn write_shapefile(
+        name: &str,
+        records: &[Vec<u8>],
+        fields: &[(&str, u8, u8, u8)],
+        rows: &[&[&str]],
+    ) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}_{}.shp", std::process::id()));
+
+        let mut shp = vec![0; 100];
+        shp[0..4].copy_from_slice(&9994_i32.to_be_bytes());
+        shp[28..32].copy_from_slice(&1000_i32.to_le_bytes());
+        shp[32..36].copy_from_slice(&3_i32.to_le_bytes());
+        for (idx, content) in records.iter().enumerate() {
+            shp.extend((idx as i32 + 1).to_be_bytes());
+            shp.extend((content.len() as i32 / 2).to_be_bytes());
+            shp.extend(content);
+        }
+        std::fs::write(&path, shp).unwrap();
+
+        let mut dbf = vec![0; 32];
+        dbf[0] = 3;
+        dbf[4..8].copy_from_slice(&(rows.len() as u32).to_le_bytes());
+        dbf[8..10].copy_from_slice(&(32 + 32 * fields.len() as u16 + 1).to_le_bytes());
+        for (name, field_type, length, decimals) in fields {
+            let mut descriptor = [0; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = *field_type;
+            descriptor[16] = *length;
+            descriptor[17] = *decimals;
+            dbf.extend(descriptor);
+        }
+        dbf.push(0x0D);
+        for row in rows {
+            let (deleted, values) = match row.split_first() {
+                Some((&"*", rest)) => (b'*', rest),
+                _ => (b' ', *row),
+            };
+            dbf.push(deleted);
+            for (value, (_, _, length, _)) in values.iter().zip(fields) {
+                dbf.extend(format!("{value:>width$}", width = *length as usize).into_bytes());
+            }
+        }
+        std::fs::write(path.with_extension("dbf"), dbf).unwrap();
+        path
+    }
+
+    fn read_all(path: &std::path::Path) -> Result<Vec<RawFeature>> {
+        let mut source = ShapefileSource::open(path.to_str().unwrap())?;
+        let mut features = Vec::new();
+        source.for_each_feature(&mut |feature| {
+            features.push(feature?);
+            Ok(())
+        })?;
+        Ok(features)
+    }
+
+    #[test]
+    fn test_read() {
+        let line = [(0.0, 0.0), (1.0, 1.0)];
+        let path = write_shapefile(
+            "test_read",
+            &[
+                polyline(3, &[&line]),
+                polyline(13, &[&line, &[(2.0, 2.0), (3.0, 3.0)]]),
+                polyline(23, &[&line]),
+                0_i32.to_le_bytes().to_vec(),
+                polyline(3, &[&line]),
+            ],
+            &[
+                ("name", b'C', 10, 0),
+                ("lanes", b'N', 3, 0),
+                ("width", b'N', 5, 1),
+                ("speed", b'F', 6, 2),
+                ("lit", b'L', 1, 0),
+            ],
+            &[
+                &["Main St", "2", "3.5", "30.25", "T"],
+                &["", "", "", "", "?"],
+                &["Side St", "1", "2.0", "x", "n"],
+                &["No shape", "1", "1.0", "1", "F"],
+                &["*", "Deleted", "1", "1.0", "1", "F"],
+            ],
+        );
+        std::fs::write(
+            path.with_extension("prj"),
+            r#"PROJCS["British_National_Grid",GEOGCS["GCS_OSGB_1936"]]"#,
+        )
+        .unwrap();
+
+        let features = read_all(&path).unwrap();
+        assert_eq!(features.len(), 4);
+        let line = LineString::from(line.to_vec());
+        assert_eq!(features[0].geometry, Some(line.clone().into()));
+        assert_eq!(
+            features[1].geometry,
+            Some(
+                MultiLineString::new(vec![
+                    line.clone(),
+                    LineString::from(vec![(2.0, 2.0), (3.0, 3.0)])
+                ])
+                .into()
+            )
+        );
+        assert_eq!(features[2].geometry, Some(line.into()));
+        assert_eq!(features[3].geometry, None);
+
+        assert_eq!(
+            Value::Object(features[0].properties.clone()),
+            json!({"name": "Main St", "lanes": 2, "width": 3.5, "speed": 30.25, "lit": true})
+        );
+        assert_eq!(
+            Value::Object(features[1].properties.clone()),
+            json!({"name": null, "lanes": null, "width": null, "speed": null, "lit": null})
+        );
+        assert_eq!(
+            Value::Object(features[2].properties.clone()),
+            json!({"name": "Side St", "lanes": 1, "width": 2.0, "speed": null, "lit": false})
+        );
+
+        let source = ShapefileSource::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(source.crs(), Some("EPSG:27700".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let line: &[(f64, f64)] = &[(0.0, 0.0), (1.0, 1.0)];
+        let fields = [("name", b'C', 1, 0)];
+
+        // A negative or oversized length
+        for length in [-1, i32::MAX] {
+            let path = write_shapefile(
+                "test_record_length",
+                &[polyline(3, &[line])],
+                &fields,
+                &[&["a"]],
+            );
+            let mut shp = std::fs::read(&path).unwrap();
+            shp[104..108].copy_from_slice(&length.to_be_bytes());
+            std::fs::write(&path, shp).unwrap();
+            assert!(read_all(&path).is_err());
+        }
+
+        let content = polyline(3, &[line, line]);
+        for len in 0..content.len() {
+            assert!(parse_polyline(&content[..len]).is_err());
+        }
+        // Negative or huge counts
+        for (pos, value) in [(36, -1), (40, -1), (36, i32::MAX), (40, i32::MAX)] {
+            let mut bad = content.clone();
+            bad[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+            assert!(parse_polyline(&bad).is_err());
+        }
+        // Parts out of order
+        let mut bad = content.clone();
+        bad[48..52].copy_from_slice(&5_i32.to_le_bytes());
+        assert!(parse_polyline(&bad).is_err());
+    }
+
+    #[test]
+    fn test_crs_from_wkt() {
+        for (wkt, expected) in [
+            (r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984"]]"#, None),
+            // The outermost authority wins
+            (
+                r#"PROJCS["OSGB 1936 / British National Grid",GEOGCS["OSGB 1936",AUTHORITY["EPSG","4277"]],AUTHORITY["EPSG","27700"]]"#,
+                Some("EPSG:27700"),
+            ),
+            (
+                r#"PROJCS["British_National_Grid",GEOGCS["GCS_OSGB_1936"]]"#,
+                Some("EPSG:27700"),
+            ),
+            (
+                r#"PROJCS["WGS_1984_UTM_Zone_30N",GEOGCS["GCS_WGS_1984"]]"#,
+                Some("EPSG:32630"),
+            ),
+            (
+                r#"PROJCS["WGS 84 / UTM zone 33S",GEOGCS["WGS 84"]]"#,
+                Some("EPSG:32733"),
+            ),
+            (
+                r#"PROJCS["ETRS_1989_UTM_Zone_32N",GEOGCS["GCS_ETRS_1989"]]"#,
+                Some("EPSG:25832"),
+            ),
+            (
+                r#"PROJCS["WGS_1984_Web_Mercator_Auxiliary_Sphere",GEOGCS["GCS_WGS_1984"]]"#,
+                Some("EPSG:3857"),
+            ),
+            (r#"PROJCS["Somewhere_Local",GEOGCS["GCS_Local"]]"#, None),
+        ] {
+            assert_eq!(crs_from_wkt(wkt).as_deref(), expected, "for {wkt}");
+        }
+    }
+}
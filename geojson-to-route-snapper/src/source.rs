@@ -0,0 +1,121 @@
+use anyhow::{bail, Result};
+use geojson::Feature;
+use route_snapper_graph::export::METADATA_KEY;
+use serde_json::{Map, Value};
+
+use crate::exported::ExportMetadata;
+
+/// One feature from any input format, before it's checked.
+pub struct RawFeature {
+    pub geometry: Option<geo::Geometry>,
+    pub properties: Map<String, Value>,
+}
+
+/// Something features can be read from, one at a time.
+pub trait FeatureSource {
+    /// The coordinate system declared by the input, like `EPSG:27700`, if any.
+    fn crs(&self) -> Option<String>;
+
+    /// If the input was written by `route_snapper_graph::export`, the graph's metadata.
+    fn exported_metadata(&self) -> Result<Option<ExportMetadata>> {
+        Ok(None)
+    }
+
+    /// Calls `f` with every feature in order. A feature that can't be decoded is passed as an
+    /// error, so the caller can decide whether to skip it. Stops at the first error returned by
+    /// `f`.
+    fn for_each_feature(
+        &mut self,
+        f: &mut dyn FnMut(Result<RawFeature>) -> Result<()>,
+    ) -> Result<()>;
+}
+
+/// Reads a GeoJSON FeatureCollection. Each feature is decoded separately, so one malformed
+/// feature doesn't stop the rest from being read.
+pub struct GeoJsonSource {
+    /// Top-level members besides `type`, `features`, and `bbox`
+    foreign_members: Map<String, Value>,
+    features: Vec<Value>,
+}
+
+impl GeoJsonSource {
+    pub fn new(input_string: &str) -> Result<Self> {
+        let Value::Object(mut object) = serde_json::from_str(input_string)? else {
+            bail!("GeoJSON input isn't an object");
+        };
+        if object.get("type").and_then(|x| x.as_str()) != Some("FeatureCollection") {
+            bail!("GeoJSON input isn't a FeatureCollection");
+        }
+        let Some(Value::Array(features)) = object.remove("features") else {
+            bail!("GeoJSON FeatureCollection has no features array");
+        };
+        object.remove("type");
+        object.remove("bbox");
+        Ok(Self {
+            foreign_members: object,
+            features,
+        })
+    }
+}
+
+impl FeatureSource for GeoJsonSource {
+    fn crs(&self) -> Option<String> {
+        // The `crs` member was removed from the GeoJSON spec, but is still common
+        self.foreign_members
+            .get("crs")?
+            .pointer("/properties/name")?
+            .as_str()
+            .map(|x| x.to_string())
+    }
+
+    fn exported_metadata(&self) -> Result<Option<ExportMetadata>> {
+        let Some(value) = self.foreign_members.get(METADATA_KEY) else {
+            return Ok(None);
+        };
+        match serde_json::from_value(value.clone()) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) => bail!("The {METADATA_KEY} member is invalid: {err}"),
+        }
+    }
+
+    fn for_each_feature(
+        &mut self,
+        f: &mut dyn FnMut(Result<RawFeature>) -> Result<()>,
+    ) -> Result<()> {
+        for value in std::mem::take(&mut self.features) {
+            f(read_geojson_feature(value))?;
+        }
+        Ok(())
+    }
+}
+
+fn read_geojson_feature(value: Value) -> Result<RawFeature> {
+    let feature = Feature::try_from(value)?;
+    let geometry = feature.geometry.map(geo::Geometry::try_from).transpose()?;
+    Ok(RawFeature {
+        geometry,
+        properties: feature.properties.unwrap_or_default(),
+    })
+}
+
+/// Opens a GeoJSON, GeoPackage, FlatGeobuf, or Shapefile, based on the file extension. Only
+/// GeoPackages can have more than one layer, so `layer` is ignored otherwise.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_file(path: &str, layer: Option<&str>) -> Result<Box<dyn FeatureSource>> {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_str() {
+        "geojson" | "json" => Ok(Box::new(GeoJsonSource::new(&std::fs::read_to_string(
+            path,
+        )?)?)),
+        "gpkg" => Ok(Box::new(crate::geopackage::GeoPackageSource::open(
+            path, layer,
+        )?)),
+        "fgb" => Ok(Box::new(crate::flatgeobuf::FlatGeobufSource::open(path)?)),
+        "shp" => Ok(Box::new(crate::shapefile::ShapefileSource::open(path)?)),
+        _ => anyhow::bail!("Don't know how to read {path}; use .geojson, .gpkg, .fgb, or .shp"),
+    }
+}
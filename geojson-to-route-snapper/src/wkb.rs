@@ -0,0 +1,236 @@
+use anyhow::{bail, Result};
+use geo::{Coord, LineString, MultiLineString, Point};
+
+/// Parses well-known binary geometry. Only points, line-strings, and multi-line-strings are
+/// supported, and Z and M values are dropped.
+pub fn parse(bytes: &[u8]) -> Result<geo::Geometry> {
+    let mut reader = Reader { bytes, pos: 0 };
+    reader.geometry()
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn geometry(&mut self) -> Result<geo::Geometry> {
+        let (little_endian, geometry_type, dims) = self.header()?;
+        match geometry_type {
+            1 => Ok(Point::from(self.coord(little_endian, dims)?).into()),
+            2 => Ok(self.line_string(little_endian, dims)?.into()),
+            5 => {
+                let num_lines = self.count(little_endian, 9)?;
+                let mut lines = Vec::with_capacity(num_lines);
+                for _ in 0..num_lines {
+                    // Don't recurse, so deeply nested input can't overflow the stack
+                    let (little_endian, geometry_type, dims) = self.header()?;
+                    if geometry_type != 2 {
+                        bail!("MultiLineString contains something else");
+                    }
+                    lines.push(self.line_string(little_endian, dims)?);
+                }
+                Ok(MultiLineString::new(lines).into())
+            }
+            x => bail!("Unsupported WKB geometry type {x}"),
+        }
+    }
+
+    /// Returns the byte order, the 2D geometry type, and the number of values per coordinate
+    fn header(&mut self) -> Result<(bool, u32, usize)> {
+        let little_endian = match self.take(1)?[0] {
+            0 => false,
+            1 => true,
+            x => bail!("Invalid WKB byte order {x}"),
+        };
+        let raw_type = self.u32(little_endian)?;
+        // ISO WKB adds 1000 for Z, 2000 for M, and 3000 for ZM. EWKB uses high bits instead.
+        let ewkb_dims =
+            (raw_type & 0x8000_0000 != 0) as usize + (raw_type & 0x4000_0000 != 0) as usize;
+        let iso_type = raw_type & 0x0FFF_FFFF;
+        let dims = 2
+            + ewkb_dims
+            + match iso_type / 1000 {
+                1 | 2 => 1,
+                3 => 2,
+                _ => 0,
+            };
+        if raw_type & 0x2000_0000 != 0 {
+            // EWKB with an SRID
+            self.u32(little_endian)?;
+        }
+        Ok((little_endian, iso_type % 1000, dims))
+    }
+
+    fn line_string(&mut self, little_endian: bool, dims: usize) -> Result<LineString> {
+        let num_pts = self.count(little_endian, 8 * dims)?;
+        let mut pts = Vec::with_capacity(num_pts);
+        for _ in 0..num_pts {
+            pts.push(self.coord(little_endian, dims)?);
+        }
+        Ok(LineString::new(pts))
+    }
+
+    fn coord(&mut self, little_endian: bool, dims: usize) -> Result<Coord> {
+        let x = self.f64(little_endian)?;
+        let y = self.f64(little_endian)?;
+        for _ in 2..dims {
+            self.f64(little_endian)?;
+        }
+        Ok(Coord { x, y })
+    }
+
+    /// Reads the number of items that follow, checking there's room for them
+    fn count(&mut self, little_endian: bool, min_item_bytes: usize) -> Result<usize> {
+        let count = self.u32(little_endian)? as usize;
+        if count > (self.bytes.len() - self.pos) / min_item_bytes {
+            bail!("WKB geometry is truncated");
+        }
+        Ok(count)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if n > self.bytes.len() - self.pos {
+            bail!("WKB geometry is truncated");
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u32(&mut self, little_endian: bool) -> Result<u32> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+        Ok(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn f64(&mut self, little_endian: bool) -> Result<f64> {
+        let bytes: [u8; 8] = self.take(8)?.try_into().unwrap();
+        Ok(if little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_bytes(x: u32, little_endian: bool) -> [u8; 4] {
+        if little_endian {
+            x.to_le_bytes()
+        } else {
+            x.to_be_bytes()
+        }
+    }
+
+    /// A LineString with the given type code, where every coordinate has `dims` values
+    fn line_string(geometry_type: u32, little_endian: bool, dims: usize, srid: bool) -> Vec<u8> {
+        let mut bytes = vec![little_endian as u8];
+        bytes.extend(u32_bytes(geometry_type, little_endian));
+        if srid {
+            bytes.extend(u32_bytes(4326, little_endian));
+        }
+        bytes.extend(u32_bytes(2, little_endian));
+        for pt in [[1.0, 2.0], [3.0, 4.0]] {
+            for value in [pt[0], pt[1], 99.0, 99.0][..dims].iter() {
+                bytes.extend(if little_endian {
+                    f64::to_le_bytes(*value)
+                } else {
+                    f64::to_be_bytes(*value)
+                });
+            }
+        }
+        bytes
+    }
+
+    fn expected() -> LineString {
+        LineString::from(vec![(1.0, 2.0), (3.0, 4.0)])
+    }
+
+    #[test]
+    fn test_dimensions() {
+        for little_endian in [true, false] {
+            for (geometry_type, dims, srid) in [
+                (2, 2, false),
+                // ISO Z, M, and ZM
+                (1002, 3, false),
+                (2002, 3, false),
+                (3002, 4, false),
+                // EWKB Z, M, and ZM, with and without an SRID
+                (0x8000_0002, 3, false),
+                (0x4000_0002, 3, false),
+                (0xC000_0002, 4, false),
+                (0xA000_0002, 3, true),
+                (0x2000_0002, 2, true),
+            ] {
+                let bytes = line_string(geometry_type, little_endian, dims, srid);
+                assert_eq!(
+                    parse(&bytes).unwrap(),
+                    geo::Geometry::LineString(expected()),
+                    "type {geometry_type:#x}, little endian {little_endian}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_geometry_types() {
+        let mut point = vec![1];
+        point.extend(1001_u32.to_le_bytes());
+        for value in [1.0_f64, 2.0, 3.0] {
+            point.extend(value.to_le_bytes());
+        }
+        assert_eq!(
+            parse(&point).unwrap(),
+            geo::Geometry::Point(Point::new(1.0, 2.0))
+        );
+
+        // The parts can have different byte orders
+        let mut multi = vec![0];
+        multi.extend(5_u32.to_be_bytes());
+        multi.extend(2_u32.to_be_bytes());
+        multi.extend(line_string(2, true, 2, false));
+        multi.extend(line_string(1002, false, 3, false));
+        assert_eq!(
+            parse(&multi).unwrap(),
+            geo::Geometry::MultiLineString(MultiLineString::new(vec![expected(), expected()]))
+        );
+
+        let mut polygon = vec![1];
+        polygon.extend(3_u32.to_le_bytes());
+        polygon.extend(0_u32.to_le_bytes());
+        assert!(parse(&polygon).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let bytes = line_string(2, true, 2, false);
+        for len in 0..bytes.len() {
+            assert!(parse(&bytes[..len]).is_err());
+        }
+
+        // A huge count fails without trying to allocate
+        let mut huge = bytes[..5].to_vec();
+        huge.extend(u32::MAX.to_le_bytes());
+        assert!(parse(&huge).unwrap_err().to_string().contains("truncated"));
+
+        // A MultiLineString inside a MultiLineString
+        let mut nested = vec![1];
+        nested.extend(5_u32.to_le_bytes());
+        nested.extend(1_u32.to_le_bytes());
+        nested.extend([1]);
+        nested.extend(5_u32.to_le_bytes());
+        nested.extend(0_u32.to_le_bytes());
+        assert!(parse(&nested).is_err());
+
+        let mut bad_order = bytes.clone();
+        bad_order[0] = 7;
+        assert!(parse(&bad_order).is_err());
+    }
+}
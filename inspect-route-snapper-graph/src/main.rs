@@ -0,0 +1,68 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use route_snapper_graph::{export::to_geojson, validate::validate, RouteSnapperMap};
+
+/// Prints statistics about a graph file and checks it for problems. Exits with 1 if there are
+/// problems, or 2 if the file can't be read at all.
+#[derive(Parser)]
+struct Args {
+    /// Path to a .bin graph file to check
+    #[arg(long)]
+    input: String,
+
+    /// Also treat the graph as broken if it has more than this many connected components
+    #[arg(long)]
+    max_components: Option<usize>,
+
+    /// Also write the graph to this path as GeoJSON, which geojson-to-route-snapper can turn back
+    /// into the same graph after editing
+    #[arg(long)]
+    geojson: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let map = match std::fs::read(&args.input)
+        .map_err(|err| err.into())
+        .and_then(|bytes| RouteSnapperMap::from_bytes(&bytes))
+    {
+        Ok(map) => map,
+        Err(err) => {
+            eprintln!("Couldn't load {}: {err}", args.input);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Some(path) = &args.geojson {
+        if let Err(err) = std::fs::write(path, to_geojson(&map).to_string()) {
+            eprintln!("Couldn't write {path}: {err}");
+            return ExitCode::from(2);
+        }
+        if map.contraction_hierarchy.is_some() {
+            eprintln!(
+                "The contraction hierarchy isn't exported. Pass --contraction-hierarchy to \
+                 geojson-to-route-snapper to build it again."
+            );
+        }
+    }
+
+    let report = validate(&map);
+    print!("{report}");
+
+    let mut ok = !report.has_problems();
+    if let Some(max) = args.max_components {
+        if report.component_sizes.len() > max {
+            println!(
+                "Problem: {} connected components, but at most {max} are allowed",
+                report.component_sizes.len()
+            );
+            ok = false;
+        }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
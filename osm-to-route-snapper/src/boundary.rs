@@ -0,0 +1,149 @@
+use anyhow::{bail, Result};
+use geo::{BooleanOps, MultiPolygon};
+use geojson::{Feature, GeoJson};
+
+/// Reads the area to clip to from a GeoJSON Geometry, Feature, or FeatureCollection. Every
+/// Polygon and MultiPolygon is combined, and holes are kept. If `filter` is set, only features
+/// with that property key and value are used.
+pub fn parse_boundary(gj_string: &str, filter: Option<&(String, String)>) -> Result<MultiPolygon> {
+    let features = match gj_string.parse::<GeoJson>()? {
+        GeoJson::Geometry(geometry) => vec![Feature::from(geometry)],
+        GeoJson::Feature(feature) => vec![feature],
+        GeoJson::FeatureCollection(fc) => fc.features,
+    };
+
+    let mut polygons = Vec::new();
+    for feature in features {
+        if let Some((key, value)) = filter {
+            let actual = match feature.property(key) {
+                Some(serde_json::Value::String(x)) => x.clone(),
+                Some(x) => x.to_string(),
+                None => continue,
+            };
+            if actual != *value {
+                continue;
+            }
+        }
+        let Some(geometry) = feature.geometry else {
+            continue;
+        };
+        match geo::Geometry::try_from(geometry)? {
+            geo::Geometry::Polygon(p) => polygons.push(MultiPolygon::new(vec![p])),
+            geo::Geometry::MultiPolygon(mp) => polygons.push(mp),
+            _ => bail!("The boundary must only contain Polygons and MultiPolygons"),
+        }
+    }
+
+    let Some(mut boundary) = polygons.pop() else {
+        if let Some((key, value)) = filter {
+            bail!("No boundary feature has {key}={value}");
+        }
+        bail!("The boundary has no polygons");
+    };
+    // Overlapping polygons would otherwise have boundaries inside the area
+    for polygon in polygons {
+        boundary = boundary.union(&polygon);
+    }
+    Ok(boundary)
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::Area;
+    use serde_json::json;
+
+    use super::*;
+
+    // A square from (x, y) with sides of 1
+    fn square(x: f64, y: f64) -> serde_json::Value {
+        json!([[
+            [x, y],
+            [x + 1.0, y],
+            [x + 1.0, y + 1.0],
+            [x, y + 1.0],
+            [x, y]
+        ]])
+    }
+
+    fn feature(geometry: serde_json::Value, name: &str) -> serde_json::Value {
+        json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": { "name": name },
+        })
+    }
+
+    fn area(gj: serde_json::Value, filter: Option<(&str, &str)>) -> f64 {
+        let filter = filter.map(|(k, v)| (k.to_string(), v.to_string()));
+        parse_boundary(&gj.to_string(), filter.as_ref())
+            .unwrap()
+            .unsigned_area()
+    }
+
+    #[test]
+    fn test_shapes() {
+        let polygon = json!({ "type": "Polygon", "coordinates": square(0.0, 0.0) });
+        assert_eq!(area(polygon, None), 1.0);
+
+        let multi_polygon = json!({
+            "type": "MultiPolygon",
+            "coordinates": [square(0.0, 0.0), square(5.0, 0.0)],
+        });
+        assert_eq!(area(multi_polygon, None), 2.0);
+
+        // Overlapping features are combined without counting the overlap twice
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [
+                feature(json!({ "type": "Polygon", "coordinates": square(0.0, 0.0) }), "a"),
+                feature(json!({ "type": "Polygon", "coordinates": square(0.5, 0.0) }), "b"),
+            ],
+        });
+        assert_eq!(area(collection, None), 1.5);
+    }
+
+    #[test]
+    fn test_filter() {
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [
+                feature(json!({ "type": "Polygon", "coordinates": square(0.0, 0.0) }), "small"),
+                feature(
+                    json!({
+                        "type": "MultiPolygon",
+                        "coordinates": [square(5.0, 0.0), square(7.0, 0.0)],
+                    }),
+                    "big",
+                ),
+                // Features that don't match aren't checked
+                feature(json!({ "type": "Point", "coordinates": [0.0, 0.0] }), "point"),
+            ],
+        });
+        assert_eq!(area(collection.clone(), Some(("name", "small"))), 1.0);
+        assert_eq!(area(collection.clone(), Some(("name", "big"))), 2.0);
+
+        let filter = ("name".to_string(), "nowhere".to_string());
+        let err = parse_boundary(&collection.to_string(), Some(&filter))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "No boundary feature has name=nowhere");
+    }
+
+    #[test]
+    fn test_not_polygons() {
+        let line = json!({ "type": "LineString", "coordinates": [[0.0, 0.0], [1.0, 1.0]] });
+        let err = parse_boundary(&line.to_string(), None)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "The boundary must only contain Polygons and MultiPolygons"
+        );
+
+        let empty = json!({ "type": "FeatureCollection", "features": [] });
+        let err = parse_boundary(&empty.to_string(), None)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "The boundary has no polygons");
+    }
+}
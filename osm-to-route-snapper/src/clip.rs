@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geo::{
+    Contains, Coord, EuclideanLength, HaversineLength, Intersects, Line, LineString, MultiPolygon,
+};
+use log::{debug, info};
+
+use route_snapper_graph::{Edge, EdgeID, NodeID, RouteSnapperMap};
+
+/// Clips edges crossing the boundary. Each piece inside the boundary is kept as its own edge, with
+/// new nodes where it crosses the boundary. Existing nodes never move. Edges totally outside the
+/// boundary are mostly skipped earlier during `split_edges`; any left are removed.
+pub fn clip(map: &mut RouteSnapperMap, boundary: &MultiPolygon) {
+    let mut remove = HashSet::new();
+    let mut new_nodes = 0;
+    let mut new_edges = 0;
+
+    for idx in 0..map.edges.len() {
+        let edge_id = EdgeID(idx as u32);
+        if boundary.contains(&map.edges[idx].geometry) {
+            continue;
+        }
+        let pieces = split_at_boundary(&map.edges[idx].geometry, boundary);
+        if pieces.is_empty() {
+            remove.insert(edge_id);
+            continue;
+        }
+
+        let old = &map.edges[idx];
+        let (old_node1, old_node2) = (old.node1, old.node2);
+        let (old_start, old_end) = (map.node(old_node1), map.node(old_node2));
+        let old_length = old.geometry.haversine_length();
+        let name = old.name.clone();
+        let costs: Vec<[Option<f64>; 2]> = map
+            .cost_tables_mut()
+            .map(|(forward_costs, backward_costs)| {
+                [
+                    forward_costs.get(idx).copied().flatten(),
+                    backward_costs.get(idx).copied().flatten(),
+                ]
+            })
+            .collect();
+        let attributes: Vec<(String, String)> = map
+            .attributes
+            .get(edge_id)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        debug!(
+            "Clipping {name:?} into {} pieces, from {old_length}m to {}m",
+            pieces.len(),
+            pieces.iter().map(|x| x.haversine_length()).sum::<f64>()
+        );
+
+        let mut piece_ids = Vec::new();
+        // The edge might leave and come back through the same point, so pieces ending there need
+        // to share a node
+        let mut nodes_at = vec![(old_start, old_node1), (old_end, old_node2)];
+        for (piece_idx, geometry) in pieces.into_iter().enumerate() {
+            let mut endpoint = |pt: Coord| {
+                if let Some((_, node)) = nodes_at.iter().find(|(at, _)| *at == pt) {
+                    return *node;
+                }
+                map.nodes.push(pt);
+                if !map.osm_node_ids.is_empty() {
+                    map.osm_node_ids.push(None);
+                }
+                new_nodes += 1;
+                let node = NodeID(map.nodes.len() as u32 - 1);
+                nodes_at.push((pt, node));
+                node
+            };
+            let node1 = endpoint(geometry.0[0]);
+            let node2 = endpoint(*geometry.0.last().unwrap());
+
+            // Costs are proportional to length
+            let ratio = geometry.haversine_length() / old_length;
+
+            let piece_id = if piece_idx == 0 {
+                let edge = &mut map.edges[idx];
+                edge.geometry = geometry;
+                edge.node1 = node1;
+                edge.node2 = node2;
+                edge_id
+            } else {
+                map.edges.push(Edge {
+                    node1,
+                    node2,
+                    geometry,
+                    name: name.clone(),
+
+                    length_meters: 0.0,
+                    forward_cost: None,
+                    backward_cost: None,
+                });
+                new_edges += 1;
+                let piece_id = EdgeID(map.edges.len() as u32 - 1);
+                if !map.osm_way_ids.is_empty() {
+                    map.osm_way_ids.push(map.osm_way_ids[idx]);
+                }
+                for (key, value) in &attributes {
+                    map.attributes.set(piece_id, key, value);
+                }
+                piece_id
+            };
+            for ((forward_costs, backward_costs), [forward, backward]) in
+                map.cost_tables_mut().zip(&costs)
+            {
+                if !forward_costs.is_empty() {
+                    set_or_push(forward_costs, piece_id, forward.map(|c| c * ratio));
+                    set_or_push(backward_costs, piece_id, backward.map(|c| c * ratio));
+                }
+            }
+            piece_ids.push((piece_id, node1, node2));
+        }
+
+        // Turns through the original endpoints now involve whichever piece still touches them
+        for turn in &mut map.turns {
+            for id in [&mut turn.from, &mut turn.to] {
+                if *id == edge_id {
+                    if let Some((piece_id, _, _)) = piece_ids
+                        .iter()
+                        .find(|(_, n1, n2)| *n1 == turn.via || *n2 == turn.via)
+                    {
+                        *id = *piece_id;
+                    }
+                }
+            }
+        }
+    }
+
+    info!(
+        "Clipping added {new_nodes} nodes and {new_edges} edges, and removed {} edges",
+        remove.len()
+    );
+    // This also cleans up nodes that were only used by the parts outside the boundary
+    map.remove_edges(&remove);
+}
+
+fn set_or_push(costs: &mut Vec<Option<f64>>, id: EdgeID, cost: Option<f64>) {
+    let idx = id.0 as usize;
+    if idx < costs.len() {
+        costs[idx] = cost;
+    } else {
+        costs.push(cost);
+    }
+}
+
+/// Returns the parts of a line-string inside the boundary, in order.
+fn split_at_boundary(linestring: &LineString, boundary: &MultiPolygon) -> Vec<LineString> {
+    let rings: Vec<Line> = boundary
+        .iter()
+        .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()))
+        .flat_map(|ring| ring.lines())
+        .collect();
+
+    let mut pieces = Vec::new();
+    let mut current: Vec<Coord> = Vec::new();
+    for segment in linestring.lines() {
+        let length = segment.euclidean_length();
+        let mut cuts: Vec<(f64, Coord)> = rings
+            .iter()
+            .filter_map(|ring_line| match line_intersection(segment, *ring_line)? {
+                LineIntersection::SinglePoint { intersection, .. } => Some(intersection),
+                // Both endpoints of the overlap are also crossings of some other ring line, or the
+                // segment runs along the boundary, which counts as inside
+                LineIntersection::Collinear { .. } => None,
+            })
+            .map(|pt| {
+                let t = if length == 0.0 {
+                    0.0
+                } else {
+                    Line::new(segment.start, pt).euclidean_length() / length
+                };
+                (t, pt)
+            })
+            .collect();
+        cuts.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut pts = vec![segment.start];
+        pts.extend(cuts.into_iter().map(|(_, pt)| pt));
+        pts.push(segment.end);
+        pts.dedup();
+
+        for pair in pts.windows(2) {
+            let midpoint = Coord {
+                x: (pair[0].x + pair[1].x) / 2.0,
+                y: (pair[0].y + pair[1].y) / 2.0,
+            };
+            if boundary.intersects(&midpoint) {
+                if current.is_empty() {
+                    current.push(pair[0]);
+                }
+                current.push(pair[1]);
+            } else if current.len() >= 2 {
+                pieces.push(LineString::new(std::mem::take(&mut current)));
+            } else {
+                current.clear();
+            }
+        }
+    }
+    if current.len() >= 2 {
+        pieces.push(LineString::new(current));
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{polygon, HaversineLength};
+    use route_snapper_graph::test_util::make_map;
+    use route_snapper_graph::{CostProfile, Turn};
+
+    use super::*;
+
+    fn boundary() -> MultiPolygon {
+        MultiPolygon::new(vec![polygon![
+            (x: 0.0, y: 0.0),
+            (x: 0.01, y: 0.0),
+            (x: 0.01, y: 0.01),
+            (x: 0.0, y: 0.01),
+        ]])
+    }
+
+    fn pt(x: f64, y: f64) -> Coord {
+        Coord { x, y }
+    }
+
+    #[test]
+    fn test_multiple_crossings() {
+        // Goes out of the east side and back in
+        let line = LineString::new(vec![
+            pt(0.005, 0.002),
+            pt(0.015, 0.002),
+            pt(0.015, 0.008),
+            pt(0.005, 0.008),
+        ]);
+        let pieces = split_at_boundary(&line, &boundary());
+        assert_eq!(
+            pieces,
+            vec![
+                LineString::new(vec![pt(0.005, 0.002), pt(0.01, 0.002)]),
+                LineString::new(vec![pt(0.01, 0.008), pt(0.005, 0.008)]),
+            ]
+        );
+
+        let mut map = make_map(vec![pt(0.005, 0.002), pt(0.005, 0.008)], &[(0, 1)]);
+        map.edges[0].geometry = line;
+        clip(&mut map, &boundary());
+        assert_eq!(map.edges.len(), 2);
+        assert_eq!(map.nodes.len(), 4);
+        assert_eq!(
+            (map.edges[0].node1, map.edges[1].node2),
+            (NodeID(0), NodeID(1))
+        );
+        assert_eq!(map.node(map.edges[0].node2), pt(0.01, 0.002));
+        assert_eq!(map.node(map.edges[1].node1), pt(0.01, 0.008));
+    }
+
+    #[test]
+    fn test_same_crossing_twice() {
+        // Leaves and comes back through the same point on the east side
+        let line = LineString::new(vec![
+            pt(0.005, 0.005),
+            pt(0.01, 0.005),
+            pt(0.015, 0.008),
+            pt(0.015, 0.002),
+            pt(0.01, 0.005),
+            pt(0.005, 0.006),
+        ]);
+        let mut map = make_map(vec![pt(0.005, 0.005), pt(0.005, 0.006)], &[(0, 1)]);
+        map.edges[0].geometry = line;
+        clip(&mut map, &boundary());
+        assert_eq!(map.edges.len(), 2);
+        assert_eq!(map.nodes.len(), 3);
+        assert_eq!(map.edges[0].node2, map.edges[1].node1);
+        assert_eq!(map.node(map.edges[0].node2), pt(0.01, 0.005));
+    }
+
+    #[test]
+    fn test_shared_node_stays_put() {
+        // An edge inside the boundary, then one leaving it, then one totally outside
+        let mut map = make_map(
+            vec![
+                pt(0.002, 0.005),
+                pt(0.008, 0.005),
+                pt(0.012, 0.005),
+                pt(0.015, 0.005),
+            ],
+            &[(0, 1), (1, 2), (2, 3)],
+        );
+        clip(&mut map, &boundary());
+        assert_eq!(map.edges.len(), 2);
+        assert_eq!(
+            map.nodes,
+            vec![pt(0.002, 0.005), pt(0.008, 0.005), pt(0.01, 0.005)]
+        );
+        assert_eq!(map.edges[0].node2, NodeID(1));
+        assert_eq!(map.edges[1].node1, NodeID(1));
+        assert_eq!(map.edges[1].node2, NodeID(2));
+    }
+
+    #[test]
+    fn test_turns_and_costs() {
+        // One edge crosses out of the boundary and back in. The other is inside, meeting it at
+        // its start.
+        let mut map = make_map(
+            vec![pt(0.005, 0.002), pt(0.005, 0.008), pt(0.002, 0.002)],
+            &[(0, 1), (2, 0)],
+        );
+        map.edges[0].geometry = LineString::new(vec![
+            pt(0.005, 0.002),
+            pt(0.015, 0.002),
+            pt(0.015, 0.008),
+            pt(0.005, 0.008),
+        ]);
+        let old_length = map.edges[0].geometry.haversine_length();
+        map.override_forward_costs = vec![Some(100.0), Some(1.0)];
+        map.override_backward_costs = vec![None, Some(1.0)];
+        map.cost_profiles.push(CostProfile {
+            name: "reverse".to_string(),
+            forward_costs: vec![None, Some(2.0)],
+            backward_costs: vec![Some(50.0), Some(2.0)],
+        });
+        for (from, via, to) in [(1, 0, 0), (0, 1, 0)] {
+            map.turns.push(Turn {
+                from: EdgeID(from),
+                via: NodeID(via),
+                to: EdgeID(to),
+                cost: Some(5.0),
+            });
+        }
+        clip(&mut map, &boundary());
+
+        // The first piece keeps the ID and touches the original start. The second is new.
+        assert_eq!(map.edges.len(), 3);
+        assert_eq!(
+            (map.turns[0].from, map.turns[0].via, map.turns[0].to),
+            (EdgeID(1), NodeID(0), EdgeID(0))
+        );
+        assert_eq!(
+            (map.turns[1].from, map.turns[1].via, map.turns[1].to),
+            (EdgeID(2), NodeID(1), EdgeID(2))
+        );
+        assert_eq!(map.turns[0].cost, Some(5.0));
+
+        // Costs are split by length, keeping directions that aren't routable
+        for piece in [0, 2] {
+            let ratio = map.edges[piece].geometry.haversine_length() / old_length;
+            assert!((map.override_forward_costs[piece].unwrap() - 100.0 * ratio).abs() < 1e-9);
+            assert_eq!(map.override_backward_costs[piece], None);
+            let profile = &map.cost_profiles[0];
+            assert_eq!(profile.forward_costs[piece], None);
+            assert!((profile.backward_costs[piece].unwrap() - 50.0 * ratio).abs() < 1e-9);
+        }
+        assert_eq!(map.override_forward_costs[1], Some(1.0));
+        assert_eq!(map.cost_profiles[0].forward_costs[1], Some(2.0));
+    }
+
+    #[test]
+    fn test_along_boundary() {
+        // Runs along the south side of the boundary, which counts as inside
+        let line = LineString::new(vec![pt(0.002, 0.0), pt(0.008, 0.0)]);
+        assert_eq!(split_at_boundary(&line, &boundary()), vec![line.clone()]);
+
+        // Comes in from outside, follows the boundary, then goes out again
+        let line = LineString::new(vec![
+            pt(0.002, -0.005),
+            pt(0.002, 0.0),
+            pt(0.008, 0.0),
+            pt(0.008, -0.005),
+        ]);
+        assert_eq!(
+            split_at_boundary(&line, &boundary()),
+            vec![LineString::new(vec![pt(0.002, 0.0), pt(0.008, 0.0)])]
+        );
+    }
+}
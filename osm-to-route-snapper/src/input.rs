@@ -0,0 +1,31 @@
+use anyhow::Result;
+use osm_reader::Element;
+
+/// Where OSM data comes from. The importer parses it twice, so files are read again from the
+/// start for each pass, rather than held in memory.
+pub enum OsmInput {
+    Bytes(Vec<u8>),
+    #[cfg(not(target_arch = "wasm32"))]
+    File(std::path::PathBuf),
+}
+
+impl OsmInput {
+    /// Calls `callback` with every element in the input.
+    pub fn parse<F: FnMut(Element)>(&self, mut callback: F) -> Result<()> {
+        match self {
+            OsmInput::Bytes(bytes) => osm_reader::parse(bytes, callback),
+            #[cfg(not(target_arch = "wasm32"))]
+            OsmInput::File(path) => {
+                let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+                let start = std::io::BufRead::fill_buf(&mut file)?;
+                // XML can't be split up, but it's only practical for small inputs anyway
+                if start.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<') {
+                    return osm_reader::parse(&std::fs::read(path)?, callback);
+                }
+                crate::pbf::for_each_batch(file, crate::pbf::BATCH_BYTES, |batch| {
+                    osm_reader::parse(batch, &mut callback)
+                })
+            }
+        }
+    }
+}
@@ -1,124 +1,385 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use anyhow::Result;
-use geo::{
-    BooleanOps, Contains, Coord, HaversineLength, Intersects, LineString, MultiLineString, Polygon,
+use geo::{Coord, HaversineBearing, HaversineLength, Intersects, LineString, MultiPolygon, Point};
+use log::{debug, info, warn};
+use osm_reader::{Element, OsmID, WayID};
+
+use route_snapper_graph::{
+    components, dem, AttributeTable, CostProfile, Edge, EdgeID, NodeID, RouteSnapperMap, Turn,
 };
-use log::{debug, info};
-use osm_reader::{Element, WayID};
 
-use route_snapper_graph::{Edge, NodeID, RouteSnapperMap};
+use input::OsmInput;
+use node_table::NodeTable;
+pub use profile::Profile;
+
+mod boundary;
+mod clip;
+mod input;
+mod name;
+mod node_table;
+#[cfg(not(target_arch = "wasm32"))]
+mod pbf;
+mod profile;
+mod progress;
+mod speed;
+
+/// Controls what's imported from OSM.
+pub struct Options {
+    /// Keep road names in the output
+    pub road_names: bool,
+    /// Prefer `name:xx` tags in these languages, in order, over `name`
+    pub name_languages: Vec<String>,
+    /// The values of these OSM tags are kept as edge attributes
+    pub attribute_tags: Vec<String>,
+    /// Import restriction relations as forbidden turns
+    pub turn_restrictions: bool,
+    /// Added to the cost of turning around onto the same edge, in the same units as edge costs
+    pub u_turn_cost: f64,
+    /// If set, turning more than 45 degrees onto another edge costs this much extra, in the same
+    /// units as edge costs, so routes prefer to keep going straight
+    pub turn_cost: Option<f64>,
+    /// Which ways are routable, and the default costs
+    pub profile: Profile,
+    /// Costs for these profiles are also stored in the graph as named cost profiles, so the route
+    /// snapper can switch between them. Ways routable by any profile are kept, and directions a
+    /// profile can't use have no cost in it.
+    pub cost_profiles: Vec<(String, Profile)>,
+    /// Make one-way streets unroutable in the wrong direction
+    pub oneways: bool,
+    /// Use the estimated travel time in seconds as the cost, instead of distance
+    pub travel_time: bool,
+    /// Remember the OSM way behind every edge and the OSM node behind every node
+    pub osm_ids: bool,
+    /// The contents of a GeoTIFF or ESRI ASCII grid file, to sample node heights from
+    pub dem: Option<Vec<u8>>,
+    /// If set, uphill directions cost more. See `route_snapper_graph::dem::apply_grade_costs`.
+    pub grade_penalty: Option<f64>,
+    /// If set, parts of the graph with fewer edges than this that aren't connected to the largest
+    /// part are removed. Otherwise they're only reported.
+    pub min_component_edges: Option<usize>,
+    /// If the boundary has many features, only use ones with this property key and value
+    pub boundary_filter: Option<(String, String)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            road_names: true,
+            name_languages: Vec::new(),
+            attribute_tags: Vec::new(),
+            turn_restrictions: false,
+            u_turn_cost: 0.0,
+            turn_cost: None,
+            profile: Profile::all(),
+            cost_profiles: Vec::new(),
+            oneways: true,
+            travel_time: false,
+            osm_ids: false,
+            dem: None,
+            grade_penalty: None,
+            min_component_edges: None,
+            boundary_filter: None,
+        }
+    }
+}
 
-/// Convert input OSM PBF or XML data into a RouteSnapperMap, extracting all highway center-lines.
-/// If a boundary polygon is specified, clips roads to this boundary.
+/// Convert input OSM PBF or XML data into a RouteSnapperMap, extracting highway center-lines
+/// routable by the chosen profile. If a boundary is specified as any GeoJSON with polygons, clips
+/// roads to this boundary.
+///
+/// The input is read twice, first for ways and then for only the nodes they use, so memory use
+/// depends on the size of the road network, not the whole input.
 pub fn convert_osm(
     input_bytes: Vec<u8>,
     boundary_gj: Option<String>,
-    road_names: bool,
+    options: Options,
 ) -> Result<RouteSnapperMap> {
+    build_graph(OsmInput::Bytes(input_bytes), boundary_gj, options)
+}
+
+/// Like `convert_osm`, but reads a file. PBF files are streamed, a few blobs at a time, so the
+/// whole input never has to fit in memory.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn convert_osm_file(
+    path: &str,
+    boundary_gj: Option<String>,
+    options: Options,
+) -> Result<RouteSnapperMap> {
+    build_graph(OsmInput::File(path.into()), boundary_gj, options)
+}
+
+fn build_graph(
+    input: OsmInput,
+    boundary_gj: Option<String>,
+    options: Options,
+) -> Result<RouteSnapperMap> {
+    let boundary = boundary_gj
+        .map(|gj| boundary::parse_boundary(&gj, options.boundary_filter.as_ref()))
+        .transpose()?;
+
     info!("Scraping OSM data");
-    let (nodes, ways) = scrape_elements(&input_bytes, road_names)?;
+    let (nodes, ways, mut restrictions) = scrape_elements(&input, &options)?;
+    drop(input);
+    if !options.turn_restrictions {
+        restrictions.clear();
+    }
     info!(
-        "Got {} nodes and {} ways. Splitting into edges",
+        "Got {} nodes ({}), {} ways, and {} turn restrictions. Splitting into edges",
         nodes.len(),
+        progress::describe_bytes(nodes.bytes()),
         ways.len(),
+        restrictions.len(),
     );
 
-    let mut boundary = None;
-    if let Some(gj_string) = boundary_gj {
-        let gj: geojson::Feature = gj_string.parse()?;
-        let boundary_geo: Polygon = gj.try_into()?;
-        boundary = Some(boundary_geo);
+    let mut map = split_edges(nodes, ways, &restrictions, boundary.as_ref(), &options);
+    map.u_turn_cost = options.u_turn_cost;
+    if let Some(cost) = options.turn_cost {
+        let added = add_turn_costs(&mut map, cost);
+        info!("Added a cost to {added} turns");
     }
-
-    let mut map = split_edges(nodes, ways, boundary.as_ref());
     if let Some(boundary) = boundary {
-        clip(&mut map, boundary);
+        clip::clip(&mut map, &boundary);
+    }
+    prune_components(&mut map, options.min_component_edges);
+    if let Some(dem) = options.dem {
+        info!("Sampling elevation");
+        let missing = dem::add_elevation(&mut map, &dem, options.grade_penalty)?;
+        if missing > 0 {
+            warn!("{missing} nodes have no elevation data in the DEM");
+        }
+    }
+    if let Some(bytes) = progress::peak_memory_bytes() {
+        info!("Peak memory use: {}", progress::describe_bytes(bytes));
     }
     Ok(map)
 }
 
+fn prune_components(map: &mut RouteSnapperMap, min_edges: Option<usize>) {
+    if let Some(min_edges) = min_edges {
+        let removed = components::prune_components(map, min_edges);
+        if removed.is_empty() {
+            return;
+        }
+        info!(
+            "Removed {} edges in {} disconnected parts with fewer than {min_edges} edges",
+            removed.iter().map(|c| c.edges.len()).sum::<usize>(),
+            removed.len()
+        );
+        for component in removed.iter().take(20) {
+            info!("  {component}");
+        }
+        if removed.len() > 20 {
+            info!("  ... and {} more", removed.len() - 20);
+        }
+    } else {
+        let disconnected = components::find_components(map).len().saturating_sub(1);
+        if disconnected > 0 {
+            warn!("{disconnected} parts of the graph aren't connected to the largest part");
+        }
+    }
+}
+
 struct Way {
     name: Option<String>,
     nodes: Vec<osm_reader::NodeID>,
+    attributes: Vec<(String, String)>,
+    /// For the default profile and then each cost profile, can the way be followed (forwards,
+    /// backwards) in the order of its nodes?
+    directions: Vec<(bool, bool)>,
+    /// Not capped by any profile's speed
+    speed_kmh: f64,
 }
 
-fn scrape_elements(
-    input_bytes: &[u8],
-    road_names: bool,
-) -> Result<(HashMap<osm_reader::NodeID, Coord>, HashMap<WayID, Way>)> {
-    // Scrape every node ID -> Coord
-    let mut nodes = HashMap::new();
+/// A `type=restriction` relation going from one way to another through a node
+struct Restriction {
+    from: WayID,
+    via: osm_reader::NodeID,
+    to: WayID,
+    /// `only_*` restrictions forbid every other turn. `no_*` restrictions just forbid this one.
+    only: bool,
+}
+
+type ScrapedElements = (NodeTable, HashMap<WayID, Way>, Vec<Restriction>);
+
+fn scrape_elements(input: &OsmInput, options: &Options) -> Result<ScrapedElements> {
     // Scrape every routable road
     let mut ways = HashMap::new();
+    let mut restrictions = Vec::new();
+
+    let mut progress = progress::Progress::new("Pass 1 of 2: reading ways and relations");
+    input.parse(|elem| {
+        progress.tick();
+        scrape_way_or_relation(elem, options, &mut ways, &mut restrictions);
+    })?;
+    progress.done();
+
+    // Only keep coordinates of nodes used by those roads
+    let mut nodes = NodeTable::new(ways.values().map(|way: &Way| way.nodes.as_slice()));
+    let mut progress = progress::Progress::new("Pass 2 of 2: reading nodes");
+    input.parse(|elem| {
+        progress.tick();
+        if let Element::Node { id, lon, lat, .. } = elem {
+            nodes.set(id, Coord { x: lon, y: lat });
+        }
+    })?;
+    progress.done();
 
-    osm_reader::parse(input_bytes, |elem| match elem {
-        Element::Node { id, lon, lat, .. } => {
-            nodes.insert(id, Coord { x: lon, y: lat });
+    // Extracts can cut ways off partway, leaving references to nodes that aren't included
+    let missing = nodes.num_missing();
+    if missing > 0 {
+        warn!("{missing} nodes used by ways are missing from the input; ways will skip them");
+        for way in ways.values_mut() {
+            way.nodes.retain(|node| nodes.get(*node).is_some());
         }
+        ways.retain(|_, way| way.nodes.len() >= 2);
+    }
+
+    Ok((nodes, ways, restrictions))
+}
+
+fn scrape_way_or_relation(
+    elem: Element,
+    options: &Options,
+    ways: &mut HashMap<WayID, Way>,
+    restrictions: &mut Vec<Restriction>,
+) {
+    match elem {
+        Element::Node { .. } => {}
         Element::Way { id, node_ids, tags } => {
-            if tags.contains_key("highway") {
-                // TODO When the name is missing, we could fallback on other OSM tags. See
-                // map_model::Road::get_name in A/B Street.
-                let name = if road_names {
-                    tags.get("name").map(|x| x.to_string())
+            let directions: Vec<(bool, bool)> = profiles(options)
+                .map(|profile| {
+                    if !profile.is_routable(&tags) {
+                        (false, false)
+                    } else if options.oneways {
+                        profile.directions(&tags)
+                    } else {
+                        (true, true)
+                    }
+                })
+                .collect();
+            if directions
+                .iter()
+                .any(|(forwards, backwards)| *forwards || *backwards)
+            {
+                let name = if options.road_names {
+                    name::way_name(&tags, &options.name_languages)
                 } else {
                     None
                 };
+                let attributes = options
+                    .attribute_tags
+                    .iter()
+                    .filter_map(|key| Some((key.clone(), tags.get(key)?.to_string())))
+                    .collect();
                 ways.insert(
                     id,
                     Way {
                         name,
                         nodes: node_ids,
+                        attributes,
+                        directions,
+                        speed_kmh: speed::speed_kmh(&tags),
                     },
                 );
             }
         }
-        Element::Relation { .. } => {}
-    })?;
+        Element::Relation { tags, members, .. } => {
+            if tags.get("type").map(|x| x.as_str()) != Some("restriction") {
+                return;
+            }
+            let Some(restriction) = tags.get("restriction") else {
+                return;
+            };
+            let only = if restriction.starts_with("only_") {
+                true
+            } else if restriction.starts_with("no_") {
+                false
+            } else {
+                return;
+            };
 
-    Ok((nodes, ways))
+            let (mut from, mut via, mut to) = (None, None, None);
+            for (role, member) in members {
+                match (role.as_str(), member) {
+                    ("from", OsmID::Way(id)) => from = Some(id),
+                    ("via", OsmID::Node(id)) => via = Some(id),
+                    ("to", OsmID::Way(id)) => to = Some(id),
+                    // Restrictions via a way are much harder to represent
+                    _ => {}
+                }
+            }
+            if let (Some(from), Some(via), Some(to)) = (from, via, to) {
+                restrictions.push(Restriction {
+                    from,
+                    via,
+                    to,
+                    only,
+                });
+            }
+        }
+    }
+}
+
+// The default profile, then each cost profile
+fn profiles(options: &Options) -> impl Iterator<Item = &Profile> {
+    std::iter::once(&options.profile).chain(options.cost_profiles.iter().map(|(_, p)| p))
 }
 
 fn split_edges(
-    nodes: HashMap<osm_reader::NodeID, Coord>,
+    nodes: NodeTable,
     ways: HashMap<WayID, Way>,
-    boundary: Option<&Polygon>,
+    restrictions: &[Restriction],
+    boundary: Option<&MultiPolygon>,
+    options: &Options,
 ) -> RouteSnapperMap {
+    let (travel_time, osm_ids) = (options.travel_time, options.osm_ids);
+    let max_speeds_kmh: Vec<Option<f64>> = profiles(options).map(|p| p.max_speed_kmh).collect();
     let mut map = RouteSnapperMap {
         nodes: Vec::new(),
         edges: Vec::new(),
         override_forward_costs: Vec::new(),
         override_backward_costs: Vec::new(),
+        attributes: AttributeTable::default(),
+        cost_profiles: options
+            .cost_profiles
+            .iter()
+            .map(|(name, _)| CostProfile {
+                name: name.clone(),
+                forward_costs: Vec::new(),
+                backward_costs: Vec::new(),
+            })
+            .collect(),
+        turns: Vec::new(),
+        u_turn_cost: 0.0,
+        costs_in_seconds: travel_time,
+        node_elevations: Vec::new(),
+        osm_way_ids: Vec::new(),
+        osm_node_ids: Vec::new(),
+        tile: None,
+        contraction_hierarchy: None,
     };
 
-    // Count how many ways reference each node
-    let mut node_counter: HashMap<osm_reader::NodeID, usize> = HashMap::new();
-    for way in ways.values() {
-        for node in &way.nodes {
-            *node_counter.entry(*node).or_insert(0) += 1;
-        }
-    }
-
     // Split each way into edges
     let mut node_id_lookup = HashMap::new();
-    for way in ways.into_values() {
+    // Remember the edges each way has at each of its nodes, for turn restrictions
+    let mut way_edges_at_node: HashMap<(WayID, osm_reader::NodeID), Vec<EdgeID>> = HashMap::new();
+    for (way_id, way) in ways {
         let mut node1 = way.nodes[0];
         let mut pts = Vec::new();
 
         let num_nodes = way.nodes.len();
         for (idx, node) in way.nodes.into_iter().enumerate() {
-            pts.push(nodes[&node]);
+            pts.push(nodes.get(node).unwrap());
             // Edges start/end at intersections between two ways. The endpoints of the way also
             // count as intersections.
-            let is_endpoint =
-                idx == 0 || idx == num_nodes - 1 || *node_counter.get(&node).unwrap() > 1;
+            let is_endpoint = idx == 0 || idx == num_nodes - 1 || nodes.references(node) > 1;
             if is_endpoint && pts.len() > 1 {
                 let geometry = LineString::new(std::mem::take(&mut pts));
                 let mut add_road = true;
                 if let Some(boundary) = boundary {
                     // If this road doesn't intersect the boundary at all, skip it
-                    if !boundary.contains(&geometry) && !boundary.exterior().intersects(&geometry) {
+                    if !boundary.intersects(&geometry) {
                         add_road = false;
                     }
                 }
@@ -127,13 +388,41 @@ fn split_edges(
                     let next_id = NodeID(node_id_lookup.len() as u32);
                     let node1_id = *node_id_lookup.entry(node1).or_insert_with(|| {
                         map.nodes.push(geometry.0[0]);
+                        if osm_ids {
+                            map.osm_node_ids.push(Some(node1.0));
+                        }
                         next_id
                     });
                     let next_id = NodeID(node_id_lookup.len() as u32);
                     let node2_id = *node_id_lookup.entry(node).or_insert_with(|| {
                         map.nodes.push(*geometry.0.last().unwrap());
+                        if osm_ids {
+                            map.osm_node_ids.push(Some(node.0));
+                        }
                         next_id
                     });
+                    // When costs are just distance, only forbidden directions need costs set
+                    // here. If every edge can be used both ways, these are cleared below.
+                    let length = geometry.haversine_length();
+                    for (idx, (forwards, backwards)) in way.directions.iter().enumerate() {
+                        let mut cost = length;
+                        if travel_time {
+                            let speed_kmh = max_speeds_kmh[idx]
+                                .map_or(way.speed_kmh, |max| way.speed_kmh.min(max));
+                            cost /= speed_kmh / 3.6;
+                        }
+                        let (forward_costs, backward_costs) = if idx == 0 {
+                            (
+                                &mut map.override_forward_costs,
+                                &mut map.override_backward_costs,
+                            )
+                        } else {
+                            let profile = &mut map.cost_profiles[idx - 1];
+                            (&mut profile.forward_costs, &mut profile.backward_costs)
+                        };
+                        forward_costs.push(forwards.then_some(cost));
+                        backward_costs.push(backwards.then_some(cost));
+                    }
                     map.edges.push(Edge {
                         node1: node1_id,
                         node2: node2_id,
@@ -144,46 +433,203 @@ fn split_edges(
                         forward_cost: None,
                         backward_cost: None,
                     });
+                    let edge_id = EdgeID(map.edges.len() as u32 - 1);
+                    if osm_ids {
+                        map.osm_way_ids.push(way_id.0);
+                    }
+                    for (key, value) in &way.attributes {
+                        map.attributes.set(edge_id, key, value);
+                    }
+                    for endpt in [node1, node] {
+                        way_edges_at_node
+                            .entry((way_id, endpt))
+                            .or_default()
+                            .push(edge_id);
+                    }
                 }
 
                 // Start the next edge
                 node1 = node;
-                pts.push(nodes[&node]);
+                pts.push(nodes.get(node).unwrap());
             }
         }
     }
 
+    if !travel_time {
+        for (forward_costs, backward_costs) in map.cost_tables_mut() {
+            if forward_costs
+                .iter()
+                .chain(backward_costs.iter())
+                .all(|cost| cost.is_some())
+            {
+                forward_costs.clear();
+                backward_costs.clear();
+            }
+        }
+    }
+
+    add_turn_restrictions(&mut map, restrictions, &node_id_lookup, &way_edges_at_node);
+
     info!(
-        "{} nodes and {} edges total",
+        "{} nodes, {} edges, and {} restricted turns total",
         map.nodes.len(),
-        map.edges.len()
+        map.edges.len(),
+        map.turns.len()
     );
     map
 }
 
-fn clip(map: &mut RouteSnapperMap, boundary: Polygon) {
-    // Fix edges crossing the boundary. Edges totally outside the boundary are skipped earlier
-    // during split_edges.
-    for edge in &mut map.edges {
-        if boundary.exterior().intersects(&edge.geometry) {
-            let invert = false;
-            let mut multi_line_string =
-                boundary.clip(&MultiLineString::from(edge.geometry.clone()), invert);
-            // If we have multiple pieces, that's hard to deal with
+fn add_turn_restrictions(
+    map: &mut RouteSnapperMap,
+    restrictions: &[Restriction],
+    node_id_lookup: &HashMap<osm_reader::NodeID, NodeID>,
+    way_edges_at_node: &HashMap<(WayID, osm_reader::NodeID), Vec<EdgeID>>,
+) {
+    // For only_* restrictions, every edge touching each node
+    let mut edges_at_node: HashMap<NodeID, Vec<EdgeID>> = HashMap::new();
+    if restrictions.iter().any(|r| r.only) {
+        for (idx, edge) in map.edges.iter().enumerate() {
+            edges_at_node
+                .entry(edge.node1)
+                .or_default()
+                .push(EdgeID(idx as u32));
+            if edge.node2 != edge.node1 {
+                edges_at_node
+                    .entry(edge.node2)
+                    .or_default()
+                    .push(EdgeID(idx as u32));
+            }
+        }
+    }
+
+    for restriction in restrictions {
+        // The ways might have been skipped for being outside the boundary, or the relation might
+        // be broken
+        let (Some(via), Some(from_edges), Some(to_edges)) = (
+            node_id_lookup.get(&restriction.via),
+            way_edges_at_node.get(&(restriction.from, restriction.via)),
+            way_edges_at_node.get(&(restriction.to, restriction.via)),
+        ) else {
             debug!(
-                "Shortening {:?} from {} to {}",
-                edge.name,
-                edge.geometry.haversine_length(),
-                multi_line_string.0[0].haversine_length()
+                "Skipping turn restriction from {:?} via {:?} to {:?}",
+                restriction.from, restriction.via, restriction.to
             );
-            edge.geometry = multi_line_string.0.remove(0);
-            // Fix both nodes; if there's no change, doesn't matter
-            map.nodes[edge.node1.0 as usize] = *edge.geometry.coords().next().unwrap();
-            map.nodes[edge.node2.0 as usize] = *edge.geometry.coords().next_back().unwrap();
+            continue;
+        };
+
+        let forbidden: Vec<EdgeID> = if restriction.only {
+            // Every other edge at the node is forbidden, including going back along the same one
+            edges_at_node
+                .get(via)
+                .into_iter()
+                .flatten()
+                .filter(|e| !to_edges.contains(e))
+                .cloned()
+                .collect()
+        } else {
+            to_edges.clone()
+        };
+        for from in from_edges {
+            for to in &forbidden {
+                map.turns.push(Turn {
+                    from: *from,
+                    via: *via,
+                    to: *to,
+                    cost: None,
+                });
+            }
         }
     }
 }
 
+// Adds a cost to every turn of more than 45 degrees between two different edges, unless the turn
+// is already forbidden. Returns the number of turns added.
+fn add_turn_costs(map: &mut RouteSnapperMap, cost: f64) -> usize {
+    let forbidden: HashSet<(EdgeID, NodeID, EdgeID)> = map
+        .turns
+        .iter()
+        .map(|turn| (turn.from, turn.via, turn.to))
+        .collect();
+    let mut edges_at_node: HashMap<NodeID, Vec<EdgeID>> = HashMap::new();
+    for (idx, edge) in map.edges.iter().enumerate() {
+        // Loops have no clear angle
+        if edge.node1 != edge.node2 {
+            for node in [edge.node1, edge.node2] {
+                edges_at_node
+                    .entry(node)
+                    .or_default()
+                    .push(EdgeID(idx as u32));
+            }
+        }
+    }
+
+    // Whether the default costs or any cost profile can use one direction of an edge
+    let routable = |edge_id: EdgeID, forwards: bool| -> bool {
+        map.cost_tables().any(|(forward_costs, backward_costs)| {
+            let costs = if forwards {
+                forward_costs
+            } else {
+                backward_costs
+            };
+            costs.is_empty() || costs[edge_id.0 as usize].is_some()
+        })
+    };
+    // The bearing of the first segment leaving `node` along an edge, if that direction is routable
+    let leaving = |edge_id: EdgeID, node: NodeID| -> Option<f64> {
+        let edge = map.edge(edge_id);
+        let pts = &edge.geometry.0;
+        let (forwards, from, to) = if edge.node1 == node {
+            (true, pts[0], pts[1])
+        } else {
+            (false, pts[pts.len() - 1], pts[pts.len() - 2])
+        };
+        routable(edge_id, forwards).then(|| Point::from(from).haversine_bearing(Point::from(to)))
+    };
+    // The bearing of the last segment arriving at `node` along an edge, if that direction is
+    // routable
+    let arriving = |edge_id: EdgeID, node: NodeID| -> Option<f64> {
+        let edge = map.edge(edge_id);
+        let pts = &edge.geometry.0;
+        let (forwards, from, to) = if edge.node2 == node {
+            (true, pts[pts.len() - 2], pts[pts.len() - 1])
+        } else {
+            (false, pts[1], pts[0])
+        };
+        routable(edge_id, forwards).then(|| Point::from(from).haversine_bearing(Point::from(to)))
+    };
+
+    let mut turns = Vec::new();
+    for (via, edges) in &edges_at_node {
+        for from in edges {
+            let Some(arrive) = arriving(*from, *via) else {
+                continue;
+            };
+            for to in edges {
+                if from == to || forbidden.contains(&(*from, *via, *to)) {
+                    continue;
+                }
+                let Some(leave) = leaving(*to, *via) else {
+                    continue;
+                };
+                let angle = (leave - arrive).rem_euclid(360.0);
+                if angle.min(360.0 - angle) > 45.0 {
+                    turns.push(Turn {
+                        from: *from,
+                        via: *via,
+                        to: *to,
+                        cost: Some(cost),
+                    });
+                }
+            }
+        }
+    }
+    // Keep the output deterministic
+    turns.sort_by_key(|turn| (turn.via, turn.from, turn.to));
+    let added = turns.len();
+    map.turns.extend(turns);
+    added
+}
+
 #[cfg(target_arch = "wasm32")]
 use std::sync::Once;
 #[cfg(target_arch = "wasm32")]
@@ -192,16 +638,317 @@ use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 static START: Once = Once::new();
 
+/// `profile` is `all` (the default), `walk`, `cycle`, `drive`, or `custom`, or a comma-separated
+/// list of them. With several, ways routable by any are kept, each one's costs are stored as a cost
+/// profile with its name, and the first is the default. A custom profile needs
+/// a comma-separated list of `highway` values in `custom_highways` and optionally access tags in
+/// `custom_access_tags`. One-way streets are respected unless `ignore_oneways` is true. If
+/// `travel_time` is true, costs are estimated travel times in seconds. `dem` is an optional
+/// GeoTIFF or ASCII grid file with heights, and `grade_penalty` makes climbs cost more. Disconnected
+/// parts of the graph with fewer than `min_component_edges` edges are removed. `name_languages` is a
+/// comma-separated list of languages to prefer for road names, like `cy,en`. If `osm_ids` is true,
+/// the OSM way and node IDs are kept in the graph. If `compress` is true, the graph is compressed.
+/// `u_turn_cost` is added to turning around onto the same edge, and `turn_cost` to turning more
+/// than 45 degrees onto another edge. If `turn_restrictions` is true, turn restriction relations
+/// via a node are imported.
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen()]
-pub fn convert(input_bytes: Vec<u8>, boundary_geojson: String) -> Result<Vec<u8>, JsValue> {
+pub fn convert(
+    input_bytes: Vec<u8>,
+    boundary_geojson: String,
+    profile: Option<String>,
+    custom_highways: Option<String>,
+    custom_access_tags: Option<String>,
+    ignore_oneways: Option<bool>,
+    travel_time: Option<bool>,
+    dem: Option<Vec<u8>>,
+    grade_penalty: Option<f64>,
+    min_component_edges: Option<usize>,
+    name_languages: Option<String>,
+    osm_ids: Option<bool>,
+    compress: Option<bool>,
+    u_turn_cost: Option<f64>,
+    turn_cost: Option<f64>,
+    turn_restrictions: Option<bool>,
+) -> Result<Vec<u8>, JsValue> {
     START.call_once(|| {
         console_log::init_with_level(log::Level::Info).unwrap();
         console_error_panic_hook::set_once();
     });
 
-    let road_names = true;
-    let snapper = convert_osm(input_bytes, Some(boundary_geojson), road_names)
+    let split = |list: Option<String>| -> Vec<String> {
+        list.unwrap_or_default()
+            .split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect()
+    };
+    let custom_highways = split(custom_highways);
+    let custom_access_tags = split(custom_access_tags);
+    let mut profiles: Vec<(String, Profile)> = Vec::new();
+    for name in split(profile) {
+        if profiles.iter().any(|(x, _)| *x == name) {
+            return Err(JsValue::from_str(&format!(
+                "Profile {name} is listed twice"
+            )));
+        }
+        let profile = if name == "custom" {
+            Profile::custom(
+                custom_highways.clone(),
+                custom_access_tags.clone(),
+                vec!["oneway".to_string()],
+            )
+        } else {
+            Profile::from_name(&name).map_err(|err| JsValue::from_str(&err.to_string()))?
+        };
+        profiles.push((name, profile));
+    }
+    let profile = profiles
+        .first()
+        .map_or_else(Profile::all, |(_, profile)| profile.clone());
+    // A single profile is just the default costs
+    if profiles.len() == 1 {
+        profiles.clear();
+    }
+    let options = Options {
+        profile,
+        cost_profiles: profiles,
+        oneways: !ignore_oneways.unwrap_or(false),
+        travel_time: travel_time.unwrap_or(false),
+        dem,
+        grade_penalty,
+        min_component_edges,
+        name_languages: split(name_languages),
+        osm_ids: osm_ids.unwrap_or(false),
+        u_turn_cost: u_turn_cost.unwrap_or(0.0),
+        turn_cost,
+        turn_restrictions: turn_restrictions.unwrap_or(false),
+        ..Default::default()
+    };
+    let snapper = convert_osm(input_bytes, Some(boundary_geojson), options)
         .map_err(|err| JsValue::from_str(&err.to_string()))?;
-    Ok(bincode::serialize(&snapper).unwrap())
+    let compression = if compress.unwrap_or(false) {
+        route_snapper_graph::Compression::Deflate
+    } else {
+        route_snapper_graph::Compression::None
+    };
+    snapper
+        .to_bytes_with(compression)
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use route_snapper_graph::test_util::make_map;
+
+    use super::*;
+
+    #[test]
+    fn test_turn_costs() {
+        // A crossroads, with edges to the north, east, south, and west
+        let mut map = make_map(
+            vec![
+                Coord { x: 0.0, y: 0.0 },
+                Coord { x: 0.0, y: 0.001 },
+                Coord { x: 0.001, y: 0.0 },
+                Coord { x: 0.0, y: -0.001 },
+                Coord { x: -0.001, y: 0.0 },
+            ],
+            &[(0, 1), (0, 2), (0, 3), (0, 4)],
+        );
+        let (north, east, south, west) = (EdgeID(0), EdgeID(1), EdgeID(2), EdgeID(3));
+        let via = NodeID(0);
+        // Turning from north to west is forbidden, and the east edge is one-way towards the middle
+        map.turns.push(Turn {
+            from: north,
+            via,
+            to: west,
+            cost: None,
+        });
+        map.override_forward_costs = vec![Some(1.0), None, Some(1.0), Some(1.0)];
+        map.override_backward_costs = vec![Some(1.0); 4];
+
+        // Every left and right turn costs extra, except the forbidden one and the two onto the
+        // one-way edge. Going straight is free.
+        assert_eq!(add_turn_costs(&mut map, 10.0), 8 - 3);
+        let turns: HashMap<(EdgeID, EdgeID), Option<f64>> = map
+            .turns
+            .iter()
+            .map(|turn| {
+                assert_eq!(turn.via, via);
+                ((turn.from, turn.to), turn.cost)
+            })
+            .collect();
+        assert_eq!(turns.len(), 6);
+        assert_eq!(turns[&(north, west)], None);
+        assert_eq!(turns[&(west, north)], Some(10.0));
+        assert_eq!(turns[&(east, north)], Some(10.0));
+        assert!(!turns.contains_key(&(north, south)));
+        assert!(!turns.contains_key(&(south, east)));
+    }
+
+    #[test]
+    fn test_only_restriction() {
+        // A crossroads of two ways, 100 north to south and 200 west to east, meeting at OSM node
+        // 5, plus a separate edge far away
+        let mut map = make_map(
+            vec![
+                Coord { x: 0.0, y: 0.0 },
+                Coord { x: 0.0, y: 0.001 },
+                Coord { x: 0.001, y: 0.0 },
+                Coord { x: 0.0, y: -0.001 },
+                Coord { x: -0.001, y: 0.0 },
+                Coord { x: 1.0, y: 1.0 },
+            ],
+            &[(0, 1), (0, 2), (0, 3), (0, 4), (1, 5)],
+        );
+        let (north, east, south, west) = (EdgeID(0), EdgeID(1), EdgeID(2), EdgeID(3));
+        let via = osm_reader::NodeID(5);
+        let node_id_lookup = HashMap::from([(via, NodeID(0))]);
+        let way_edges_at_node = HashMap::from([
+            ((WayID(100), via), vec![north, south]),
+            ((WayID(200), via), vec![east, west]),
+        ]);
+
+        // From the north-south way, only turning onto the west-east way is allowed
+        add_turn_restrictions(
+            &mut map,
+            &[Restriction {
+                from: WayID(100),
+                via,
+                to: WayID(200),
+                only: true,
+            }],
+            &node_id_lookup,
+            &way_edges_at_node,
+        );
+        let mut forbidden: Vec<(EdgeID, EdgeID)> = map
+            .turns
+            .iter()
+            .map(|turn| {
+                assert_eq!(turn.via, NodeID(0));
+                assert_eq!(turn.cost, None);
+                (turn.from, turn.to)
+            })
+            .collect();
+        forbidden.sort_by_key(|(from, to)| (from.0, to.0));
+        assert_eq!(
+            forbidden,
+            vec![
+                (north, north),
+                (north, south),
+                (south, north),
+                (south, south)
+            ]
+        );
+    }
+
+    fn way(id: i64, nodes: &[i64], tags: &[(&str, &str)]) -> Element {
+        Element::Way {
+            id: WayID(id),
+            node_ids: nodes.iter().map(|x| osm_reader::NodeID(*x)).collect(),
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_ignore_oneways() {
+        let roundabout = || {
+            way(
+                1,
+                &[1, 2],
+                &[("highway", "primary"), ("junction", "roundabout")],
+            )
+        };
+        for (oneways, directions) in [(true, (true, false)), (false, (true, true))] {
+            let options = Options {
+                oneways,
+                ..Default::default()
+            };
+            let mut ways = HashMap::new();
+            scrape_way_or_relation(roundabout(), &options, &mut ways, &mut Vec::new());
+            assert_eq!(ways[&WayID(1)].directions, vec![directions]);
+        }
+    }
+
+    #[test]
+    fn test_several_profiles() {
+        let options = Options {
+            profile: Profile::walk(),
+            cost_profiles: vec![
+                ("walk".to_string(), Profile::walk()),
+                ("drive".to_string(), Profile::drive()),
+            ],
+            travel_time: true,
+            ..Default::default()
+        };
+        let mut ways = HashMap::new();
+        for elem in [
+            way(1, &[1, 2], &[("highway", "footway"), ("name", "Footpath")]),
+            way(
+                2,
+                &[2, 3],
+                &[
+                    ("highway", "primary"),
+                    ("name", "High Street"),
+                    ("oneway", "yes"),
+                    ("maxspeed", "36"),
+                ],
+            ),
+            way(3, &[3, 4], &[("highway", "motorway"), ("name", "Motorway")]),
+            way(4, &[4, 5], &[("highway", "cycleway"), ("name", "Cycleway")]),
+        ] {
+            scrape_way_or_relation(elem, &options, &mut ways, &mut Vec::new());
+        }
+        // The cycleway isn't usable by either profile
+        assert_eq!(ways.len(), 3);
+
+        let mut nodes = NodeTable::new(ways.values().map(|way| way.nodes.as_slice()));
+        for id in 1..5 {
+            nodes.set(
+                osm_reader::NodeID(id),
+                Coord {
+                    x: 0.001 * id as f64,
+                    y: 0.0,
+                },
+            );
+        }
+        let map = split_edges(nodes, ways, &[], None, &options);
+        let names: Vec<String> = map.cost_profiles.iter().map(|p| p.name.clone()).collect();
+        assert_eq!(names, vec!["walk", "drive"]);
+
+        // Seconds per km for the default costs, then each profile, forwards and backwards
+        let costs = |name: &str| -> [Option<f64>; 6] {
+            let idx = map
+                .edges
+                .iter()
+                .position(|e| e.name.as_deref() == Some(name))
+                .unwrap();
+            let length = map.edges[idx].geometry.haversine_length();
+            let (walk, drive) = (&map.cost_profiles[0], &map.cost_profiles[1]);
+            [
+                map.override_forward_costs[idx],
+                map.override_backward_costs[idx],
+                walk.forward_costs[idx],
+                walk.backward_costs[idx],
+                drive.forward_costs[idx],
+                drive.backward_costs[idx],
+            ]
+            .map(|cost| cost.map(|c| (c / length * 1000.0).round()))
+        };
+        // Walking is capped at 5km/h and ignores oneway. Driving isn't allowed on footways, and
+        // the high street is 36km/h.
+        let walk = Some(720.0);
+        assert_eq!(costs("Footpath"), [walk, walk, walk, walk, None, None]);
+        assert_eq!(
+            costs("High Street"),
+            [walk, walk, walk, walk, Some(100.0), None]
+        );
+        let motorway = costs("Motorway");
+        assert_eq!(motorway[..4], [None; 4]);
+        assert!(motorway[4].is_some() && motorway[5].is_some());
+    }
 }
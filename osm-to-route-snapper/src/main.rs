@@ -1,8 +1,7 @@
-use std::fs::File;
-use std::io::BufWriter;
-
-use clap::Parser;
-use osm_to_route_snapper::convert_osm;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use osm_to_route_snapper::{convert_osm_file, Options, Profile};
+use route_snapper_graph::{ch, tiles, Compression};
 
 #[derive(Parser)]
 struct Args {
@@ -10,30 +9,213 @@ struct Args {
     #[arg(long)]
     input: String,
 
-    /// Path to GeoJSON file with the boundary to clip the input to
+    /// Path to GeoJSON file with the boundary to clip the input to. It can be a Polygon,
+    /// MultiPolygon, or a FeatureCollection of them.
     #[arg(short, long)]
     boundary: Option<String>,
 
-    /// Output file to write
+    /// Only use boundary features with this property, like `name=Southwark`
+    #[arg(long, value_parser = parse_key_value)]
+    boundary_filter: Option<(String, String)>,
+
+    /// Output file to write, or a directory with `--tile-size`
     #[arg(long, default_value = "snap.bin")]
     output: String,
 
+    /// Split the graph into square tiles this many degrees wide, writing each to `{x}_{y}.bin`
+    /// in the output directory along with an `index.json`. The route snapper can load tiles as
+    /// they come into view.
+    #[clap(long)]
+    tile_size: Option<f64>,
+
+    /// Precompute a contraction hierarchy, so the route snapper finds paths much faster on large
+    /// graphs. It makes the file about a third bigger and only helps with the default costs,
+    /// without cost profiles or turn restrictions.
+    #[clap(long, conflicts_with = "tile_size")]
+    contraction_hierarchy: bool,
+
+    /// Compress the output, usually to about half the size. The route snapper decompresses it
+    /// when loading.
+    #[clap(long)]
+    compress: bool,
+
     /// Omit road names from the output, saving some space.
     #[clap(long)]
     no_road_names: bool,
+
+    /// A comma-separated list of languages, like `cy,en`, to prefer `name:cy` and then `name:en`
+    /// over `name`
+    #[clap(long, value_delimiter = ',')]
+    name_languages: Vec<String>,
+
+    /// A comma-separated list of OSM tags, like `highway,maxspeed`, to keep as edge attributes
+    #[clap(long, value_delimiter = ',')]
+    attributes: Vec<String>,
+
+    /// Import turn restriction relations, so routes can't make forbidden turns. Only restrictions
+    /// via a node are supported.
+    #[clap(long)]
+    turn_restrictions: bool,
+
+    /// Add this to the cost of turning around onto the same edge. It's in meters, or seconds with
+    /// `--travel-time`.
+    #[clap(long, default_value_t = 0.0)]
+    u_turn_cost: f64,
+
+    /// Add this to the cost of turning more than 45 degrees onto another road, so routes prefer
+    /// going straight. It's in meters, or seconds with `--travel-time`.
+    #[clap(long)]
+    turn_cost: Option<f64>,
+
+    /// Which ways are routable: `all` keeps everything with a `highway` tag, `walk`, `cycle`, and
+    /// `drive` filter by highway class and access tags, and `custom` uses `--highways` and
+    /// `--access-tags`. With a comma-separated list like `walk,cycle`, ways routable by any of them
+    /// are kept, and each one's costs are stored as a cost profile with that name. The first one is
+    /// the default.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "all",
+        value_parser = ["all", "walk", "cycle", "drive", "custom"]
+    )]
+    profile: Vec<String>,
+
+    /// For the custom profile, a comma-separated list of routable `highway` values
+    #[clap(long, value_delimiter = ',')]
+    highways: Vec<String>,
+
+    /// For the custom profile, a comma-separated list of access tags to check before `access`,
+    /// most specific first, like `bicycle,vehicle`
+    #[clap(long, value_delimiter = ',')]
+    access_tags: Vec<String>,
+
+    /// For the custom profile, a comma-separated list of one-way tags to check, most specific
+    /// first, like `oneway:bicycle,oneway`
+    #[clap(long, value_delimiter = ',', default_value = "oneway")]
+    oneway_tags: Vec<String>,
+
+    /// Store the OSM way ID of every edge and the OSM node ID of every node in the graph, so routes
+    /// can be traced back to OSM. This makes the file bigger.
+    #[clap(long)]
+    osm_ids: bool,
+
+    /// Keep both directions of one-way streets routable, for planning new routes
+    #[clap(long)]
+    ignore_oneways: bool,
+
+    /// Use estimated travel time as the cost, based on `maxspeed` or defaults for the highway
+    /// class, capped by the profile's speed. Otherwise distance is used.
+    #[clap(long)]
+    travel_time: bool,
+
+    /// Path to a GeoTIFF or ESRI ASCII grid (.asc) file with heights in meters, using WGS84
+    /// coordinates. Each node's elevation is stored in the graph.
+    #[clap(long)]
+    dem: Option<String>,
+
+    /// With `--dem`, multiply the cost of going uphill by `1 + penalty * grade`, where grade is
+    /// like 0.05 for a 5% slope
+    #[clap(long)]
+    grade_penalty: Option<f64>,
+
+    /// Remove parts of the graph with fewer than this many edges that aren't connected to the
+    /// largest part. Without this, they're only reported.
+    #[clap(long)]
+    min_component_edges: Option<usize>,
 }
 
 fn main() {
     simple_logger::init_with_level(log::Level::Info).unwrap();
     let args = Args::parse();
-    let snapper = convert_osm(
-        std::fs::read(&args.input).unwrap(),
+    let mut profiles: Vec<(String, Profile)> = Vec::new();
+    for name in args.profile {
+        if profiles.iter().any(|(x, _)| *x == name) {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("--profile {name} is listed twice"),
+                )
+                .exit();
+        }
+        let profile = if name == "custom" {
+            if args.highways.is_empty() {
+                Args::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--profile custom needs --highways",
+                    )
+                    .exit();
+            }
+            Profile::custom(
+                args.highways.clone(),
+                args.access_tags.clone(),
+                args.oneway_tags.clone(),
+            )
+        } else {
+            Profile::from_name(&name).unwrap()
+        };
+        profiles.push((name, profile));
+    }
+    let profile = profiles[0].1.clone();
+    // A single profile is just the default costs
+    if profiles.len() == 1 {
+        profiles.clear();
+    }
+    let options = Options {
+        road_names: !args.no_road_names,
+        name_languages: args.name_languages,
+        attribute_tags: args.attributes,
+        turn_restrictions: args.turn_restrictions,
+        u_turn_cost: args.u_turn_cost,
+        turn_cost: args.turn_cost,
+        profile,
+        cost_profiles: profiles,
+        oneways: !args.ignore_oneways,
+        travel_time: args.travel_time,
+        osm_ids: args.osm_ids,
+        dem: args.dem.map(|path| std::fs::read(path).unwrap()),
+        grade_penalty: args.grade_penalty,
+        min_component_edges: args.min_component_edges,
+        boundary_filter: args.boundary_filter,
+    };
+    let mut snapper = convert_osm_file(
+        &args.input,
         args.boundary
             .map(|path| std::fs::read_to_string(path).unwrap()),
-        !args.no_road_names,
+        options,
     )
     .unwrap();
 
-    let output = BufWriter::new(File::create(args.output).unwrap());
-    bincode::serialize_into(output, &snapper).unwrap();
+    if args.contraction_hierarchy {
+        let ch = ch::build(&snapper);
+        log::info!(
+            "Built a contraction hierarchy with {} shortcuts",
+            ch.num_shortcuts()
+        );
+        snapper.contraction_hierarchy = Some(ch);
+    }
+
+    let compression = if args.compress {
+        Compression::Deflate
+    } else {
+        Compression::None
+    };
+    if let Some(tile_size) = args.tile_size {
+        let tiles = tiles::write_tiles(
+            &snapper,
+            tile_size,
+            compression,
+            std::path::Path::new(&args.output),
+        )
+        .unwrap();
+        log::info!("Wrote {tiles} tiles to {}", args.output);
+    } else {
+        std::fs::write(args.output, snapper.to_bytes_with(compression).unwrap()).unwrap();
+    }
+}
+
+fn parse_key_value(x: &str) -> Result<(String, String), String> {
+    x.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| "must look like key=value".to_string())
 }
@@ -0,0 +1,133 @@
+use osm_reader::Tags;
+
+/// Picks a name for a way, so unnamed roads still get a useful label. In order, this tries
+/// `name:xx` for each of the preferred languages, `name`, the `junction:name` of a roundabout or
+/// other junction, `ref`, and finally a description of the road type, like "unnamed footway".
+/// Only returns `None` for ways without a `highway` tag.
+pub fn way_name(tags: &Tags, languages: &[String]) -> Option<String> {
+    languages
+        .iter()
+        .map(|lang| format!("name:{lang}"))
+        .chain(["name".to_string(), "junction:name".to_string()])
+        .find_map(|key| non_empty(tags, &key))
+        .or_else(|| non_empty(tags, "ref").map(|x| x.replace(';', " / ")))
+        .or_else(|| describe(tags))
+}
+
+fn non_empty(tags: &Tags, key: &str) -> Option<String> {
+    let value = tags.get(key)?.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn describe(tags: &Tags) -> Option<String> {
+    let highway = tags.get("highway")?;
+    if tags.get("junction").map(|x| x.as_str()) == Some("roundabout") {
+        return Some("unnamed roundabout".to_string());
+    }
+    let kind = match highway.as_str() {
+        // Described by what they're for
+        "footway" if tags.get("footway").map(|x| x.as_str()) == Some("sidewalk") => "pavement",
+        "footway" if tags.get("footway").map(|x| x.as_str()) == Some("crossing") => "crossing",
+        "service" if tags.contains_key("service") => {
+            return Some(format!("unnamed {}", tags["service"].replace('_', " ")));
+        }
+        "steps" => "steps",
+        "living_street" => "living street",
+        "road" | "yes" => "road",
+        x => {
+            if let Some(class) = x.strip_suffix("_link") {
+                return Some(format!("unnamed {class} slip road"));
+            }
+            return Some(format!("unnamed {}", x.replace('_', " ")));
+        }
+    };
+    Some(format!("unnamed {kind}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(pairs: &[(&str, &str)], languages: &[&str]) -> Option<String> {
+        let tags: Tags = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let languages: Vec<String> = languages.iter().map(|x| x.to_string()).collect();
+        way_name(&tags, &languages)
+    }
+
+    #[test]
+    fn test_languages() {
+        let tags = [
+            ("highway", "primary"),
+            ("name", "Brussel"),
+            ("name:fr", "Bruxelles"),
+            ("name:en", "Brussels"),
+        ];
+        assert_eq!(name(&tags, &[]).unwrap(), "Brussel");
+        assert_eq!(name(&tags, &["en", "fr"]).unwrap(), "Brussels");
+        assert_eq!(name(&tags, &["de", "fr"]).unwrap(), "Bruxelles");
+        assert_eq!(name(&tags, &["de"]).unwrap(), "Brussel");
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        // Blank names are skipped
+        assert_eq!(
+            name(&[("highway", "primary"), ("name", " "), ("ref", "A1")], &[]).unwrap(),
+            "A1"
+        );
+        assert_eq!(
+            name(&[("highway", "trunk"), ("ref", "A1;A10")], &[]).unwrap(),
+            "A1 / A10"
+        );
+        assert_eq!(
+            name(
+                &[
+                    ("highway", "primary"),
+                    ("junction", "roundabout"),
+                    ("junction:name", "Hyde Park Corner"),
+                    ("ref", "A4"),
+                ],
+                &[]
+            )
+            .unwrap(),
+            "Hyde Park Corner"
+        );
+        assert_eq!(name(&[("name", "Not a road")], &[]).unwrap(), "Not a road");
+        assert_eq!(name(&[("railway", "rail")], &[]), None);
+    }
+
+    #[test]
+    fn test_descriptions() {
+        for (tags, expected) in [
+            (vec![("highway", "footway")], "unnamed footway"),
+            (
+                vec![("highway", "footway"), ("footway", "sidewalk")],
+                "unnamed pavement",
+            ),
+            (
+                vec![("highway", "footway"), ("footway", "crossing")],
+                "unnamed crossing",
+            ),
+            (
+                vec![("highway", "service"), ("service", "parking_aisle")],
+                "unnamed parking aisle",
+            ),
+            (vec![("highway", "service")], "unnamed service"),
+            (vec![("highway", "living_street")], "unnamed living street"),
+            (vec![("highway", "yes")], "unnamed road"),
+            (
+                vec![("highway", "primary_link")],
+                "unnamed primary slip road",
+            ),
+            (
+                vec![("highway", "tertiary"), ("junction", "roundabout")],
+                "unnamed roundabout",
+            ),
+        ] {
+            assert_eq!(name(&tags, &[]).unwrap(), expected);
+        }
+    }
+}
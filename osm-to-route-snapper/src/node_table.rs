@@ -0,0 +1,82 @@
+use geo::Coord;
+use osm_reader::NodeID;
+
+/// Coordinates for only the OSM nodes used by routable ways, sorted by ID. This is much smaller
+/// than a map of every node in the input, which is mostly buildings and other things.
+pub struct NodeTable {
+    ids: Vec<i64>,
+    /// NaN until the node is found in the input
+    coords: Vec<Coord>,
+    /// How many times ways refer to each node
+    references: Vec<u32>,
+}
+
+impl NodeTable {
+    /// Remembers every node referenced by these ways, without coordinates yet.
+    pub fn new<'a>(ways: impl Iterator<Item = &'a [NodeID]>) -> Self {
+        let mut all: Vec<i64> = ways.flat_map(|nodes| nodes.iter().map(|n| n.0)).collect();
+        all.sort_unstable();
+
+        let mut ids = Vec::new();
+        let mut references = Vec::new();
+        for id in all {
+            if ids.last() == Some(&id) {
+                *references.last_mut().unwrap() += 1;
+            } else {
+                ids.push(id);
+                references.push(1);
+            }
+        }
+        ids.shrink_to_fit();
+        references.shrink_to_fit();
+        let coords = vec![
+            Coord {
+                x: f64::NAN,
+                y: f64::NAN
+            };
+            ids.len()
+        ];
+        Self {
+            ids,
+            coords,
+            references,
+        }
+    }
+
+    /// Records the coordinate of a node, if it's one of the referenced nodes.
+    pub fn set(&mut self, id: NodeID, pt: Coord) {
+        if let Ok(idx) = self.ids.binary_search(&id.0) {
+            self.coords[idx] = pt;
+        }
+    }
+
+    /// Returns `None` for nodes not referenced by a way, or missing from the input.
+    pub fn get(&self, id: NodeID) -> Option<Coord> {
+        let idx = self.ids.binary_search(&id.0).ok()?;
+        let pt = self.coords[idx];
+        (!pt.x.is_nan()).then_some(pt)
+    }
+
+    pub fn references(&self, id: NodeID) -> u32 {
+        match self.ids.binary_search(&id.0) {
+            Ok(idx) => self.references[idx],
+            Err(_) => 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// How many referenced nodes weren't found in the input
+    pub fn num_missing(&self) -> usize {
+        self.coords.iter().filter(|pt| pt.x.is_nan()).count()
+    }
+
+    /// Approximately how much memory this uses
+    pub fn bytes(&self) -> usize {
+        self.ids.capacity() * std::mem::size_of::<i64>()
+            + self.coords.capacity() * std::mem::size_of::<Coord>()
+            + self.references.capacity() * std::mem::size_of::<u32>()
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::Read;
+
+use anyhow::{bail, Result};
+
+/// PBF files are parsed a batch of blobs at a time. Each blob is at most 32 MB, so a batch holds
+/// at least one.
+pub const BATCH_BYTES: usize = 64 * 1024 * 1024;
+// From https://wiki.openstreetmap.org/wiki/PBF_Format
+const MAX_BLOB_HEADER_BYTES: usize = 64 * 1024;
+const MAX_BLOB_BYTES: usize = 32 * 1024 * 1024;
+
+/// Reads whole blobs from a PBF file, calling `f` with up to `batch_bytes` of them at a time (or
+/// one bigger blob). Each batch is valid PBF on its own.
+pub fn for_each_batch<R: Read, F: FnMut(&[u8]) -> Result<()>>(
+    mut reader: R,
+    batch_bytes: usize,
+    mut f: F,
+) -> Result<()> {
+    let mut batch = Vec::new();
+    loop {
+        // Only stop cleanly between blobs
+        let mut header_length = [0; 4];
+        if reader.read(&mut header_length[..1])? == 0 {
+            break;
+        }
+        reader.read_exact(&mut header_length[1..])?;
+        let header_length = u32::from_be_bytes(header_length) as usize;
+        if header_length > MAX_BLOB_HEADER_BYTES {
+            bail!("PBF blob header is {header_length} bytes; the input is probably corrupt");
+        }
+        let mut header = vec![0; header_length];
+        reader.read_exact(&mut header)?;
+        let blob_length = blob_data_size(&header)?;
+        if blob_length > MAX_BLOB_BYTES {
+            bail!("PBF blob is {blob_length} bytes; the input is probably corrupt");
+        }
+
+        if !batch.is_empty() && batch.len() + 4 + header_length + blob_length > batch_bytes {
+            f(&batch)?;
+            batch.clear();
+        }
+        batch.extend((header_length as u32).to_be_bytes());
+        batch.extend(header);
+        let start = batch.len();
+        batch.resize(start + blob_length, 0);
+        reader.read_exact(&mut batch[start..])?;
+    }
+    if !batch.is_empty() {
+        f(&batch)?;
+    }
+    Ok(())
+}
+
+/// Finds the `datasize` field in a `BlobHeader` protobuf message.
+fn blob_data_size(mut header: &[u8]) -> Result<usize> {
+    fn varint(bytes: &mut &[u8]) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let Some((byte, rest)) = bytes.split_first() else {
+                bail!("PBF blob header is truncated");
+            };
+            *bytes = rest;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("PBF blob header has an invalid varint");
+    }
+
+    let mut data_size = None;
+    while !header.is_empty() {
+        let key = varint(&mut header)?;
+        match key & 0b111 {
+            0 => {
+                let value = varint(&mut header)?;
+                if key >> 3 == 3 {
+                    data_size = Some(value as usize);
+                }
+            }
+            2 => {
+                let length = varint(&mut header)? as usize;
+                if length > header.len() {
+                    bail!("PBF blob header is truncated");
+                }
+                header = &header[length..];
+            }
+            x => bail!("PBF blob header has unexpected wire type {x}"),
+        }
+    }
+    match data_size {
+        Some(x) => Ok(x),
+        None => bail!("PBF blob header has no datasize"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The length and header of a blob of this size
+    fn blob_header(blob_type: &str, size: usize) -> Vec<u8> {
+        let mut header = vec![0x0A, blob_type.len() as u8];
+        header.extend(blob_type.as_bytes());
+        // datasize as a varint
+        header.push(0x18);
+        let mut value = size;
+        while value >= 0x80 {
+            header.push((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+        header.push(value as u8);
+
+        let mut bytes = (header.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(header);
+        bytes
+    }
+
+    /// A blob header and fake contents
+    fn blob(blob_type: &str, size: usize) -> Vec<u8> {
+        let mut bytes = blob_header(blob_type, size);
+        bytes.extend(std::iter::repeat_n(size as u8, size));
+        bytes
+    }
+
+    fn batches(input: &[u8], batch_bytes: usize) -> Result<Vec<Vec<u8>>> {
+        let mut batches = Vec::new();
+        for_each_batch(input, batch_bytes, |batch| {
+            batches.push(batch.to_vec());
+            Ok(())
+        })?;
+        Ok(batches)
+    }
+
+    #[test]
+    fn test_batches() {
+        let blobs = [
+            blob("OSMHeader", 10),
+            blob("OSMData", 200),
+            blob("OSMData", 20),
+            blob("OSMData", 30),
+        ];
+        let input = blobs.concat();
+
+        // Everything fits in one batch
+        assert_eq!(batches(&input, 1000).unwrap(), vec![input.clone()]);
+        // Batches break between blobs, and a blob bigger than the limit gets its own batch
+        assert_eq!(
+            batches(&input, 100).unwrap(),
+            vec![
+                blobs[0].clone(),
+                blobs[1].clone(),
+                [blobs[2].clone(), blobs[3].clone()].concat()
+            ]
+        );
+        assert!(batches(&[], 100).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let input = [blob("OSMHeader", 10), blob("OSMData", 20)].concat();
+        // Cutting off anything but a whole blob is an error
+        for len in 1..input.len() {
+            if len != blob("OSMHeader", 10).len() {
+                assert!(batches(&input[..len], 1000).is_err(), "cut at {len}");
+            }
+        }
+
+        let mut huge_header = input.clone();
+        huge_header[0..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(batches(&huge_header, 1000).is_err());
+
+        let huge_blob = blob_header("OSMData", MAX_BLOB_BYTES + 1);
+        assert!(batches(&huge_blob, 1000).is_err());
+
+        // No datasize
+        let mut no_size = 2_u32.to_be_bytes().to_vec();
+        no_size.extend([0x0A, 0]);
+        assert!(batches(&no_size, 1000).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::{bail, Result};
+use osm_reader::Tags;
+
+/// Decides which OSM ways are routable for some travel mode.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// Only ways with one of these `highway` values are routable, unless a mode-specific access
+    /// tag explicitly allows it. If `None`, every way with a `highway` tag is routable and access
+    /// tags are ignored.
+    pub highways: Option<Vec<String>>,
+    /// Access tags for this mode, most specific first, like `["bicycle", "vehicle"]`. The first
+    /// one present on a way decides. If none are, the general `access` tag is used.
+    pub access_tags: Vec<String>,
+    /// One-way tags for this mode, most specific first, like `["oneway:bicycle", "oneway"]`. The
+    /// first one present on a way decides. `junction=roundabout` implies one-way if `oneway` is
+    /// listed, and `cycleway=opposite*` exempts ways from `oneway` if `oneway:bicycle` is listed.
+    pub oneway_tags: Vec<String>,
+    /// When estimating travel time, nothing goes faster than this, in km/h. `None` means the
+    /// speed limit or a default for the road type is used.
+    pub max_speed_kmh: Option<f64>,
+}
+
+// Normal roads. Motorways and trunk roads are added per mode.
+const ROADS: [&str; 12] = [
+    "primary",
+    "primary_link",
+    "secondary",
+    "secondary_link",
+    "tertiary",
+    "tertiary_link",
+    "unclassified",
+    "residential",
+    "living_street",
+    "service",
+    "road",
+    "trunk_link",
+];
+
+impl Profile {
+    /// Every way with a `highway` tag, including ones under construction and paths only usable by
+    /// some modes. This is meant for sketching new routes along existing roads.
+    pub fn all() -> Self {
+        Self {
+            highways: None,
+            access_tags: Vec::new(),
+            oneway_tags: vec!["oneway".to_string()],
+            max_speed_kmh: None,
+        }
+    }
+
+    pub fn walk() -> Self {
+        Self::new(
+            &[
+                "trunk",
+                "footway",
+                "path",
+                "pedestrian",
+                "steps",
+                "track",
+                "bridleway",
+                "corridor",
+            ],
+            &["foot"],
+            &["oneway:foot"],
+            Some(5.0),
+        )
+    }
+
+    pub fn cycle() -> Self {
+        Self::new(
+            &["trunk", "cycleway", "path", "track"],
+            &["bicycle", "vehicle"],
+            &["oneway:bicycle", "oneway"],
+            Some(15.0),
+        )
+    }
+
+    pub fn drive() -> Self {
+        Self::new(
+            &["motorway", "motorway_link", "trunk"],
+            &["motor_vehicle", "vehicle"],
+            &["oneway"],
+            None,
+        )
+    }
+
+    /// Only the listed `highway` values are routable, using the listed access and one-way tags.
+    pub fn custom(
+        highways: Vec<String>,
+        access_tags: Vec<String>,
+        oneway_tags: Vec<String>,
+    ) -> Self {
+        Self {
+            highways: Some(highways),
+            access_tags,
+            oneway_tags,
+            max_speed_kmh: None,
+        }
+    }
+
+    /// Looks up one of the built-in profiles: `all`, `walk`, `cycle`, or `drive`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "all" => Ok(Self::all()),
+            "walk" => Ok(Self::walk()),
+            "cycle" => Ok(Self::cycle()),
+            "drive" => Ok(Self::drive()),
+            _ => bail!("Unknown profile {name}; use all, walk, cycle, or drive"),
+        }
+    }
+
+    fn new(
+        extra_highways: &[&str],
+        access_tags: &[&str],
+        oneway_tags: &[&str],
+        max_speed_kmh: Option<f64>,
+    ) -> Self {
+        Self {
+            highways: Some(
+                ROADS
+                    .iter()
+                    .chain(extra_highways)
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            access_tags: access_tags.iter().map(|x| x.to_string()).collect(),
+            oneway_tags: oneway_tags.iter().map(|x| x.to_string()).collect(),
+            max_speed_kmh,
+        }
+    }
+
+    pub fn is_routable(&self, tags: &Tags) -> bool {
+        let Some(highway) = tags.get("highway") else {
+            return false;
+        };
+        let Some(ref highways) = self.highways else {
+            return true;
+        };
+
+        // A mode-specific tag like `bicycle=yes` on a footway overrides the highway class
+        for key in &self.access_tags {
+            if let Some(value) = tags.get(key) {
+                if is_denied(value) {
+                    return false;
+                }
+                if ["yes", "designated", "permissive", "destination"].contains(&value.as_str()) {
+                    return true;
+                }
+                break;
+            }
+        }
+
+        if !highways.contains(highway) {
+            return false;
+        }
+        !tags.get("access").is_some_and(|x| is_denied(x))
+    }
+
+    /// Returns whether a way can be followed (forwards, backwards) in the order of its nodes.
+    pub fn directions(&self, tags: &Tags) -> (bool, bool) {
+        for key in &self.oneway_tags {
+            if key == "oneway"
+                && self.oneway_tags.iter().any(|x| x == "oneway:bicycle")
+                && tags
+                    .get("cycleway")
+                    .is_some_and(|x| x.starts_with("opposite"))
+            {
+                return (true, true);
+            }
+            match tags.get(key).map(|x| x.as_str()) {
+                Some("yes" | "true" | "1") => return (true, false),
+                Some("-1" | "reverse") => return (false, true),
+                Some("no" | "false" | "0") => return (true, true),
+                // Values like `alternating` or `reversible` change over time
+                _ => {}
+            }
+        }
+        if self.oneway_tags.iter().any(|x| x == "oneway")
+            && tags.get("junction").is_some_and(|x| x == "roundabout")
+        {
+            return (true, false);
+        }
+        (true, true)
+    }
+}
+
+fn is_denied(value: &str) -> bool {
+    ["no", "private", "use_sidepath"].contains(&value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_highway_classes() {
+        let footway = tags(&[("highway", "footway")]);
+        let cycleway = tags(&[("highway", "cycleway")]);
+        let motorway = tags(&[("highway", "motorway")]);
+        let residential = tags(&[("highway", "residential")]);
+
+        assert!(Profile::walk().is_routable(&footway));
+        assert!(!Profile::walk().is_routable(&motorway));
+        assert!(Profile::cycle().is_routable(&cycleway));
+        assert!(!Profile::cycle().is_routable(&footway));
+        assert!(Profile::drive().is_routable(&motorway));
+        assert!(!Profile::drive().is_routable(&cycleway));
+        for profile in [Profile::walk(), Profile::cycle(), Profile::drive()] {
+            assert!(profile.is_routable(&residential));
+        }
+
+        // Without a highway tag, nothing is routable
+        assert!(!Profile::all().is_routable(&tags(&[("railway", "rail")])));
+        assert!(Profile::all().is_routable(&tags(&[("highway", "construction")])));
+    }
+
+    #[test]
+    fn test_access_tags() {
+        // A mode-specific tag overrides the highway class
+        let footway = tags(&[("highway", "footway"), ("bicycle", "yes")]);
+        assert!(Profile::cycle().is_routable(&footway));
+        let pedestrian = tags(&[("highway", "pedestrian"), ("motor_vehicle", "destination")]);
+        assert!(Profile::drive().is_routable(&pedestrian));
+
+        // ...and denies access to an otherwise routable road
+        let residential = tags(&[("highway", "residential"), ("foot", "no")]);
+        assert!(!Profile::walk().is_routable(&residential));
+        assert!(Profile::cycle().is_routable(&residential));
+        let sidepath = tags(&[("highway", "primary"), ("bicycle", "use_sidepath")]);
+        assert!(!Profile::cycle().is_routable(&sidepath));
+
+        // The most specific tag present decides
+        let service = tags(&[
+            ("highway", "service"),
+            ("vehicle", "no"),
+            ("bicycle", "yes"),
+        ]);
+        assert!(Profile::cycle().is_routable(&service));
+        assert!(!Profile::drive().is_routable(&service));
+
+        // The general access tag applies when no mode-specific tag is present
+        let private = tags(&[("highway", "service"), ("access", "private")]);
+        assert!(!Profile::walk().is_routable(&private));
+        let private = tags(&[
+            ("highway", "service"),
+            ("access", "private"),
+            ("foot", "permissive"),
+        ]);
+        assert!(Profile::walk().is_routable(&private));
+
+        // The all profile ignores access tags
+        assert!(Profile::all().is_routable(&tags(&[("highway", "service"), ("access", "no")])));
+    }
+
+    #[test]
+    fn test_custom() {
+        let profile = Profile::custom(
+            vec!["track".to_string()],
+            vec!["horse".to_string()],
+            Vec::new(),
+        );
+        assert!(profile.is_routable(&tags(&[("highway", "track")])));
+        assert!(!profile.is_routable(&tags(&[("highway", "residential")])));
+        assert!(profile.is_routable(&tags(&[("highway", "footway"), ("horse", "designated")])));
+        assert!(!profile.is_routable(&tags(&[("highway", "track"), ("horse", "no")])));
+    }
+
+    #[test]
+    fn test_oneways() {
+        let both = (true, true);
+        let forwards = (true, false);
+        let backwards = (false, true);
+
+        for profile in [Profile::all(), Profile::cycle(), Profile::drive()] {
+            assert_eq!(
+                profile.directions(&tags(&[("highway", "residential")])),
+                both
+            );
+            assert_eq!(profile.directions(&tags(&[("oneway", "yes")])), forwards);
+            assert_eq!(profile.directions(&tags(&[("oneway", "-1")])), backwards);
+            assert_eq!(profile.directions(&tags(&[("oneway", "no")])), both);
+            // Time-dependent values are treated as two-way
+            assert_eq!(profile.directions(&tags(&[("oneway", "reversible")])), both);
+            assert_eq!(
+                profile.directions(&tags(&[("junction", "roundabout")])),
+                forwards
+            );
+            assert_eq!(
+                profile.directions(&tags(&[("junction", "roundabout"), ("oneway", "no")])),
+                both
+            );
+        }
+
+        // Pedestrians ignore oneway and roundabouts, but not oneway:foot
+        let walk = Profile::walk();
+        assert_eq!(walk.directions(&tags(&[("oneway", "yes")])), both);
+        assert_eq!(walk.directions(&tags(&[("junction", "roundabout")])), both);
+        assert_eq!(walk.directions(&tags(&[("oneway:foot", "yes")])), forwards);
+    }
+
+    #[test]
+    fn test_cycling_contraflow() {
+        let cycle = Profile::cycle();
+        let drive = Profile::drive();
+
+        let exempt = tags(&[("oneway", "yes"), ("oneway:bicycle", "no")]);
+        assert_eq!(cycle.directions(&exempt), (true, true));
+        assert_eq!(drive.directions(&exempt), (true, false));
+
+        for value in ["opposite", "opposite_lane", "opposite_track"] {
+            let contraflow = tags(&[("oneway", "yes"), ("cycleway", value)]);
+            assert_eq!(cycle.directions(&contraflow), (true, true));
+            assert_eq!(drive.directions(&contraflow), (true, false));
+        }
+
+        // oneway:bicycle can also restrict cycling on an otherwise two-way road
+        let restricted = tags(&[("oneway:bicycle", "-1")]);
+        assert_eq!(cycle.directions(&restricted), (false, true));
+        assert_eq!(drive.directions(&restricted), (true, true));
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert!(Profile::from_name("walk").is_ok());
+        assert_eq!(
+            Profile::from_name("cycle").unwrap().max_speed_kmh,
+            Some(15.0)
+        );
+        assert!(Profile::from_name("horse").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use log::info;
+
+const LOG_EVERY: usize = 5_000_000;
+
+/// Logs how many OSM elements have been read so far, for large inputs.
+pub struct Progress {
+    label: &'static str,
+    count: usize,
+}
+
+impl Progress {
+    pub fn new(label: &'static str) -> Self {
+        info!("{label}");
+        Self { label, count: 0 }
+    }
+
+    pub fn tick(&mut self) {
+        self.count += 1;
+        if self.count.is_multiple_of(LOG_EVERY) {
+            info!("  {}: read {} elements", self.label, self.count);
+        }
+    }
+
+    pub fn done(self) {
+        info!("  {}: read {} elements total", self.label, self.count);
+    }
+}
+
+/// The most memory this process has used so far, if the platform can tell us.
+pub fn peak_memory_bytes() -> Option<usize> {
+    // Only Linux is supported for now
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: usize = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+pub fn describe_bytes(bytes: usize) -> String {
+    let mb = bytes as f64 / 1024.0 / 1024.0;
+    if mb >= 1024.0 {
+        format!("{:.1} GB", mb / 1024.0)
+    } else {
+        format!("{mb:.1} MB")
+    }
+}
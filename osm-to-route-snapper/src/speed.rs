@@ -0,0 +1,111 @@
+use osm_reader::Tags;
+
+const MPH_TO_KMH: f64 = 1.609344;
+
+/// Estimates how fast traffic moves along a way in km/h, from `maxspeed` if it's set and
+/// understood, otherwise from the `highway` class.
+pub fn speed_kmh(tags: &Tags) -> f64 {
+    tags.get("maxspeed")
+        .and_then(|x| parse_maxspeed(x))
+        .unwrap_or_else(|| default_speed_kmh(tags.get("highway").map(|x| x.as_str())))
+}
+
+// Handles plain numbers in km/h, `mph` and `knots` units, and some implicit values like
+// `GB:nsl_single` or `DE:urban`. See https://wiki.openstreetmap.org/wiki/Key:maxspeed.
+fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let kmh = if let Some(mph) = value.strip_suffix("mph") {
+        mph.trim().parse::<f64>().ok()? * MPH_TO_KMH
+    } else if let Some(knots) = value.strip_suffix("knots") {
+        knots.trim().parse::<f64>().ok()? * 1.852
+    } else if let Ok(kmh) = value
+        .strip_suffix("km/h")
+        .unwrap_or(value)
+        .trim()
+        .parse::<f64>()
+    {
+        kmh
+    } else {
+        implicit_maxspeed(value)?
+    };
+    // Zero, negative, or infinite speeds would make travel times meaningless
+    (kmh > 0.0 && kmh.is_finite()).then_some(kmh)
+}
+
+fn implicit_maxspeed(value: &str) -> Option<f64> {
+    match value {
+        "walk" => Some(5.0),
+        "GB:nsl_single" => Some(60.0 * MPH_TO_KMH),
+        "GB:nsl_dual" | "GB:motorway" => Some(70.0 * MPH_TO_KMH),
+        _ => {
+            let (_, zone) = value.split_once(':')?;
+            match zone {
+                "living_street" => Some(10.0),
+                "urban" => Some(50.0),
+                "rural" => Some(90.0),
+                "trunk" => Some(100.0),
+                "motorway" => Some(120.0),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn default_speed_kmh(highway: Option<&str>) -> f64 {
+    match highway.unwrap_or("") {
+        "motorway" => 110.0,
+        "trunk" => 90.0,
+        "primary" => 60.0,
+        "motorway_link" | "trunk_link" | "secondary" => 50.0,
+        "primary_link" | "secondary_link" | "tertiary" | "tertiary_link" | "unclassified"
+        | "road" => 40.0,
+        "residential" => 30.0,
+        "service" | "track" => 20.0,
+        "living_street" => 10.0,
+        "cycleway" | "path" | "bridleway" => 15.0,
+        "footway" | "pedestrian" | "steps" | "corridor" => 5.0,
+        _ => 30.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        assert_eq!(parse_maxspeed("50"), Some(50.0));
+        assert_eq!(parse_maxspeed(" 50 km/h"), Some(50.0));
+        assert_eq!(parse_maxspeed("30 mph"), Some(30.0 * MPH_TO_KMH));
+        assert_eq!(parse_maxspeed("30mph"), Some(30.0 * MPH_TO_KMH));
+        assert_eq!(parse_maxspeed("10 knots"), Some(18.52));
+    }
+
+    #[test]
+    fn test_implicit_zones() {
+        assert_eq!(parse_maxspeed("walk"), Some(5.0));
+        assert_eq!(parse_maxspeed("GB:nsl_single"), Some(60.0 * MPH_TO_KMH));
+        assert_eq!(parse_maxspeed("GB:nsl_dual"), Some(70.0 * MPH_TO_KMH));
+        assert_eq!(parse_maxspeed("DE:urban"), Some(50.0));
+        assert_eq!(parse_maxspeed("FR:rural"), Some(90.0));
+        assert_eq!(parse_maxspeed("RU:living_street"), Some(10.0));
+        assert_eq!(parse_maxspeed("DE:zone30"), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for value in [
+            "", "none", "signals", "0", "-30", "-30 mph", "0 knots", "-5 knots", "inf", "NaN",
+            "inf mph", "fast mph", "mph",
+        ] {
+            assert_eq!(parse_maxspeed(value), None, "{value} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_default_speeds() {
+        assert_eq!(default_speed_kmh(Some("motorway")), 110.0);
+        assert_eq!(default_speed_kmh(Some("footway")), 5.0);
+        assert_eq!(default_speed_kmh(None), 30.0);
+    }
+}
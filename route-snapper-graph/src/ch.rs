@@ -0,0 +1,699 @@
+//! A contraction hierarchy, so the route snapper can find paths without searching the whole graph.
+//!
+//! Importers build this once. Nodes are removed from the graph one at a time, least important
+//! first, adding a shortcut between the neighbours of each node whenever the cheapest path between
+//! them went through it. A search from both ends then only ever needs to move towards more
+//! important nodes, which visits a tiny part of the graph.
+//!
+//! The hierarchy is only valid for the default costs, without turn costs. The route snapper falls
+//! back to A* for anything else.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use anyhow::{bail, Result};
+use geo::HaversineLength;
+use serde::{Deserialize, Serialize};
+
+use crate::{EdgeID, NodeID, RouteSnapperMap};
+
+/// Searching for a path that makes a shortcut unnecessary gives up after settling this many nodes,
+/// adding the shortcut anyway. Higher limits make fewer shortcuts but take longer to build.
+const WITNESS_SEARCH_LIMIT: usize = 200;
+
+/// What's stored in the graph file. Everything else is worked out again from the graph by
+/// `prepare`, keeping files small.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ContractionHierarchy {
+    /// For every node, its place in the order nodes were contracted
+    rank: Vec<u32>,
+    /// Each shortcut as two earlier arcs. The arcs are the directed edges in `original_arcs`, then
+    /// these shortcuts.
+    shortcuts: Vec<(u32, u32)>,
+}
+
+/// A hierarchy ready to search.
+pub struct PreparedHierarchy {
+    arcs: Vec<Arc>,
+    /// Arcs leaving each node towards a more important node. Node `i` has
+    /// `up[up_offsets[i]..up_offsets[i + 1]]`.
+    up_offsets: Vec<u32>,
+    up: Vec<u32>,
+    /// Arcs arriving at each node from a more important node, stored the same way
+    down_offsets: Vec<u32>,
+    down: Vec<u32>,
+}
+
+#[derive(Clone)]
+struct Arc {
+    from: NodeID,
+    to: NodeID,
+    cost: f64,
+    via: Via,
+}
+
+#[derive(Clone, Copy)]
+enum Via {
+    /// An edge, and true if it's crossed forwards
+    Edge(EdgeID, bool),
+    /// Two earlier arcs, skipping over the node between them
+    Shortcut(u32, u32),
+}
+
+/// Builds a hierarchy for the default costs: `override_forward_costs` and
+/// `override_backward_costs`, or the length of each edge if those are empty.
+pub fn build(map: &RouteSnapperMap) -> ContractionHierarchy {
+    let num_nodes = map.nodes.len();
+    let arcs = original_arcs(map);
+    let num_original = arcs.len();
+
+    let mut builder = Builder {
+        outgoing: vec![Vec::new(); num_nodes],
+        incoming: vec![Vec::new(); num_nodes],
+        contracted: vec![false; num_nodes],
+        contracted_neighbours: vec![0; num_nodes],
+        distances: vec![f64::INFINITY; num_nodes],
+        arcs,
+    };
+    for (idx, arc) in builder.arcs.iter().enumerate() {
+        builder.outgoing[arc.from.0 as usize].push(idx as u32);
+        builder.incoming[arc.to.0 as usize].push(idx as u32);
+    }
+
+    // Contract the node with the lowest priority next. Priorities only go up as neighbours are
+    // contracted, so they're recalculated lazily when a node reaches the front of the queue.
+    let mut queue = BinaryHeap::new();
+    for node in 0..num_nodes {
+        let node = NodeID(node as u32);
+        queue.push(Item {
+            cost: builder.priority(node),
+            node,
+        });
+    }
+    let mut rank = vec![0; num_nodes];
+    let mut next_rank = 0;
+    while let Some(item) = queue.pop() {
+        let priority = builder.priority(item.node);
+        if queue.peek().is_some_and(|next| priority > next.cost) {
+            queue.push(Item {
+                cost: priority,
+                node: item.node,
+            });
+            continue;
+        }
+        builder.contract(item.node);
+        rank[item.node.0 as usize] = next_rank;
+        next_rank += 1;
+    }
+
+    ContractionHierarchy {
+        rank,
+        shortcuts: builder.arcs[num_original..]
+            .iter()
+            .map(|arc| match arc.via {
+                Via::Shortcut(first, second) => (first, second),
+                Via::Edge(..) => unreachable!(),
+            })
+            .collect(),
+    }
+}
+
+// Every routable direction of every edge, using the default costs
+fn original_arcs(map: &RouteSnapperMap) -> Vec<Arc> {
+    let mut arcs = Vec::new();
+    for (idx, edge) in map.edges.iter().enumerate() {
+        // Loops are never part of the cheapest path
+        if edge.node1 == edge.node2 {
+            continue;
+        }
+        let length = edge.geometry.haversine_length();
+        for (forwards, costs, from, to) in [
+            (true, &map.override_forward_costs, edge.node1, edge.node2),
+            (false, &map.override_backward_costs, edge.node2, edge.node1),
+        ] {
+            let cost = if costs.is_empty() {
+                Some(length)
+            } else {
+                costs.get(idx).copied().flatten()
+            };
+            if let Some(cost) = cost {
+                arcs.push(Arc {
+                    from,
+                    to,
+                    cost,
+                    via: Via::Edge(EdgeID(idx as u32), forwards),
+                });
+            }
+        }
+    }
+    arcs
+}
+
+// Lists the arcs for each node, as offsets into one list
+fn group_arcs(
+    num_nodes: usize,
+    arcs: &[Arc],
+    node_for: impl Fn(&Arc) -> Option<NodeID>,
+) -> (Vec<u32>, Vec<u32>) {
+    let mut per_node = vec![Vec::new(); num_nodes];
+    for (idx, arc) in arcs.iter().enumerate() {
+        if let Some(arcs) = node_for(arc).and_then(|node| per_node.get_mut(node.0 as usize)) {
+            arcs.push(idx as u32);
+        }
+    }
+    let mut offsets = vec![0];
+    let mut list = Vec::new();
+    for arcs in per_node {
+        list.extend(arcs);
+        offsets.push(list.len() as u32);
+    }
+    (offsets, list)
+}
+
+struct Builder {
+    arcs: Vec<Arc>,
+    outgoing: Vec<Vec<u32>>,
+    incoming: Vec<Vec<u32>>,
+    contracted: Vec<bool>,
+    contracted_neighbours: Vec<usize>,
+    // Reused by every witness search; infinite except during one
+    distances: Vec<f64>,
+}
+
+impl Builder {
+    // Prefer nodes that add few shortcuts compared to the arcs they remove, and spread contraction
+    // evenly instead of eating into one area
+    fn priority(&mut self, node: NodeID) -> f64 {
+        let removed = self.live_arcs(&self.incoming[node.0 as usize]).count()
+            + self.live_arcs(&self.outgoing[node.0 as usize]).count();
+        let added = self.shortcuts(node).len();
+        added as f64 - removed as f64 + self.contracted_neighbours[node.0 as usize] as f64
+    }
+
+    fn contract(&mut self, node: NodeID) {
+        for (arc1, arc2) in self.shortcuts(node) {
+            let (from, to) = (self.arcs[arc1 as usize].from, self.arcs[arc2 as usize].to);
+            let idx = self.arcs.len() as u32;
+            self.arcs.push(Arc {
+                from,
+                to,
+                cost: self.arcs[arc1 as usize].cost + self.arcs[arc2 as usize].cost,
+                via: Via::Shortcut(arc1, arc2),
+            });
+            self.outgoing[from.0 as usize].push(idx);
+            self.incoming[to.0 as usize].push(idx);
+        }
+
+        let neighbours: Vec<NodeID> = self
+            .live_arcs(&self.incoming[node.0 as usize])
+            .map(|arc| arc.from)
+            .chain(
+                self.live_arcs(&self.outgoing[node.0 as usize])
+                    .map(|arc| arc.to),
+            )
+            .collect();
+        self.contracted[node.0 as usize] = true;
+        for neighbour in neighbours {
+            self.contracted_neighbours[neighbour.0 as usize] += 1;
+        }
+    }
+
+    // Arcs whose ends haven't been contracted yet
+    fn live_arcs<'a>(&'a self, arcs: &'a [u32]) -> impl Iterator<Item = &'a Arc> {
+        arcs.iter()
+            .map(|idx| &self.arcs[*idx as usize])
+            .filter(|arc| {
+                !self.contracted[arc.from.0 as usize] && !self.contracted[arc.to.0 as usize]
+            })
+    }
+
+    // The cheapest live arc to each neighbour
+    fn cheapest_arcs(&self, arcs: &[u32], other_end: impl Fn(&Arc) -> NodeID) -> Vec<u32> {
+        let mut cheapest: HashMap<NodeID, u32> = HashMap::new();
+        for idx in arcs {
+            let arc = &self.arcs[*idx as usize];
+            if self.contracted[arc.from.0 as usize] || self.contracted[arc.to.0 as usize] {
+                continue;
+            }
+            let entry = cheapest.entry(other_end(arc)).or_insert(*idx);
+            if arc.cost < self.arcs[*entry as usize].cost {
+                *entry = *idx;
+            }
+        }
+        let mut result: Vec<u32> = cheapest.into_values().collect();
+        // Keep shortcuts in a stable order, so the same graph always builds the same hierarchy
+        result.sort();
+        result
+    }
+
+    // The pairs of arcs into and out of `node` that need a shortcut when it's contracted
+    fn shortcuts(&mut self, node: NodeID) -> Vec<(u32, u32)> {
+        let incoming = self.cheapest_arcs(&self.incoming[node.0 as usize], |arc| arc.from);
+        let outgoing = self.cheapest_arcs(&self.outgoing[node.0 as usize], |arc| arc.to);
+        let Some(max_out) = outgoing
+            .iter()
+            .map(|idx| self.arcs[*idx as usize].cost)
+            .max_by(f64::total_cmp)
+        else {
+            return Vec::new();
+        };
+
+        let mut shortcuts = Vec::new();
+        for arc1 in incoming {
+            let (from, cost1) = (self.arcs[arc1 as usize].from, self.arcs[arc1 as usize].cost);
+            let touched = self.witness_search(from, node, cost1 + max_out);
+            for arc2 in &outgoing {
+                let arc2_ref = &self.arcs[*arc2 as usize];
+                if arc2_ref.to != from
+                    && self.distances[arc2_ref.to.0 as usize] > cost1 + arc2_ref.cost
+                {
+                    shortcuts.push((arc1, *arc2));
+                }
+            }
+            for idx in touched {
+                self.distances[idx] = f64::INFINITY;
+            }
+        }
+        shortcuts
+    }
+
+    // Dijkstra from `start` without going through `skip`, filling in `distances` up to
+    // `max_cost`. Returns the nodes it set, to reset after.
+    fn witness_search(&mut self, start: NodeID, skip: NodeID, max_cost: f64) -> Vec<usize> {
+        let mut touched = vec![start.0 as usize];
+        self.distances[start.0 as usize] = 0.0;
+        let mut queue = BinaryHeap::new();
+        queue.push(Item {
+            cost: 0.0,
+            node: start,
+        });
+        let mut settled = 0;
+        while let Some(current) = queue.pop() {
+            if current.cost > self.distances[current.node.0 as usize] {
+                continue;
+            }
+            settled += 1;
+            if current.cost > max_cost || settled > WITNESS_SEARCH_LIMIT {
+                break;
+            }
+            for idx in &self.outgoing[current.node.0 as usize] {
+                let arc = &self.arcs[*idx as usize];
+                if arc.to == skip || self.contracted[arc.to.0 as usize] {
+                    continue;
+                }
+                let cost = current.cost + arc.cost;
+                let to = arc.to.0 as usize;
+                if cost < self.distances[to] {
+                    if self.distances[to] == f64::INFINITY {
+                        touched.push(to);
+                    }
+                    self.distances[to] = cost;
+                    queue.push(Item { cost, node: arc.to });
+                }
+            }
+        }
+        touched
+    }
+}
+
+impl ContractionHierarchy {
+    /// Rebuilds the arcs of the hierarchy from the graph it was built for, checking they fit.
+    pub fn prepare(&self, map: &RouteSnapperMap) -> Result<PreparedHierarchy> {
+        let num_nodes = map.nodes.len();
+        if self.rank.len() != num_nodes {
+            bail!(
+                "the hierarchy has {} nodes, but the graph has {num_nodes}",
+                self.rank.len()
+            );
+        }
+        let mut arcs = original_arcs(map);
+        if arcs
+            .iter()
+            .any(|arc| arc.from.0 as usize >= num_nodes || arc.to.0 as usize >= num_nodes)
+        {
+            bail!("an edge refers to a node that doesn't exist");
+        }
+        for (first, second) in &self.shortcuts {
+            let idx = arcs.len();
+            let (Some(first_arc), Some(second_arc)) =
+                (arcs.get(*first as usize), arcs.get(*second as usize))
+            else {
+                bail!("shortcut {idx} isn't made of earlier arcs");
+            };
+            if first_arc.to != second_arc.from {
+                bail!("shortcut {idx} joins arcs that don't meet");
+            }
+            if !(first_arc.cost + second_arc.cost).is_finite() {
+                bail!("shortcut {idx} has a cost that isn't finite");
+            }
+            arcs.push(Arc {
+                from: first_arc.from,
+                to: second_arc.to,
+                cost: first_arc.cost + second_arc.cost,
+                via: Via::Shortcut(*first, *second),
+            });
+        }
+        if let Some(arc) = arcs
+            .iter()
+            .find(|arc| !arc.cost.is_finite() || arc.cost < 0.0)
+        {
+            bail!("an edge has cost {}", arc.cost);
+        }
+
+        let rank = &self.rank;
+        let (up_offsets, up) = group_arcs(num_nodes, &arcs, |arc| {
+            (rank[arc.to.0 as usize] > rank[arc.from.0 as usize]).then_some(arc.from)
+        });
+        let (down_offsets, down) = group_arcs(num_nodes, &arcs, |arc| {
+            (rank[arc.from.0 as usize] > rank[arc.to.0 as usize]).then_some(arc.to)
+        });
+        Ok(PreparedHierarchy {
+            arcs,
+            up_offsets,
+            up,
+            down_offsets,
+            down,
+        })
+    }
+
+    /// The number of shortcuts added while building
+    pub fn num_shortcuts(&self) -> usize {
+        self.shortcuts.len()
+    }
+}
+
+impl PreparedHierarchy {
+    /// Finds the cheapest path between two nodes. Returns its cost and every edge along it, with
+    /// true if the edge is crossed forwards.
+    pub fn pathfind(&self, start: NodeID, end: NodeID) -> Option<(f64, Vec<(EdgeID, bool)>)> {
+        let num_nodes = self.up_offsets.len() - 1;
+        if start.0 as usize >= num_nodes || end.0 as usize >= num_nodes {
+            return None;
+        }
+        let mut forwards = Search::new(start);
+        let mut backwards = Search::new(end);
+        // (cost, the node where both searches meet)
+        let mut best: Option<(f64, NodeID)> = None;
+
+        loop {
+            // Advance whichever search has the cheaper next node. Once that's no cheaper than the
+            // best path so far, neither search can improve on it.
+            let (search, other, offsets, list, is_forwards) =
+                match (forwards.next_cost(), backwards.next_cost()) {
+                    (None, None) => break,
+                    (Some(f), b) if b.is_none_or(|b| f <= b) => {
+                        (&mut forwards, &backwards, &self.up_offsets, &self.up, true)
+                    }
+                    _ => (
+                        &mut backwards,
+                        &forwards,
+                        &self.down_offsets,
+                        &self.down,
+                        false,
+                    ),
+                };
+            let Some((cost, node)) = search.pop() else {
+                continue;
+            };
+            if best.is_some_and(|(best, _)| cost >= best) {
+                break;
+            }
+            if let Some((other_cost, _)) = other.best.get(&node) {
+                if best.is_none_or(|(best, _)| cost + other_cost < best) {
+                    best = Some((cost + other_cost, node));
+                }
+            }
+
+            let idx = node.0 as usize;
+            for arc_idx in &list[offsets[idx] as usize..offsets[idx + 1] as usize] {
+                let arc = &self.arcs[*arc_idx as usize];
+                let next = if is_forwards { arc.to } else { arc.from };
+                search.relax(next, cost + arc.cost, *arc_idx);
+            }
+        }
+
+        let (cost, meet) = best?;
+        let mut arcs = Vec::new();
+        let mut at = meet;
+        while let Some((_, Some(arc))) = forwards.best.get(&at) {
+            arcs.push(*arc);
+            at = self.arcs[*arc as usize].from;
+        }
+        arcs.reverse();
+        let mut at = meet;
+        while let Some((_, Some(arc))) = backwards.best.get(&at) {
+            arcs.push(*arc);
+            at = self.arcs[*arc as usize].to;
+        }
+
+        let mut path = Vec::new();
+        for arc in arcs {
+            let mut stack = vec![arc];
+            while let Some(arc) = stack.pop() {
+                match self.arcs[arc as usize].via {
+                    Via::Edge(edge, forwards) => path.push((edge, forwards)),
+                    Via::Shortcut(first, second) => {
+                        stack.push(second);
+                        stack.push(first);
+                    }
+                }
+            }
+        }
+        Some((cost, path))
+    }
+}
+
+// One direction of a search
+struct Search {
+    // The cheapest cost found to each node, and the arc used to get there
+    best: HashMap<NodeID, (f64, Option<u32>)>,
+    queue: BinaryHeap<Item>,
+}
+
+impl Search {
+    fn new(start: NodeID) -> Self {
+        let mut best = HashMap::new();
+        best.insert(start, (0.0, None));
+        let mut queue = BinaryHeap::new();
+        queue.push(Item {
+            cost: 0.0,
+            node: start,
+        });
+        Self { best, queue }
+    }
+
+    // Skips stale entries
+    fn next_cost(&mut self) -> Option<f64> {
+        while let Some(item) = self.queue.peek() {
+            if item.cost > self.best[&item.node].0 {
+                self.queue.pop();
+            } else {
+                return Some(item.cost);
+            }
+        }
+        None
+    }
+
+    fn pop(&mut self) -> Option<(f64, NodeID)> {
+        self.next_cost()?;
+        self.queue.pop().map(|item| (item.cost, item.node))
+    }
+
+    fn relax(&mut self, node: NodeID, cost: f64, arc: u32) {
+        if self.best.get(&node).is_some_and(|(x, _)| *x <= cost) {
+            return;
+        }
+        self.best.insert(node, (cost, Some(arc)));
+        self.queue.push(Item { cost, node });
+    }
+}
+
+struct Item {
+    cost: f64,
+    node: NodeID,
+}
+
+// BinaryHeap is a max-heap, so order by lowest cost first, breaking ties by node so results don't
+// depend on insertion order
+impl Ord for Item {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Item {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Item {}
+
+#[cfg(test)]
+mod tests {
+    use geo::Coord;
+
+    use super::*;
+    use crate::test_util::make_map;
+
+    // A 4x4 grid of streets
+    fn grid() -> RouteSnapperMap {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        for y in 0..4 {
+            for x in 0..4 {
+                nodes.push(Coord {
+                    x: x as f64 * 0.001,
+                    y: 51.5 + y as f64 * 0.001,
+                });
+                let idx = y * 4 + x;
+                if x > 0 {
+                    edges.push((idx - 1, idx));
+                }
+                if y > 0 {
+                    edges.push((idx - 4, idx));
+                }
+            }
+        }
+        make_map(nodes, &edges)
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut map = grid();
+        // Make some streets one-way, and one much slower
+        map.override_forward_costs = map
+            .edges
+            .iter()
+            .map(|e| Some(e.geometry.haversine_length()))
+            .collect();
+        map.override_backward_costs = map.override_forward_costs.clone();
+        map.override_backward_costs[0] = None;
+        map.override_backward_costs[5] = None;
+        map.override_forward_costs[3] = Some(10_000.0);
+
+        let ch = build(&map).prepare(&map).unwrap();
+        let cost = |edge: EdgeID, forwards: bool| {
+            if forwards {
+                map.override_forward_costs[edge.0 as usize]
+            } else {
+                map.override_backward_costs[edge.0 as usize]
+            }
+        };
+        for start in 0..16 {
+            let expected = dijkstra(&map, NodeID(start));
+            for end in 0..16 {
+                let (total, path) = ch.pathfind(NodeID(start), NodeID(end)).unwrap();
+                assert!((total - expected[end as usize]).abs() < 1e-6);
+
+                // The path is connected and adds up to the cost
+                let mut at = NodeID(start);
+                let mut sum = 0.0;
+                for (edge, forwards) in path {
+                    let e = map.edge(edge);
+                    let (from, to) = if forwards {
+                        (e.node1, e.node2)
+                    } else {
+                        (e.node2, e.node1)
+                    };
+                    assert_eq!(from, at);
+                    at = to;
+                    sum += cost(edge, forwards).unwrap();
+                }
+                assert_eq!(at, NodeID(end));
+                assert!((sum - total).abs() < 1e-6);
+            }
+        }
+    }
+
+    // The cost from `start` to every node
+    fn dijkstra(map: &RouteSnapperMap, start: NodeID) -> Vec<f64> {
+        let mut arcs_from: Vec<Vec<Arc>> = (0..map.nodes.len()).map(|_| Vec::new()).collect();
+        for arc in original_arcs(map) {
+            arcs_from[arc.from.0 as usize].push(arc);
+        }
+        let mut costs = vec![f64::INFINITY; map.nodes.len()];
+        costs[start.0 as usize] = 0.0;
+        let mut queue = BinaryHeap::new();
+        queue.push(Item {
+            cost: 0.0,
+            node: start,
+        });
+        while let Some(Item { cost, node }) = queue.pop() {
+            if cost > costs[node.0 as usize] {
+                continue;
+            }
+            for arc in &arcs_from[node.0 as usize] {
+                let cost = cost + arc.cost;
+                if cost < costs[arc.to.0 as usize] {
+                    costs[arc.to.0 as usize] = cost;
+                    queue.push(Item { cost, node: arc.to });
+                }
+            }
+        }
+        costs
+    }
+
+    #[test]
+    fn test_real_graph() {
+        let bytes = std::fs::read("../examples/southwark.bin").unwrap();
+        let map = RouteSnapperMap::from_bytes(&bytes).unwrap();
+        let mut map_with_ch = RouteSnapperMap::from_bytes(&bytes).unwrap();
+        map_with_ch.contraction_hierarchy = Some(build(&map));
+        // The hierarchy survives serialization
+        let map_with_ch = RouteSnapperMap::from_bytes(&map_with_ch.to_bytes().unwrap()).unwrap();
+        let ch = map_with_ch
+            .contraction_hierarchy
+            .as_ref()
+            .unwrap()
+            .prepare(&map)
+            .unwrap();
+
+        for start in [10, 20, 30, 40, 50] {
+            let expected = dijkstra(&map, NodeID(start));
+            for end in [10, 20, 30, 40, 50] {
+                match ch.pathfind(NodeID(start), NodeID(end)) {
+                    Some((total, _)) => {
+                        assert!((total - expected[end as usize]).abs() < 1e-6)
+                    }
+                    None => assert!(expected[end as usize].is_infinite()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_broken() {
+        let map = grid();
+        let ch = build(&map);
+        assert!(ch
+            .prepare(&map)
+            .unwrap()
+            .pathfind(NodeID(0), NodeID(16))
+            .is_none());
+
+        // Different nodes
+        let mut other = grid();
+        other.nodes.pop();
+        assert!(ch.prepare(&other).is_err());
+
+        // An edge pointing past the last node
+        let mut other = grid();
+        other.edges[0].node2 = NodeID(100);
+        assert!(ch.prepare(&other).is_err());
+
+        // A shortcut that doesn't join up
+        let mut ch = build(&map);
+        ch.shortcuts.push((0, 0));
+        assert!(ch.prepare(&map).is_err());
+        ch.shortcuts.pop();
+        ch.shortcuts.push((0, 1_000_000));
+        assert!(ch.prepare(&map).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! The layout used since format v8. Coordinates are stored as the difference from the previous
+//! point, names are stored once in a table, and integers are varints, so most values only take a
+//! byte or two. The result can also be compressed.
+//!
+//! Fields added after v8 are written after `CompactMap`, so older graphs just end sooner.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::{bail, Result};
+use bincode::Options;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use geo::{Coord, LineString};
+use serde::{Deserialize, Serialize};
+
+use crate::ch::ContractionHierarchy;
+use crate::{
+    deserialize_f64, serialize_f64, AttributeTable, CostProfile, Edge, NodeID, RouteSnapperMap,
+    TileInfo, Turn,
+};
+
+/// How `RouteSnapperMap::to_bytes_with` compresses a graph. Reading a graph works out which was
+/// used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// DEFLATE, which usually makes graphs about half the size and is quick to decompress
+    Deflate,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactMap<'a> {
+    /// Each node as the difference from the previous one
+    nodes: Vec<(i32, i32)>,
+    /// Every distinct edge name
+    names: Vec<String>,
+    edges: Vec<CompactEdge>,
+    override_forward_costs: Cow<'a, [Option<f64>]>,
+    override_backward_costs: Cow<'a, [Option<f64>]>,
+    attributes: Cow<'a, AttributeTable>,
+    cost_profiles: Cow<'a, [CostProfile]>,
+    turns: Cow<'a, [Turn]>,
+    u_turn_cost: f64,
+    costs_in_seconds: bool,
+    node_elevations: Cow<'a, [f32]>,
+    /// Each ID as the difference from the previous one. Neighbouring ways often have close IDs.
+    osm_way_ids: Vec<i64>,
+    /// Each ID as the difference from the previous node that has one
+    osm_node_ids: Vec<Option<i64>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactEdge {
+    node1: NodeID,
+    node2: NodeID,
+    /// One more than the index into `names`, or 0 if the edge has no name
+    name: u32,
+    /// Each point as the difference from the previous one, starting from `node1`
+    geometry: Vec<(i32, i32)>,
+}
+
+/// Since v9
+#[derive(Serialize, Deserialize)]
+struct CompactTile {
+    size_degrees: f64,
+    x: i32,
+    y: i32,
+    /// Each ID as the difference from the previous one. They're usually in increasing order.
+    node_ids: Vec<u32>,
+    edge_ids: Vec<u32>,
+}
+
+/// Varints for every integer, and no limit on size
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+pub fn encode(map: &RouteSnapperMap, compression: Compression) -> Result<Vec<u8>> {
+    let node_ints: Vec<(i32, i32)> = map.nodes.iter().map(|pt| to_ints(*pt)).collect();
+
+    let mut names = Vec::new();
+    let mut name_ids: HashMap<&str, u32> = HashMap::new();
+    let edges = map
+        .edges
+        .iter()
+        .map(|edge| {
+            let name = match &edge.name {
+                Some(name) => *name_ids.entry(name.as_str()).or_insert_with(|| {
+                    names.push(name.clone());
+                    names.len() as u32
+                }),
+                None => 0,
+            };
+            CompactEdge {
+                node1: edge.node1,
+                node2: edge.node2,
+                name,
+                geometry: deltas(
+                    node_ints[edge.node1.0 as usize],
+                    edge.geometry.coords().map(|pt| to_ints(*pt)),
+                ),
+            }
+        })
+        .collect();
+
+    let mut previous: i64 = 0;
+    let osm_node_ids = map
+        .osm_node_ids
+        .iter()
+        .map(|id| {
+            id.map(|id| {
+                let delta = id.wrapping_sub(previous);
+                previous = id;
+                delta
+            })
+        })
+        .collect();
+
+    let compact = CompactMap {
+        nodes: deltas((0, 0), node_ints.into_iter()),
+        names,
+        edges,
+        override_forward_costs: Cow::Borrowed(&map.override_forward_costs),
+        override_backward_costs: Cow::Borrowed(&map.override_backward_costs),
+        attributes: Cow::Borrowed(&map.attributes),
+        cost_profiles: Cow::Borrowed(&map.cost_profiles),
+        turns: Cow::Borrowed(&map.turns),
+        u_turn_cost: map.u_turn_cost,
+        costs_in_seconds: map.costs_in_seconds,
+        node_elevations: Cow::Borrowed(&map.node_elevations),
+        osm_way_ids: map
+            .osm_way_ids
+            .iter()
+            .scan(0i64, |previous, id| {
+                let delta = id.wrapping_sub(*previous);
+                *previous = *id;
+                Some(delta)
+            })
+            .collect(),
+        osm_node_ids,
+    };
+
+    let tile = map.tile.as_ref().map(|tile| CompactTile {
+        size_degrees: tile.size_degrees,
+        x: tile.x,
+        y: tile.y,
+        node_ids: id_deltas(&tile.node_ids),
+        edge_ids: id_deltas(&tile.edge_ids),
+    });
+
+    let mut bytes = Vec::new();
+    match compression {
+        Compression::None => {
+            bytes.push(0);
+            bincode_options().serialize_into(&mut bytes, &compact)?;
+            bincode_options().serialize_into(&mut bytes, &tile)?;
+            bincode_options().serialize_into(&mut bytes, &map.contraction_hierarchy)?;
+        }
+        Compression::Deflate => {
+            bytes.push(1);
+            let mut encoder = DeflateEncoder::new(bytes, flate2::Compression::best());
+            bincode_options().serialize_into(&mut encoder, &compact)?;
+            bincode_options().serialize_into(&mut encoder, &tile)?;
+            bincode_options().serialize_into(&mut encoder, &map.contraction_hierarchy)?;
+            bytes = encoder.finish()?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Reads format v8 or later.
+pub fn decode(payload: &[u8], version: u32) -> Result<RouteSnapperMap> {
+    let Some((compression, rest)) = payload.split_first() else {
+        bail!("Graph file is truncated; it ends in the middle of the header");
+    };
+    let decompressed;
+    let body = match compression {
+        0 => rest,
+        1 => {
+            let mut buffer = Vec::new();
+            DeflateDecoder::new(rest).read_to_end(&mut buffer)?;
+            decompressed = buffer;
+            &decompressed
+        }
+        x => bail!("Graph uses unknown compression {x}"),
+    };
+    let mut reader = body;
+    let compact: CompactMap = bincode_options()
+        .allow_trailing_bytes()
+        .deserialize_from(&mut reader)?;
+    let tile: Option<CompactTile> = if version >= 9 {
+        bincode_options()
+            .allow_trailing_bytes()
+            .deserialize_from(&mut reader)?
+    } else {
+        None
+    };
+    let contraction_hierarchy: Option<ContractionHierarchy> = if version >= 10 {
+        bincode_options()
+            .allow_trailing_bytes()
+            .deserialize_from(&mut reader)?
+    } else {
+        None
+    };
+    if !reader.is_empty() {
+        bail!("Graph has {} unexpected bytes at the end", reader.len());
+    }
+
+    let node_ints = undo_deltas((0, 0), &compact.nodes);
+    let mut edges = Vec::new();
+    for edge in compact.edges {
+        let Some(start) = node_ints.get(edge.node1.0 as usize) else {
+            bail!("Graph has an edge using a node that doesn't exist");
+        };
+        if edge.node2.0 as usize >= node_ints.len() {
+            bail!("Graph has an edge using a node that doesn't exist");
+        }
+        let name = match edge.name {
+            0 => None,
+            idx => match compact.names.get(idx as usize - 1) {
+                Some(name) => Some(name.clone()),
+                None => bail!("Graph has an edge using a name that doesn't exist"),
+            },
+        };
+        edges.push(Edge {
+            node1: edge.node1,
+            node2: edge.node2,
+            geometry: LineString::new(
+                undo_deltas(*start, &edge.geometry)
+                    .into_iter()
+                    .map(from_ints)
+                    .collect(),
+            ),
+            name,
+
+            length_meters: 0.0,
+            forward_cost: None,
+            backward_cost: None,
+        });
+    }
+
+    for turn in compact.turns.iter() {
+        if turn.from.0 as usize >= edges.len()
+            || turn.to.0 as usize >= edges.len()
+            || turn.via.0 as usize >= node_ints.len()
+        {
+            bail!("Graph has a turn using an edge or node that doesn't exist");
+        }
+    }
+
+    let mut previous: i64 = 0;
+    let osm_node_ids = compact
+        .osm_node_ids
+        .into_iter()
+        .map(|delta| {
+            delta.map(|delta| {
+                previous = previous.wrapping_add(delta);
+                previous
+            })
+        })
+        .collect();
+
+    Ok(RouteSnapperMap {
+        nodes: node_ints.into_iter().map(from_ints).collect(),
+        edges,
+        override_forward_costs: compact.override_forward_costs.into_owned(),
+        override_backward_costs: compact.override_backward_costs.into_owned(),
+        attributes: compact.attributes.into_owned(),
+        cost_profiles: compact.cost_profiles.into_owned(),
+        turns: compact.turns.into_owned(),
+        u_turn_cost: compact.u_turn_cost,
+        costs_in_seconds: compact.costs_in_seconds,
+        node_elevations: compact.node_elevations.into_owned(),
+        osm_way_ids: compact
+            .osm_way_ids
+            .into_iter()
+            .scan(0i64, |previous, delta| {
+                *previous = previous.wrapping_add(delta);
+                Some(*previous)
+            })
+            .collect(),
+        osm_node_ids,
+        tile: tile.map(|tile| TileInfo {
+            size_degrees: tile.size_degrees,
+            x: tile.x,
+            y: tile.y,
+            node_ids: undo_id_deltas(&tile.node_ids),
+            edge_ids: undo_id_deltas(&tile.edge_ids),
+        }),
+        contraction_hierarchy,
+    })
+}
+
+fn to_ints(pt: Coord) -> (i32, i32) {
+    (serialize_f64(pt.x), serialize_f64(pt.y))
+}
+
+fn from_ints((x, y): (i32, i32)) -> Coord {
+    Coord {
+        x: deserialize_f64(x),
+        y: deserialize_f64(y),
+    }
+}
+
+fn deltas(start: (i32, i32), pts: impl Iterator<Item = (i32, i32)>) -> Vec<(i32, i32)> {
+    let mut previous = start;
+    pts.map(|pt| {
+        let delta = (pt.0.wrapping_sub(previous.0), pt.1.wrapping_sub(previous.1));
+        previous = pt;
+        delta
+    })
+    .collect()
+}
+
+fn undo_deltas(start: (i32, i32), deltas: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let mut previous = start;
+    deltas
+        .iter()
+        .map(|delta| {
+            previous = (
+                previous.0.wrapping_add(delta.0),
+                previous.1.wrapping_add(delta.1),
+            );
+            previous
+        })
+        .collect()
+}
+
+fn id_deltas(ids: &[u32]) -> Vec<u32> {
+    let mut previous = 0;
+    ids.iter()
+        .map(|id| {
+            let delta = id.wrapping_sub(previous);
+            previous = *id;
+            delta
+        })
+        .collect()
+}
+
+fn undo_id_deltas(deltas: &[u32]) -> Vec<u32> {
+    let mut previous: u32 = 0;
+    deltas
+        .iter()
+        .map(|delta| {
+            previous = previous.wrapping_add(*delta);
+            previous
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::make_map;
+    use crate::{EdgeID, FORMAT_VERSION};
+
+    #[test]
+    fn test_bad_references() {
+        let mut map = make_map(
+            vec![Coord { x: 0.0, y: 0.0 }, Coord { x: 0.001, y: 0.0 }],
+            &[(0, 1), (1, 0)],
+        );
+        map.turns.push(Turn {
+            from: EdgeID(0),
+            via: NodeID(1),
+            to: EdgeID(1),
+            cost: None,
+        });
+        let decode = |map: &RouteSnapperMap| {
+            let bytes = encode(map, Compression::None).unwrap();
+            decode(&bytes, FORMAT_VERSION).map(|_| ())
+        };
+        assert!(decode(&map).is_ok());
+
+        map.edges[1].node2 = NodeID(2);
+        let err = decode(&map).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Graph has an edge using a node that doesn't exist"
+        );
+        map.edges[1].node2 = NodeID(0);
+
+        for (from, via, to) in [(0, 2, 1), (2, 1, 1), (0, 1, 2)] {
+            map.turns[0] = Turn {
+                from: EdgeID(from),
+                via: NodeID(via),
+                to: EdgeID(to),
+                cost: None,
+            };
+            let err = decode(&map).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Graph has a turn using an edge or node that doesn't exist"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let legacy_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+        let map = RouteSnapperMap::from_bytes(&legacy_bytes).unwrap();
+        let plain = map.to_bytes().unwrap();
+        let compressed = map.to_bytes_with(Compression::Deflate).unwrap();
+        assert!(plain.len() < legacy_bytes.len());
+        assert!(compressed.len() < plain.len());
+
+        // Coordinates are already rounded in the original, so nothing changes
+        for bytes in [plain, compressed] {
+            let copy = RouteSnapperMap::from_bytes(&bytes).unwrap();
+            assert_eq!(copy.nodes, map.nodes);
+            assert_eq!(copy.edges.len(), map.edges.len());
+            for (a, b) in copy.edges.iter().zip(&map.edges) {
+                assert_eq!((a.node1, a.node2), (b.node1, b.node2));
+                assert_eq!(a.geometry, b.geometry);
+                assert_eq!(a.name, b.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Finding parts of the graph that aren't connected to each other, for importers to clean up.
+
+use std::collections::{HashMap, HashSet};
+
+use geo::{BoundingRect, HaversineLength, MultiPoint, Point, Rect};
+
+use crate::{EdgeID, RouteSnapperMap};
+
+/// A set of edges connected to each other, ignoring direction, but not to anything else.
+pub struct Component {
+    pub edges: Vec<EdgeID>,
+    pub length_meters: f64,
+    /// Where the component is, in WGS84
+    pub bounds: Rect,
+}
+
+impl std::fmt::Display for Component {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let center = self.bounds.center();
+        write!(
+            f,
+            "{} edges ({:.0}m) around {:.6}, {:.6}",
+            self.edges.len(),
+            self.length_meters,
+            center.x,
+            center.y
+        )
+    }
+}
+
+/// Returns every connected component, largest (by number of edges) first.
+pub fn find_components(map: &RouteSnapperMap) -> Vec<Component> {
+    // Union-find over nodes
+    let mut parent: Vec<usize> = (0..map.nodes.len()).collect();
+    fn root(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+    for edge in &map.edges {
+        let a = root(&mut parent, edge.node1.0 as usize);
+        let b = root(&mut parent, edge.node2.0 as usize);
+        parent[a] = b;
+    }
+
+    let mut edges_per_root: HashMap<usize, Vec<EdgeID>> = HashMap::new();
+    for (idx, edge) in map.edges.iter().enumerate() {
+        edges_per_root
+            .entry(root(&mut parent, edge.node1.0 as usize))
+            .or_default()
+            .push(EdgeID(idx as u32));
+    }
+
+    let mut components: Vec<Component> = edges_per_root
+        .into_values()
+        .map(|mut edges| {
+            edges.sort();
+            let length_meters = edges
+                .iter()
+                .map(|id| map.edge(*id).geometry.haversine_length())
+                .sum();
+            // Include the nodes, in case some geometry is empty
+            let bounds = edges
+                .iter()
+                .flat_map(|id| {
+                    let edge = map.edge(*id);
+                    [map.node(edge.node1), map.node(edge.node2)]
+                        .into_iter()
+                        .chain(edge.geometry.coords().cloned())
+                })
+                .map(Point::from)
+                .collect::<MultiPoint>()
+                .bounding_rect()
+                .unwrap();
+            Component {
+                edges,
+                length_meters,
+                bounds,
+            }
+        })
+        .collect();
+    // Break ties by the first edge, so the result is deterministic
+    components.sort_by_key(|c| (std::cmp::Reverse(c.edges.len()), c.edges[0]));
+    components
+}
+
+/// Removes every component with fewer than `min_edges` edges, except for the largest one. Returns
+/// the removed components, whose edge IDs refer to the graph before removal.
+pub fn prune_components(map: &mut RouteSnapperMap, min_edges: usize) -> Vec<Component> {
+    let removed: Vec<Component> = find_components(map)
+        .into_iter()
+        .skip(1)
+        .filter(|c| c.edges.len() < min_edges)
+        .collect();
+    let remove: HashSet<EdgeID> = removed
+        .iter()
+        .flat_map(|c| c.edges.iter().cloned())
+        .collect();
+    map.remove_edges(&remove);
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::make_map;
+    use crate::{NodeID, Turn};
+    use geo::Coord;
+
+    // A line of 3 edges, another of 2 edges, and a lone edge, with edge IDs interleaved
+    fn three_parts() -> RouteSnapperMap {
+        let nodes = (0..9)
+            .map(|i| Coord {
+                x: 0.001 * i as f64,
+                y: 0.0,
+            })
+            .collect();
+        let mut map = make_map(nodes, &[(0, 1), (4, 5), (1, 2), (7, 8), (2, 3), (5, 6)]);
+        map.turns = vec![
+            Turn {
+                from: EdgeID(2),
+                via: NodeID(2),
+                to: EdgeID(4),
+                cost: Some(1.0),
+            },
+            Turn {
+                from: EdgeID(1),
+                via: NodeID(5),
+                to: EdgeID(5),
+                cost: None,
+            },
+        ];
+        map
+    }
+
+    #[test]
+    fn test_find_components() {
+        let components = find_components(&three_parts());
+        let edges: Vec<Vec<EdgeID>> = components.iter().map(|c| c.edges.clone()).collect();
+        assert_eq!(
+            edges,
+            vec![
+                vec![EdgeID(0), EdgeID(2), EdgeID(4)],
+                vec![EdgeID(1), EdgeID(5)],
+                vec![EdgeID(3)],
+            ]
+        );
+        // Each edge is about 111m long
+        assert!((components[0].length_meters - 333.6).abs() < 1.0);
+        assert_eq!(components[2].bounds.min().x, 0.007);
+        assert_eq!(components[2].bounds.max().x, 0.008);
+    }
+
+    #[test]
+    fn test_prune_components() {
+        let mut map = three_parts();
+        let removed = prune_components(&mut map, 2);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].edges, vec![EdgeID(3)]);
+        assert_eq!(map.edges.len(), 5);
+        assert_eq!(map.nodes.len(), 7);
+
+        let mut map = three_parts();
+        let removed = prune_components(&mut map, 3);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(map.edges.len(), 3);
+        assert_eq!(map.nodes.len(), 4);
+        let ends: Vec<(NodeID, NodeID)> = map.edges.iter().map(|e| (e.node1, e.node2)).collect();
+        assert_eq!(
+            ends,
+            vec![
+                (NodeID(0), NodeID(1)),
+                (NodeID(1), NodeID(2)),
+                (NodeID(2), NodeID(3)),
+            ]
+        );
+        // The turn on the removed part is gone, and the other one follows the new edge IDs
+        let turns: Vec<(EdgeID, NodeID, EdgeID)> =
+            map.turns.iter().map(|t| (t.from, t.via, t.to)).collect();
+        assert_eq!(turns, vec![(EdgeID(1), NodeID(2), EdgeID(2))]);
+    }
+
+    #[test]
+    fn test_largest_component_is_kept() {
+        let mut map = three_parts();
+        assert_eq!(prune_components(&mut map, 100).len(), 2);
+        assert_eq!(map.edges.len(), 3);
+        assert_eq!(find_components(&map).len(), 1);
+
+        // Nothing to remove
+        assert!(prune_components(&mut map, 100).is_empty());
+        assert_eq!(map.edges.len(), 3);
+    }
+}
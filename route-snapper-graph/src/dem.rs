@@ -0,0 +1,553 @@
+//! Sampling elevation from a digital elevation model, for importers to use.
+
+use std::io::Cursor;
+
+use anyhow::{bail, Context, Result};
+use geo::{Coord, HaversineLength};
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+
+use crate::RouteSnapperMap;
+
+// GeoTIFF tags, from http://geotiff.maptools.org/spec/geotiff2.6.html. The decoder parses these
+// to named variants, so `Tag::Unknown` wouldn't match.
+const MODEL_PIXEL_SCALE: Tag = Tag::ModelPixelScaleTag;
+const MODEL_TIEPOINT: Tag = Tag::ModelTiepointTag;
+const GEO_KEY_DIRECTORY: Tag = Tag::GeoKeyDirectoryTag;
+const GEO_KEY_DIRECTORY_ID: u16 = 34735;
+// A GDAL extension
+const GDAL_NODATA: Tag = Tag::GdalNodata;
+// From the GeoKeyDirectoryTag, whether the coordinates are projected (1), geographic (2), or
+// geocentric (3)
+const GT_MODEL_TYPE_GEO_KEY: u16 = 1024;
+// From the GeoKeyDirectoryTag, whether each pixel covers an area starting at its tiepoint (1, the
+// default) or is a point sample centered on it (2)
+const GT_RASTER_TYPE_GEO_KEY: u16 = 1025;
+
+/// A grid of heights in meters. The coordinates must be WGS84 longitude and latitude, like the
+/// graph.
+pub struct Dem {
+    width: usize,
+    height: usize,
+    /// The outer corner of the top-left cell
+    left: f64,
+    top: f64,
+    cell_width: f64,
+    cell_height: f64,
+    /// Row-major, starting from the top
+    values: Vec<f32>,
+    nodata: Option<f32>,
+}
+
+impl Dem {
+    /// Reads a GeoTIFF or ESRI ASCII grid (`.asc`) file, detected by the contents.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            Self::from_geotiff(bytes)
+        } else {
+            Self::from_ascii_grid(
+                std::str::from_utf8(bytes).context("DEM isn't a GeoTIFF or ASCII grid")?,
+            )
+        }
+    }
+
+    fn from_geotiff(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder::new(Cursor::new(bytes))?.with_limits(Limits::unlimited());
+        let (width, height) = decoder.dimensions()?;
+        let scale = decoder
+            .get_tag_f64_vec(MODEL_PIXEL_SCALE)
+            .context("GeoTIFF is missing ModelPixelScaleTag")?;
+        let tiepoint = decoder
+            .get_tag_f64_vec(MODEL_TIEPOINT)
+            .context("GeoTIFF is missing ModelTiepointTag")?;
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            bail!("GeoTIFF georeferencing tags are malformed");
+        }
+        let keys = decoder
+            .get_tag_u16_vec(GEO_KEY_DIRECTORY)
+            .unwrap_or_default();
+        // Without a model type, assume it's geographic
+        if short_geo_key(&keys, GT_MODEL_TYPE_GEO_KEY)?.is_some_and(|x| x != 2) {
+            bail!(
+                "GeoTIFF doesn't use longitude and latitude. Reproject it to WGS84 first, like \
+                 with `gdalwarp -t_srs EPSG:4326`"
+            );
+        }
+        let pixel_is_point = match short_geo_key(&keys, GT_RASTER_TYPE_GEO_KEY)? {
+            None | Some(1) => false,
+            Some(2) => true,
+            Some(x) => bail!("GeoTIFF has an unknown raster type {x}"),
+        };
+        let nodata = decoder
+            .get_tag_ascii_string(GDAL_NODATA)
+            .ok()
+            .and_then(|x| x.trim_end_matches('\0').trim().parse().ok());
+
+        let values = match decoder.read_image()? {
+            DecodingResult::U8(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::U16(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::U32(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::U64(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I8(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I16(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I32(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I64(x) => x.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::F32(x) => x,
+            DecodingResult::F64(x) => x.into_iter().map(|v| v as f32).collect(),
+        };
+
+        // The tiepoint maps raster (i, j) to model (x, y). For point samples, that's the center of
+        // the cell, not its outer corner.
+        let (cell_width, cell_height) = (scale[0], scale[1]);
+        let mut left = tiepoint[3] - tiepoint[0] * cell_width;
+        let mut top = tiepoint[4] + tiepoint[1] * cell_height;
+        if pixel_is_point {
+            left -= cell_width / 2.0;
+            top += cell_height / 2.0;
+        }
+        Self::new(
+            width as usize,
+            height as usize,
+            left,
+            top,
+            cell_width,
+            cell_height,
+            values,
+            nodata,
+        )
+    }
+
+    fn from_ascii_grid(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        let (mut ncols, mut nrows, mut cellsize, mut nodata) = (None, None, None, None);
+        let (mut x, mut y, mut centered) = (None, None, false);
+        let mut values = Vec::new();
+        for line in lines.by_ref() {
+            let mut parts = line.split_whitespace();
+            let Some(key) = parts.next() else {
+                continue;
+            };
+            let Some(value) = parts.next() else {
+                bail!("Bad ASCII grid header line: {line}");
+            };
+            match key.to_lowercase().as_str() {
+                "ncols" => ncols = Some(value.parse::<usize>()?),
+                "nrows" => nrows = Some(value.parse::<usize>()?),
+                "xllcorner" => x = Some(value.parse::<f64>()?),
+                "yllcorner" => y = Some(value.parse::<f64>()?),
+                "xllcenter" => {
+                    x = Some(value.parse::<f64>()?);
+                    centered = true;
+                }
+                "yllcenter" => {
+                    y = Some(value.parse::<f64>()?);
+                    centered = true;
+                }
+                "cellsize" => cellsize = Some(value.parse::<f64>()?),
+                "nodata_value" => nodata = Some(value.parse::<f32>()?),
+                // The header is over; this line has values
+                _ => {
+                    for value in line.split_whitespace() {
+                        values.push(value.parse::<f32>()?);
+                    }
+                    break;
+                }
+            }
+        }
+        for line in lines {
+            for value in line.split_whitespace() {
+                values.push(value.parse::<f32>()?);
+            }
+        }
+
+        let (Some(ncols), Some(nrows), Some(mut x), Some(mut y), Some(cellsize)) =
+            (ncols, nrows, x, y, cellsize)
+        else {
+            bail!("ASCII grid header is missing ncols, nrows, xllcorner, yllcorner, or cellsize");
+        };
+        if centered {
+            x -= cellsize / 2.0;
+            y -= cellsize / 2.0;
+        }
+        let top = y + nrows as f64 * cellsize;
+        Self::new(ncols, nrows, x, top, cellsize, cellsize, values, nodata)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        width: usize,
+        height: usize,
+        left: f64,
+        top: f64,
+        cell_width: f64,
+        cell_height: f64,
+        values: Vec<f32>,
+        nodata: Option<f32>,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            bail!("DEM is empty");
+        }
+        if values.len() != width * height {
+            bail!(
+                "DEM should have {width}x{height} values, but has {}",
+                values.len()
+            );
+        }
+        if !(cell_width > 0.0 && cell_height > 0.0) {
+            bail!("DEM cell size {cell_width}x{cell_height} isn't positive");
+        }
+        // Projected coordinates in meters would usually be way out of range
+        let right = left + width as f64 * cell_width;
+        let bottom = top - height as f64 * cell_height;
+        if !(-180.0..=180.0).contains(&left)
+            || !(-180.0..=180.0).contains(&right)
+            || !(-90.0..=90.0).contains(&top)
+            || !(-90.0..=90.0).contains(&bottom)
+        {
+            bail!(
+                "DEM doesn't look like it uses WGS84 coordinates; it covers x from {left} to \
+                 {right} and y from {bottom} to {top}"
+            );
+        }
+        Ok(Self {
+            width,
+            height,
+            left,
+            top,
+            cell_width,
+            cell_height,
+            values,
+            nodata,
+        })
+    }
+
+    /// Bilinearly interpolates the height at a point, or returns `None` if it's outside the grid or
+    /// there's no data there.
+    pub fn elevation(&self, pt: Coord) -> Option<f32> {
+        // Position relative to cell centers
+        let col = (pt.x - self.left) / self.cell_width - 0.5;
+        let row = (self.top - pt.y) / self.cell_height - 0.5;
+        if col < -0.5
+            || row < -0.5
+            || col > self.width as f64 - 0.5
+            || row > self.height as f64 - 0.5
+        {
+            return None;
+        }
+        let col = col.clamp(0.0, (self.width - 1) as f64);
+        let row = row.clamp(0.0, (self.height - 1) as f64);
+
+        let (col0, row0) = (col.floor() as usize, row.floor() as usize);
+        let (col1, row1) = (
+            (col0 + 1).min(self.width - 1),
+            (row0 + 1).min(self.height - 1),
+        );
+        let (dx, dy) = ((col - col0 as f64) as f32, (row - row0 as f64) as f32);
+
+        let get = |c: usize, r: usize| {
+            let value = self.values[r * self.width + c];
+            (Some(value) != self.nodata && !value.is_nan()).then_some(value)
+        };
+        // A neighbor with no weight doesn't need data, so points exactly on a row or column next
+        // to missing data still work
+        let lerp = |a: Option<f32>, b: Option<f32>, t: f32| {
+            if t == 0.0 {
+                a
+            } else {
+                Some(a? * (1.0 - t) + b? * t)
+            }
+        };
+        let top = lerp(get(col0, row0), get(col1, row0), dx);
+        let bottom = lerp(get(col0, row1), get(col1, row1), dx);
+        lerp(top, bottom, dy)
+    }
+}
+
+/// Reads a DEM file, sets the height of every node, and if `grade_penalty` is set, makes climbs
+/// more expensive with `apply_grade_costs`. Returns the number of nodes with no data. Fails if
+/// most nodes have no data, because then the DEM probably covers the wrong area.
+pub fn add_elevation(
+    map: &mut RouteSnapperMap,
+    dem_bytes: &[u8],
+    grade_penalty: Option<f64>,
+) -> Result<usize> {
+    let dem = Dem::from_bytes(dem_bytes)?;
+    let missing = set_elevations(map, &dem);
+    if missing * 2 > map.nodes.len() {
+        bail!(
+            "{missing} of {} nodes have no data in the DEM. Does it cover the area?",
+            map.nodes.len()
+        );
+    }
+    if let Some(factor) = grade_penalty {
+        apply_grade_costs(map, factor);
+    }
+    Ok(missing)
+}
+
+/// Samples the height of every node. Nodes outside the DEM or without data get NaN. Returns the
+/// number of nodes with no data.
+pub fn set_elevations(map: &mut RouteSnapperMap, dem: &Dem) -> usize {
+    let elevations: Vec<f32> = map
+        .nodes
+        .iter()
+        .map(|pt| dem.elevation(*pt).unwrap_or(f32::NAN))
+        .collect();
+    map.node_elevations = elevations;
+    map.node_elevations.iter().filter(|x| x.is_nan()).count()
+}
+
+/// Makes going uphill more expensive. Each direction of an edge has its cost multiplied by `1 +
+/// factor * grade`, where grade is the rise over the length of the edge, like 0.05 for a 5% slope.
+/// This applies to the default costs and every cost profile. Downhill and flat directions don't
+/// change, and neither do edges with a missing height at either end. `set_elevations` must be
+/// called first.
+pub fn apply_grade_costs(map: &mut RouteSnapperMap, factor: f64) {
+    if map.node_elevations.is_empty() {
+        return;
+    }
+    let lengths: Vec<f64> = map
+        .edges
+        .iter()
+        .map(|e| e.geometry.haversine_length())
+        .collect();
+    let grades: Vec<Option<f64>> = map
+        .edges
+        .iter()
+        .zip(&lengths)
+        .map(|(edge, length)| {
+            let rise = (map.node_elevations[edge.node2.0 as usize]
+                - map.node_elevations[edge.node1.0 as usize]) as f64;
+            (*length != 0.0 && !rise.is_nan()).then(|| rise / length)
+        })
+        .collect();
+
+    for (forward_costs, backward_costs) in map.cost_tables_mut() {
+        for costs in [&mut *forward_costs, &mut *backward_costs] {
+            // The default costs are the length
+            if costs.is_empty() {
+                *costs = lengths.iter().map(|x| Some(*x)).collect();
+            }
+        }
+        for (idx, grade) in grades.iter().enumerate() {
+            let Some(grade) = grade else {
+                continue;
+            };
+            if let Some(cost) = &mut forward_costs[idx] {
+                *cost *= 1.0 + factor * grade.max(0.0);
+            }
+            if let Some(cost) = &mut backward_costs[idx] {
+                *cost *= 1.0 + factor * (-grade).max(0.0);
+            }
+        }
+    }
+}
+
+// Looks up a GeoKey with a single SHORT value. After a 4 value header, the GeoKeyDirectoryTag has
+// (ID, location, count, value) for each key. A location of 0 means the value is inline, and the
+// GeoKeyDirectoryTag's own ID means the value is at that offset into the directory. Other tags
+// hold doubles or strings.
+fn short_geo_key(keys: &[u16], id: u16) -> Result<Option<u16>> {
+    let Some(key) = keys
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        .find(|key| key[0] == id)
+    else {
+        return Ok(None);
+    };
+    match key[1] {
+        0 => Ok(Some(key[3])),
+        GEO_KEY_DIRECTORY_ID => keys
+            .get(key[3] as usize)
+            .copied()
+            .map(Some)
+            .with_context(|| format!("GeoTIFF key {id} points past the end of the directory")),
+        location => bail!("GeoTIFF key {id} should be a number, but it's stored in tag {location}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::make_map;
+    use crate::CostProfile;
+
+    // A 2x2 grid covering (0, 0) to (1, 1), with no data in the bottom-right cell
+    const GRID: &str = "ncols 2
+nrows 2
+xllcorner 0.0
+yllcorner 0.0
+cellsize 0.5
+nodata_value -9999
+10 20
+30 -9999
+";
+
+    fn pt(x: f64, y: f64) -> Coord {
+        Coord { x, y }
+    }
+
+    #[test]
+    fn test_ascii_grid() {
+        let dem = Dem::from_bytes(GRID.as_bytes()).unwrap();
+        // Cell centers
+        assert_eq!(dem.elevation(pt(0.25, 0.75)), Some(10.0));
+        assert_eq!(dem.elevation(pt(0.75, 0.75)), Some(20.0));
+        assert_eq!(dem.elevation(pt(0.25, 0.25)), Some(30.0));
+        // Halfway between the top two
+        assert_eq!(dem.elevation(pt(0.5, 0.75)), Some(15.0));
+        // No data, or interpolating from a cell without data
+        assert_eq!(dem.elevation(pt(0.75, 0.25)), None);
+        assert_eq!(dem.elevation(pt(0.5, 0.5)), None);
+        // Outside
+        assert_eq!(dem.elevation(pt(1.5, 0.75)), None);
+    }
+
+    #[test]
+    fn test_missing_heights() {
+        let dem = Dem::from_bytes(GRID.as_bytes()).unwrap();
+        // Going east along the top row, then off the grid
+        let mut map = make_map(
+            vec![pt(0.25, 0.75), pt(0.75, 0.75), pt(1.5, 0.75)],
+            &[(0, 1), (1, 2)],
+        );
+        assert_eq!(set_elevations(&mut map, &dem), 1);
+        assert_eq!(map.node_elevations[..2], [10.0, 20.0]);
+        assert!(map.node_elevations[2].is_nan());
+
+        // Only the climb on the first edge costs more
+        apply_grade_costs(&mut map, 10.0);
+        let lengths: Vec<f64> = map
+            .edges
+            .iter()
+            .map(|e| e.geometry.haversine_length())
+            .collect();
+        let climb = 1.0 + 10.0 * 10.0 / lengths[0];
+        assert_eq!(map.override_forward_costs[0], Some(lengths[0] * climb));
+        assert_eq!(map.override_backward_costs[0], Some(lengths[0]));
+        assert_eq!(map.override_forward_costs[1], Some(lengths[1]));
+        assert_eq!(map.override_backward_costs[1], Some(lengths[1]));
+    }
+
+    #[test]
+    fn test_grade_costs_for_cost_profiles() {
+        let dem = Dem::from_bytes(GRID.as_bytes()).unwrap();
+        let mut map = make_map(vec![pt(0.25, 0.75), pt(0.75, 0.75)], &[(0, 1)]);
+        map.cost_profiles.push(CostProfile {
+            name: "oneway".to_string(),
+            forward_costs: vec![Some(100.0)],
+            backward_costs: vec![None],
+        });
+        set_elevations(&mut map, &dem);
+        apply_grade_costs(&mut map, 10.0);
+
+        let length = map.edges[0].geometry.haversine_length();
+        let climb = 1.0 + 10.0 * 10.0 / length;
+        assert_eq!(map.override_forward_costs, vec![Some(length * climb)]);
+        assert_eq!(
+            map.cost_profiles[0].forward_costs,
+            vec![Some(100.0 * climb)]
+        );
+        assert_eq!(map.cost_profiles[0].backward_costs, vec![None]);
+    }
+
+    #[test]
+    fn test_mostly_missing() {
+        let mut map = make_map(
+            vec![pt(0.25, 0.75), pt(5.0, 5.0), pt(6.0, 6.0)],
+            &[(0, 1), (1, 2)],
+        );
+        let err = add_elevation(&mut map, GRID.as_bytes(), None).unwrap_err();
+        assert!(err.to_string().starts_with("2 of 3 nodes have no data"));
+    }
+
+    #[test]
+    fn test_projected_ascii_grid() {
+        let grid = GRID.replace("xllcorner 0.0", "xllcorner 530000");
+        let err = Dem::from_bytes(grid.as_bytes()).err().unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("DEM doesn't look like it uses WGS84"));
+    }
+
+    // A 2x2 GeoTIFF with the same grid, with these GeoKeys
+    fn geotiff(tiepoint: [f64; 2], keys: &[u16]) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut encoder = tiff::encoder::TiffEncoder::new(&mut bytes).unwrap();
+        let mut image = encoder
+            .new_image::<tiff::encoder::colortype::Gray32Float>(2, 2)
+            .unwrap();
+        let tags = image.encoder();
+        tags.write_tag(MODEL_PIXEL_SCALE, &[0.5, 0.5, 0.0][..])
+            .unwrap();
+        tags.write_tag(
+            MODEL_TIEPOINT,
+            &[0.0, 0.0, 0.0, tiepoint[0], tiepoint[1], 0.0][..],
+        )
+        .unwrap();
+        let header = [1, 1, 0, keys.len() as u16 / 4];
+        let directory: Vec<u16> = header.iter().chain(keys).copied().collect();
+        tags.write_tag(GEO_KEY_DIRECTORY, &directory[..]).unwrap();
+        image.write_data(&[10.0, 20.0, 30.0, f32::NAN]).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_geotiff_model_type() {
+        let corner = [0.0, 1.0];
+        let dem = Dem::from_bytes(&geotiff(corner, &[GT_MODEL_TYPE_GEO_KEY, 0, 1, 2])).unwrap();
+        assert_eq!(dem.elevation(pt(0.25, 0.75)), Some(10.0));
+        assert_eq!(dem.elevation(pt(0.75, 0.25)), None);
+
+        let err = Dem::from_bytes(&geotiff(corner, &[GT_MODEL_TYPE_GEO_KEY, 0, 1, 1]))
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("GeoTIFF doesn't use longitude"));
+
+        // The value can also come after the keys in the directory
+        let in_directory = |model_type| {
+            geotiff(
+                corner,
+                &[
+                    GT_MODEL_TYPE_GEO_KEY,
+                    GEO_KEY_DIRECTORY_ID,
+                    1,
+                    8,
+                    model_type,
+                ],
+            )
+        };
+        assert!(Dem::from_bytes(&in_directory(2)).is_ok());
+        let err = Dem::from_bytes(&in_directory(1)).err().unwrap();
+        assert!(err.to_string().starts_with("GeoTIFF doesn't use longitude"));
+
+        // The model type can't be a double from GeoDoubleParamsTag
+        let err = Dem::from_bytes(&geotiff(corner, &[GT_MODEL_TYPE_GEO_KEY, 34736, 1, 0]))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("should be a number"));
+    }
+
+    #[test]
+    fn test_geotiff_pixel_is_point() {
+        // The tiepoint is the center of the top-left cell, so the grid covers the same area
+        let dem = Dem::from_bytes(&geotiff(
+            [0.25, 0.75],
+            &[
+                GT_MODEL_TYPE_GEO_KEY,
+                0,
+                1,
+                2,
+                GT_RASTER_TYPE_GEO_KEY,
+                0,
+                1,
+                2,
+            ],
+        ))
+        .unwrap();
+        assert_eq!(dem.elevation(pt(0.25, 0.75)), Some(10.0));
+        assert_eq!(dem.elevation(pt(0.75, 0.75)), Some(20.0));
+        assert_eq!(dem.elevation(pt(0.25, 0.25)), Some(30.0));
+    }
+}
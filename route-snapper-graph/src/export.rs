@@ -0,0 +1,165 @@
+//! Writes a graph as GeoJSON that geojson-to-route-snapper turns back into the same graph, so
+//! graphs can be edited in tools like QGIS.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{EdgeID, RouteSnapperMap, TileInfo, FORMAT_VERSION};
+
+/// The FeatureCollection member holding `ExportMetadata`
+pub const METADATA_KEY: &str = "route_snapper";
+
+/// Everything about the graph that isn't stored on individual edges and nodes.
+#[derive(Serialize, Deserialize)]
+pub struct ExportMetadata {
+    /// The graph format version the file was exported from, for information only
+    pub format_version: u32,
+    /// If false, the graph had no `override_forward_costs` or `override_backward_costs`, and
+    /// edges don't have `forward_cost` or `backward_cost` properties.
+    pub override_costs: bool,
+    /// The names of cost profiles. Each edge has `forward_cost:name` and `backward_cost:name`.
+    pub cost_profiles: Vec<String>,
+    /// Each attribute key, and the property holding it on edges. They're usually the same, unless
+    /// the key clashes with another property, like `name`.
+    pub attributes: Vec<(String, String)>,
+    /// (from `edge_id`, via `node_id`, to `edge_id`, cost or `None` if forbidden)
+    pub turns: Vec<(u32, u32, u32, Option<f64>)>,
+    pub u_turn_cost: f64,
+    pub costs_in_seconds: bool,
+    /// If true, edges have `osm_way_id` and nodes may have `osm_node_id`.
+    pub osm_ids: bool,
+    /// If the graph is a tile, where it fits. Its IDs are in the order of `edge_id` and `node_id`.
+    #[serde(default)]
+    pub tile: Option<TileInfo>,
+}
+
+/// Returns a FeatureCollection with a LineString for every edge, then a Point for every node.
+///
+/// Edges have `edge_id`, `node1`, `node2`, `name`, costs, and attributes as properties. Nodes have
+/// `node_id` and optionally `elevation`. OSM IDs are included if the graph has them. The
+/// `ExportMetadata` is stored in the `route_snapper` member. The contraction hierarchy isn't
+/// exported, since it's derived from everything else; build it again after importing.
+pub fn to_geojson(map: &RouteSnapperMap) -> Value {
+    let override_costs = !map.override_forward_costs.is_empty();
+
+    // Keep keys in the order they first appear, so re-importing sets attributes in the same order
+    let mut attribute_keys: Vec<&str> = Vec::new();
+    for idx in 0..map.edges.len() {
+        for (key, _) in map.attributes.get(EdgeID(idx as u32)) {
+            if !attribute_keys.contains(&key) {
+                attribute_keys.push(key);
+            }
+        }
+    }
+    let is_reserved = |key: &str| {
+        matches!(
+            key,
+            "edge_id"
+                | "node1"
+                | "node2"
+                | "name"
+                | "forward_cost"
+                | "backward_cost"
+                | "osm_way_id"
+        ) || key.starts_with("forward_cost:")
+            || key.starts_with("backward_cost:")
+            || key.starts_with("attribute:")
+    };
+    let attribute_properties: Vec<(&str, String)> = attribute_keys
+        .iter()
+        .map(|key| {
+            let property = if is_reserved(key) {
+                format!("attribute:{key}")
+            } else {
+                key.to_string()
+            };
+            (*key, property)
+        })
+        .collect();
+
+    let mut features = Vec::new();
+    for (idx, edge) in map.edges.iter().enumerate() {
+        let edge_id = EdgeID(idx as u32);
+        let mut properties = Map::new();
+        properties.insert("edge_id".to_string(), idx.into());
+        properties.insert("node1".to_string(), edge.node1.0.into());
+        properties.insert("node2".to_string(), edge.node2.0.into());
+        properties.insert("name".to_string(), edge.name.clone().into());
+        if override_costs {
+            properties.insert(
+                "forward_cost".to_string(),
+                map.override_forward_costs[idx].into(),
+            );
+            properties.insert(
+                "backward_cost".to_string(),
+                map.override_backward_costs[idx].into(),
+            );
+        }
+        for profile in &map.cost_profiles {
+            properties.insert(
+                format!("forward_cost:{}", profile.name),
+                profile.forward_costs[idx].into(),
+            );
+            properties.insert(
+                format!("backward_cost:{}", profile.name),
+                profile.backward_costs[idx].into(),
+            );
+        }
+        for (key, property) in &attribute_properties {
+            if let Some(value) = map.attributes.get_value(edge_id, key) {
+                properties.insert(property.clone(), value.into());
+            }
+        }
+        if let Some(id) = map.osm_way_ids.get(idx) {
+            properties.insert("osm_way_id".to_string(), (*id).into());
+        }
+
+        let coordinates: Vec<[f64; 2]> = edge.geometry.coords().map(|pt| [pt.x, pt.y]).collect();
+        features.push(json!({
+            "type": "Feature",
+            "geometry": { "type": "LineString", "coordinates": coordinates },
+            "properties": properties,
+        }));
+    }
+
+    for (idx, pt) in map.nodes.iter().enumerate() {
+        let mut properties = Map::new();
+        properties.insert("node_id".to_string(), idx.into());
+        if let Some(elevation) = map.node_elevations.get(idx).filter(|x| !x.is_nan()) {
+            properties.insert("elevation".to_string(), (*elevation).into());
+        }
+        if let Some(Some(id)) = map.osm_node_ids.get(idx) {
+            properties.insert("osm_node_id".to_string(), (*id).into());
+        }
+        features.push(json!({
+            "type": "Feature",
+            "geometry": { "type": "Point", "coordinates": [pt.x, pt.y] },
+            "properties": properties,
+        }));
+    }
+
+    let metadata = ExportMetadata {
+        format_version: FORMAT_VERSION,
+        override_costs,
+        cost_profiles: map.cost_profiles.iter().map(|p| p.name.clone()).collect(),
+        attributes: attribute_properties
+            .into_iter()
+            .map(|(key, property)| (key.to_string(), property))
+            .collect(),
+        turns: map
+            .turns
+            .iter()
+            .map(|turn| (turn.from.0, turn.via.0, turn.to.0, turn.cost))
+            .collect(),
+        u_turn_cost: map.u_turn_cost,
+        costs_in_seconds: map.costs_in_seconds,
+        osm_ids: !map.osm_way_ids.is_empty(),
+        tile: map.tile.clone(),
+    };
+
+    json!({
+        "type": "FeatureCollection",
+        (METADATA_KEY): metadata,
+        "features": features,
+    })
+}
@@ -0,0 +1,136 @@
+//! Older versions of the binary format, kept around so `RouteSnapperMap::from_bytes` can migrate
+//! graphs built by older importers.
+
+use geo::Coord;
+use serde::Deserialize;
+
+use crate::{deserialize_coords, AttributeTable, CostProfile, Edge, RouteSnapperMap, Turn};
+
+// Each version only appends fields to the previous one. Bincode doesn't add anything for nested
+// structs, so each version can wrap the previous one.
+
+/// Format v1, also used by 0.4.0 graphs without a header
+#[derive(Deserialize)]
+pub struct MapV1 {
+    #[serde(deserialize_with = "deserialize_coords")]
+    nodes: Vec<Coord>,
+    edges: Vec<Edge>,
+    override_forward_costs: Vec<Option<f64>>,
+    override_backward_costs: Vec<Option<f64>>,
+}
+
+impl From<MapV1> for RouteSnapperMap {
+    fn from(map: MapV1) -> Self {
+        Self {
+            nodes: map.nodes,
+            edges: map.edges,
+            override_forward_costs: map.override_forward_costs,
+            override_backward_costs: map.override_backward_costs,
+            attributes: AttributeTable::default(),
+            cost_profiles: Vec::new(),
+            turns: Vec::new(),
+            u_turn_cost: 0.0,
+            costs_in_seconds: false,
+            node_elevations: Vec::new(),
+            osm_way_ids: Vec::new(),
+            osm_node_ids: Vec::new(),
+            tile: None,
+            contraction_hierarchy: None,
+        }
+    }
+}
+
+/// Format v2, adding `attributes`
+#[derive(Deserialize)]
+pub struct MapV2 {
+    v1: MapV1,
+    attributes: AttributeTable,
+}
+
+impl From<MapV2> for RouteSnapperMap {
+    fn from(map: MapV2) -> Self {
+        let mut result = Self::from(map.v1);
+        result.attributes = map.attributes;
+        result
+    }
+}
+
+/// Format v3, adding `cost_profiles`
+#[derive(Deserialize)]
+pub struct MapV3 {
+    v2: MapV2,
+    cost_profiles: Vec<CostProfile>,
+}
+
+impl From<MapV3> for RouteSnapperMap {
+    fn from(map: MapV3) -> Self {
+        let mut result = Self::from(map.v2);
+        result.cost_profiles = map.cost_profiles;
+        result
+    }
+}
+
+/// Format v4, adding `turns` and `u_turn_cost`
+#[derive(Deserialize)]
+pub struct MapV4 {
+    v3: MapV3,
+    turns: Vec<Turn>,
+    u_turn_cost: f64,
+}
+
+impl From<MapV4> for RouteSnapperMap {
+    fn from(map: MapV4) -> Self {
+        let mut result = Self::from(map.v3);
+        result.turns = map.turns;
+        result.u_turn_cost = map.u_turn_cost;
+        result
+    }
+}
+
+/// Format v5, adding `costs_in_seconds`
+#[derive(Deserialize)]
+pub struct MapV5 {
+    v4: MapV4,
+    costs_in_seconds: bool,
+}
+
+impl From<MapV5> for RouteSnapperMap {
+    fn from(map: MapV5) -> Self {
+        let mut result = Self::from(map.v4);
+        result.costs_in_seconds = map.costs_in_seconds;
+        result
+    }
+}
+
+/// Format v6, adding `node_elevations`
+#[derive(Deserialize)]
+pub struct MapV6 {
+    v5: MapV5,
+    node_elevations: Vec<f32>,
+}
+
+impl From<MapV6> for RouteSnapperMap {
+    fn from(map: MapV6) -> Self {
+        let mut result = Self::from(map.v5);
+        result.node_elevations = map.node_elevations;
+        result
+    }
+}
+
+/// Format v7, adding `osm_way_ids` and `osm_node_ids`. This is the last version stored as plain
+/// bincode; see `compact` for the layout after.
+#[derive(Deserialize)]
+pub struct MapV7 {
+    v6: MapV6,
+    osm_way_ids: Vec<i64>,
+    osm_node_ids: Vec<Option<i64>>,
+}
+
+impl From<MapV7> for RouteSnapperMap {
+    fn from(map: MapV7) -> Self {
+        let mut result = Self::from(map.v6);
+        result.osm_way_ids = map.osm_way_ids;
+        result.osm_node_ids = map.osm_node_ids;
+        result
+    }
+}
@@ -1,12 +1,32 @@
+pub mod ch;
+mod compact;
+pub mod components;
+#[cfg(feature = "dem")]
+pub mod dem;
+#[cfg(feature = "export")]
+pub mod export;
+mod legacy;
+pub mod tiles;
+pub mod validate;
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
 use geo::{Coord, LineString};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize)]
+pub use compact::Compression;
+pub use tiles::TileInfo;
+
+/// Every serialized graph starts with these bytes, followed by `FORMAT_VERSION` as a
+/// little-endian `u32`, then the graph in the layout described in `compact`.
+const MAGIC: &[u8; 4] = b"RSNP";
+
+/// Bump this whenever the binary format changes, and teach `RouteSnapperMap::from_bytes` how to
+/// read the previous version.
+pub const FORMAT_VERSION: u32 = 10;
+
 pub struct RouteSnapperMap {
-    #[serde(
-        serialize_with = "serialize_coords",
-        deserialize_with = "deserialize_coords"
-    )]
     pub nodes: Vec<Coord>,
     pub edges: Vec<Edge>,
 
@@ -15,6 +35,55 @@ pub struct RouteSnapperMap {
     /// `None`, that edge won't be routable in the specified direction.
     pub override_forward_costs: Vec<Option<f64>>,
     pub override_backward_costs: Vec<Option<f64>>,
+
+    /// Arbitrary key/value attributes per edge, like OSM tags.
+    pub attributes: AttributeTable,
+
+    /// Alternative costs that can be used instead of the default ones above.
+    pub cost_profiles: Vec<CostProfile>,
+
+    /// Turns that are forbidden or cost extra. Any turn not listed here is allowed and free.
+    pub turns: Vec<Turn>,
+    /// Added to the cost of going along an edge and immediately back along the same edge.
+    pub u_turn_cost: f64,
+
+    /// If true, `override_forward_costs` and `override_backward_costs` are travel times in
+    /// seconds, so routes can report an estimated duration.
+    pub costs_in_seconds: bool,
+
+    /// If non-empty, the height of every node in meters. Unknown heights are NaN.
+    pub node_elevations: Vec<f32>,
+
+    /// If non-empty, the OSM way every edge came from.
+    pub osm_way_ids: Vec<i64>,
+    /// If non-empty, the OSM node at every node. This is `None` for nodes added by the importer,
+    /// like where roads cross a clipping boundary.
+    pub osm_node_ids: Vec<Option<i64>>,
+
+    /// If this graph is one tile of a larger graph, where it fits. See `tiles`.
+    pub tile: Option<TileInfo>,
+
+    /// Optionally built by importers to speed up routing with the default costs. See `ch`.
+    pub contraction_hierarchy: Option<ch::ContractionHierarchy>,
+}
+
+/// A named set of edge costs, like "walking" or "quiet streets".
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CostProfile {
+    pub name: String,
+    /// These have the same meaning as `override_forward_costs` and `override_backward_costs`.
+    pub forward_costs: Vec<Option<f64>>,
+    pub backward_costs: Vec<Option<f64>>,
+}
+
+/// Going from one edge to another through the node they share.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Turn {
+    pub from: EdgeID,
+    pub via: NodeID,
+    pub to: EdgeID,
+    /// If `None`, the turn is forbidden. Otherwise this is added to the cost of the path.
+    pub cost: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,6 +108,19 @@ pub struct Edge {
     pub backward_cost: Option<f64>,
 }
 
+/// String key/value pairs per edge. Every distinct key and value is only stored once.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AttributeTable {
+    strings: Vec<String>,
+    /// Indexed by `EdgeID`, each a list of (key, value) indices into `strings`. Edges past the end
+    /// have no attributes.
+    per_edge: Vec<Vec<(u32, u32)>>,
+
+    /// Only used while building the table, to intern strings.
+    #[serde(skip_serializing, skip_deserializing)]
+    lookup: HashMap<String, u32>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct EdgeID(pub u32);
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
@@ -51,6 +133,239 @@ impl RouteSnapperMap {
     pub fn node(&self, id: NodeID) -> Coord {
         self.nodes[id.0 as usize]
     }
+    pub fn cost_profile(&self, name: &str) -> Option<&CostProfile> {
+        self.cost_profiles.iter().find(|p| p.name == name)
+    }
+
+    /// The forward and backward costs of the default profile, then of each cost profile.
+    pub fn cost_tables(&self) -> impl Iterator<Item = (&Vec<Option<f64>>, &Vec<Option<f64>>)> {
+        std::iter::once((&self.override_forward_costs, &self.override_backward_costs)).chain(
+            self.cost_profiles
+                .iter()
+                .map(|p| (&p.forward_costs, &p.backward_costs)),
+        )
+    }
+
+    /// Like `cost_tables`, but mutable.
+    pub fn cost_tables_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (&mut Vec<Option<f64>>, &mut Vec<Option<f64>>)> {
+        std::iter::once((
+            &mut self.override_forward_costs,
+            &mut self.override_backward_costs,
+        ))
+        .chain(
+            self.cost_profiles
+                .iter_mut()
+                .map(|p| (&mut p.forward_costs, &mut p.backward_costs)),
+        )
+    }
+
+    /// Removes some edges, any nodes no longer used by an edge, and everything referring to them.
+    /// The remaining edges and nodes keep their order, but their IDs change.
+    pub fn remove_edges(&mut self, remove: &HashSet<EdgeID>) {
+        // Changing IDs breaks the hierarchy, and it can't be patched up
+        self.contraction_hierarchy = None;
+        let keep_edge: Vec<bool> = (0..self.edges.len())
+            .map(|idx| !remove.contains(&EdgeID(idx as u32)))
+            .collect();
+        let edge_ids = compact_ids(&keep_edge);
+        retain_by_index(&mut self.edges, &keep_edge);
+        for (forward_costs, backward_costs) in self.cost_tables_mut() {
+            retain_by_index(forward_costs, &keep_edge);
+            retain_by_index(backward_costs, &keep_edge);
+        }
+        retain_by_index(&mut self.attributes.per_edge, &keep_edge);
+        retain_by_index(&mut self.osm_way_ids, &keep_edge);
+
+        let mut keep_node = vec![false; self.nodes.len()];
+        for edge in &self.edges {
+            keep_node[edge.node1.0 as usize] = true;
+            keep_node[edge.node2.0 as usize] = true;
+        }
+        let node_ids = compact_ids(&keep_node);
+        retain_by_index(&mut self.nodes, &keep_node);
+        retain_by_index(&mut self.node_elevations, &keep_node);
+        retain_by_index(&mut self.osm_node_ids, &keep_node);
+        if let Some(tile) = &mut self.tile {
+            retain_by_index(&mut tile.edge_ids, &keep_edge);
+            retain_by_index(&mut tile.node_ids, &keep_node);
+        }
+        for edge in &mut self.edges {
+            edge.node1 = NodeID(node_ids[edge.node1.0 as usize].unwrap());
+            edge.node2 = NodeID(node_ids[edge.node2.0 as usize].unwrap());
+        }
+
+        self.turns.retain_mut(|turn| {
+            let (Some(from), Some(via), Some(to)) = (
+                edge_ids[turn.from.0 as usize],
+                node_ids[turn.via.0 as usize],
+                edge_ids[turn.to.0 as usize],
+            ) else {
+                return false;
+            };
+            turn.from = EdgeID(from);
+            turn.via = NodeID(via);
+            turn.to = EdgeID(to);
+            true
+        });
+    }
+
+    /// Serializes the graph, prefixed by a header identifying the format version.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_bytes_with(Compression::None)
+    }
+
+    /// Like `to_bytes`, but optionally compressing the graph. `from_bytes` reads either.
+    pub fn to_bytes_with(&self, compression: Compression) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend(compact::encode(self, compression)?);
+        Ok(bytes)
+    }
+
+    /// Deserializes a graph produced by `to_bytes`, migrating older format versions when possible.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            // Graphs built before the header existed just hold the raw bincoded struct. The 0.4.0
+            // layout is the same as v1, but anything older can't be distinguished and will fail.
+            return match bincode::deserialize::<legacy::MapV1>(bytes) {
+                Ok(map) => Ok(map.into()),
+                Err(err) => bail!(
+                    "Graph has no format header and couldn't be read as the 0.4.0 layout ({err}). \
+                     It was probably built by an older version of route-snapper; this library \
+                     reads format v{FORMAT_VERSION}, so please rebuild the graph"
+                ),
+            };
+        };
+        if rest.len() < 4 {
+            bail!("Graph file is truncated; it ends in the middle of the header");
+        }
+        let version = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let payload = &rest[4..];
+
+        let map: Self = match version {
+            8..=FORMAT_VERSION => compact::decode(payload, version)?,
+            1 => bincode::deserialize::<legacy::MapV1>(payload)?.into(),
+            2 => bincode::deserialize::<legacy::MapV2>(payload)?.into(),
+            3 => bincode::deserialize::<legacy::MapV3>(payload)?.into(),
+            4 => bincode::deserialize::<legacy::MapV4>(payload)?.into(),
+            5 => bincode::deserialize::<legacy::MapV5>(payload)?.into(),
+            6 => bincode::deserialize::<legacy::MapV6>(payload)?.into(),
+            7 => bincode::deserialize::<legacy::MapV7>(payload)?.into(),
+            _ if version > FORMAT_VERSION => bail!(
+                "Graph built with format v{version}, this library reads v{FORMAT_VERSION}. \
+                 Please upgrade route-snapper"
+            ),
+            _ => bail!(
+                "Graph built with format v{version}, this library reads v{FORMAT_VERSION}. \
+                 Please rebuild the graph"
+            ),
+        };
+        // A corrupt table would otherwise make AttributeTable::get panic later
+        map.attributes.check_indices()?;
+        Ok(map)
+    }
+}
+
+impl AttributeTable {
+    /// Sets one attribute for an edge, replacing any previous value for the same key.
+    pub fn set(&mut self, edge: EdgeID, key: &str, value: &str) {
+        let key = self.intern(key);
+        let value = self.intern(value);
+        let idx = edge.0 as usize;
+        if self.per_edge.len() <= idx {
+            self.per_edge.resize(idx + 1, Vec::new());
+        }
+        let pairs = &mut self.per_edge[idx];
+        if let Some(pair) = pairs.iter_mut().find(|(k, _)| *k == key) {
+            pair.1 = value;
+        } else {
+            pairs.push((key, value));
+        }
+    }
+
+    /// Returns all (key, value) attributes for an edge.
+    pub fn get(&self, edge: EdgeID) -> impl Iterator<Item = (&str, &str)> {
+        self.per_edge
+            .get(edge.0 as usize)
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| {
+                (
+                    self.strings[*k as usize].as_str(),
+                    self.strings[*v as usize].as_str(),
+                )
+            })
+    }
+
+    /// Returns one attribute for an edge, if it's set.
+    pub fn get_value(&self, edge: EdgeID, key: &str) -> Option<&str> {
+        self.get(edge).find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.per_edge.iter().all(|pairs| pairs.is_empty())
+    }
+
+    /// Fails if any key or value points past the end of the string table.
+    pub fn check_indices(&self) -> Result<()> {
+        for (edge, pairs) in self.per_edge.iter().enumerate() {
+            for (k, v) in pairs {
+                if *k as usize >= self.strings.len() || *v as usize >= self.strings.len() {
+                    bail!(
+                        "Attributes for edge {edge} refer to string {}, but there are only {} \
+                         strings",
+                        (*k).max(*v),
+                        self.strings.len()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn intern(&mut self, x: &str) -> u32 {
+        // After deserializing, the lookup is empty
+        if self.lookup.len() != self.strings.len() {
+            self.lookup = self
+                .strings
+                .iter()
+                .enumerate()
+                .map(|(idx, s)| (s.clone(), idx as u32))
+                .collect();
+        }
+        if let Some(idx) = self.lookup.get(x) {
+            return *idx;
+        }
+        let idx = self.strings.len() as u32;
+        self.strings.push(x.to_string());
+        self.lookup.insert(x.to_string(), idx);
+        idx
+    }
+}
+
+// For every old ID, the new ID if it's kept
+fn compact_ids(keep: &[bool]) -> Vec<Option<u32>> {
+    let mut next = 0;
+    keep.iter()
+        .map(|keep| {
+            keep.then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect()
+}
+
+// Vectors parallel to edges or nodes may be empty or shorter; only existing entries are checked
+fn retain_by_index<T>(list: &mut Vec<T>, keep: &[bool]) {
+    let mut idx = 0;
+    list.retain(|_| {
+        idx += 1;
+        keep.get(idx - 1).copied().unwrap_or(true)
+    });
 }
 
 fn serialize_coords<S: Serializer>(coords: &Vec<Coord>, s: S) -> Result<S::Ok, S::Error> {
@@ -62,7 +377,7 @@ fn serialize_coords<S: Serializer>(coords: &Vec<Coord>, s: S) -> Result<S::Ok, S
     flattened.serialize(s)
 }
 
-fn deserialize_coords<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Coord>, D::Error> {
+pub(crate) fn deserialize_coords<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Coord>, D::Error> {
     let flattened = <Vec<i32>>::deserialize(d)?;
     let mut pts = Vec::new();
     for pair in flattened.chunks(2) {
@@ -93,3 +408,103 @@ fn serialize_f64(x: f64) -> i32 {
 fn deserialize_f64(x: i32) -> f64 {
     x as f64 / 1_000_000.0
 }
+
+/// Fixtures shared by tests in this crate and the crates that depend on it.
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+    use geo::{Coord, LineString};
+
+    use crate::{AttributeTable, Edge, NodeID, RouteSnapperMap};
+
+    /// Builds a graph from straight lines between points, routable both ways
+    pub fn make_map(nodes: Vec<Coord>, edges: &[(u32, u32)]) -> RouteSnapperMap {
+        RouteSnapperMap {
+            edges: edges
+                .iter()
+                .map(|(node1, node2)| Edge {
+                    node1: NodeID(*node1),
+                    node2: NodeID(*node2),
+                    geometry: LineString::new(vec![nodes[*node1 as usize], nodes[*node2 as usize]]),
+                    name: None,
+                    length_meters: 0.0,
+                    forward_cost: None,
+                    backward_cost: None,
+                })
+                .collect(),
+            nodes,
+            override_forward_costs: Vec::new(),
+            override_backward_costs: Vec::new(),
+            attributes: AttributeTable::default(),
+            cost_profiles: Vec::new(),
+            turns: Vec::new(),
+            u_turn_cost: 0.0,
+            costs_in_seconds: false,
+            node_elevations: Vec::new(),
+            osm_way_ids: Vec::new(),
+            osm_node_ids: Vec::new(),
+            tile: None,
+            contraction_hierarchy: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::make_map;
+
+    #[test]
+    fn test_format_header() {
+        // southwark.bin was built before the header existed
+        let legacy_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+        let map = RouteSnapperMap::from_bytes(&legacy_bytes).unwrap();
+        let num_edges = map.edges.len();
+
+        // Round-trip with the header
+        let mut bytes = map.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        let map = RouteSnapperMap::from_bytes(&bytes).unwrap();
+        assert_eq!(map.edges.len(), num_edges);
+
+        // Pretend the graph came from the future
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let err = RouteSnapperMap::from_bytes(&bytes)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.starts_with(&format!(
+            "Graph built with format v{}, this library reads v{FORMAT_VERSION}",
+            FORMAT_VERSION + 1
+        )));
+
+        // Cut off in the middle of the header
+        let err = RouteSnapperMap::from_bytes(&bytes[..6])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("truncated"));
+
+        // Garbage without a header
+        assert!(RouteSnapperMap::from_bytes(&[1, 2, 3]).is_err());
+    }
+    #[test]
+    fn test_corrupt_attributes() {
+        let mut map = make_map(
+            vec![Coord { x: 0.0, y: 0.0 }, Coord { x: 0.001, y: 0.0 }],
+            &[(0, 1)],
+        );
+        map.attributes.set(EdgeID(0), "surface", "asphalt");
+        let copy = RouteSnapperMap::from_bytes(&map.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            copy.attributes.get_value(EdgeID(0), "surface"),
+            Some("asphalt")
+        );
+
+        map.attributes.per_edge[0][0].1 = 5;
+        let err = RouteSnapperMap::from_bytes(&map.to_bytes().unwrap())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("refer to string 5"));
+    }
+}
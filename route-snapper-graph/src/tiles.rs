@@ -0,0 +1,550 @@
+//! Splitting a graph into square tiles, so a client can load only the area it needs, and combining
+//! tiles again as they're loaded.
+//!
+//! Every tile is a complete graph, holding every edge whose bounding box touches the tile, and the
+//! nodes at both ends of those edges. Edges crossing a tile's boundary are in both tiles. Each tile
+//! remembers the ID of its nodes and edges in the whole graph, so `TileSet` can tell when two tiles
+//! share something. Since both edges of a turn touch the node between them, every turn is in a
+//! tile with both of its edges.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use geo::BoundingRect;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AttributeTable, Compression, CostProfile, Edge, EdgeID, NodeID, RouteSnapperMap, Turn,
+};
+
+/// Where a tile is in the whole graph.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileInfo {
+    /// Tiles are squares this many degrees wide. Tile (0, 0) starts at longitude 0, latitude 0.
+    pub size_degrees: f64,
+    pub x: i32,
+    pub y: i32,
+    /// For every node in this tile, its ID in the whole graph
+    pub node_ids: Vec<u32>,
+    /// For every edge in this tile, its ID in the whole graph
+    pub edge_ids: Vec<u32>,
+}
+
+/// Returns the tile containing a point.
+pub fn tile_for(size_degrees: f64, lon: f64, lat: f64) -> (i32, i32) {
+    (
+        (lon / size_degrees).floor() as i32,
+        (lat / size_degrees).floor() as i32,
+    )
+}
+
+/// Splits a graph into tiles, leaving out empty ones.
+pub fn split(map: &RouteSnapperMap, size_degrees: f64) -> Result<Vec<RouteSnapperMap>> {
+    if !size_degrees.is_finite() || size_degrees <= 0.0 {
+        bail!("Tile size must be positive, not {size_degrees}");
+    }
+    if map.tile.is_some() {
+        bail!("This graph is already a tile");
+    }
+
+    // Edges in each tile, in order
+    let mut tile_edges: BTreeMap<(i32, i32), Vec<usize>> = BTreeMap::new();
+    for (idx, edge) in map.edges.iter().enumerate() {
+        let Some(bounds) = edge.geometry.bounding_rect() else {
+            continue;
+        };
+        let (x1, y1) = tile_for(size_degrees, bounds.min().x, bounds.min().y);
+        let (x2, y2) = tile_for(size_degrees, bounds.max().x, bounds.max().y);
+        for x in x1..=x2 {
+            for y in y1..=y2 {
+                tile_edges.entry((x, y)).or_default().push(idx);
+            }
+        }
+    }
+
+    let mut turns_per_edge: HashMap<EdgeID, Vec<&Turn>> = HashMap::new();
+    for turn in &map.turns {
+        turns_per_edge.entry(turn.from).or_default().push(turn);
+    }
+
+    Ok(tile_edges
+        .into_iter()
+        .map(|((x, y), edges)| make_tile(map, size_degrees, x, y, edges, &turns_per_edge))
+        .collect())
+}
+
+fn make_tile(
+    map: &RouteSnapperMap,
+    size_degrees: f64,
+    x: i32,
+    y: i32,
+    edge_ids: Vec<usize>,
+    turns_per_edge: &HashMap<EdgeID, Vec<&Turn>>,
+) -> RouteSnapperMap {
+    let mut node_ids: Vec<u32> = edge_ids
+        .iter()
+        .flat_map(|idx| [map.edges[*idx].node1.0, map.edges[*idx].node2.0])
+        .collect();
+    node_ids.sort();
+    node_ids.dedup();
+    let local_nodes: HashMap<u32, NodeID> = node_ids
+        .iter()
+        .enumerate()
+        .map(|(local, global)| (*global, NodeID(local as u32)))
+        .collect();
+    let local_edges: HashMap<u32, EdgeID> = edge_ids
+        .iter()
+        .enumerate()
+        .map(|(local, global)| (*global as u32, EdgeID(local as u32)))
+        .collect();
+
+    // Vectors that may be empty stay empty
+    let pick_edges = |list: &[Option<f64>]| -> Vec<Option<f64>> {
+        if list.is_empty() {
+            Vec::new()
+        } else {
+            edge_ids.iter().map(|idx| list[*idx]).collect()
+        }
+    };
+
+    let mut attributes = AttributeTable::default();
+    let mut turns = Vec::new();
+    for (local, global) in edge_ids.iter().enumerate() {
+        let local = EdgeID(local as u32);
+        for (key, value) in map.attributes.get(EdgeID(*global as u32)) {
+            attributes.set(local, key, value);
+        }
+        for turn in turns_per_edge
+            .get(&EdgeID(*global as u32))
+            .into_iter()
+            .flatten()
+        {
+            if let Some(to) = local_edges.get(&turn.to.0) {
+                turns.push(Turn {
+                    from: local,
+                    via: local_nodes[&turn.via.0],
+                    to: *to,
+                    cost: turn.cost,
+                });
+            }
+        }
+    }
+
+    RouteSnapperMap {
+        nodes: node_ids.iter().map(|id| map.nodes[*id as usize]).collect(),
+        edges: edge_ids
+            .iter()
+            .map(|idx| {
+                let edge = &map.edges[*idx];
+                Edge {
+                    node1: local_nodes[&edge.node1.0],
+                    node2: local_nodes[&edge.node2.0],
+                    geometry: edge.geometry.clone(),
+                    name: edge.name.clone(),
+
+                    length_meters: 0.0,
+                    forward_cost: None,
+                    backward_cost: None,
+                }
+            })
+            .collect(),
+        override_forward_costs: pick_edges(&map.override_forward_costs),
+        override_backward_costs: pick_edges(&map.override_backward_costs),
+        attributes,
+        cost_profiles: map
+            .cost_profiles
+            .iter()
+            .map(|profile| CostProfile {
+                name: profile.name.clone(),
+                forward_costs: pick_edges(&profile.forward_costs),
+                backward_costs: pick_edges(&profile.backward_costs),
+            })
+            .collect(),
+        turns,
+        u_turn_cost: map.u_turn_cost,
+        costs_in_seconds: map.costs_in_seconds,
+        node_elevations: if map.node_elevations.is_empty() {
+            Vec::new()
+        } else {
+            node_ids
+                .iter()
+                .map(|id| map.node_elevations[*id as usize])
+                .collect()
+        },
+        osm_way_ids: if map.osm_way_ids.is_empty() {
+            Vec::new()
+        } else {
+            edge_ids.iter().map(|idx| map.osm_way_ids[*idx]).collect()
+        },
+        osm_node_ids: if map.osm_node_ids.is_empty() {
+            Vec::new()
+        } else {
+            node_ids
+                .iter()
+                .map(|id| map.osm_node_ids[*id as usize])
+                .collect()
+        },
+        tile: Some(TileInfo {
+            size_degrees,
+            x,
+            y,
+            edge_ids: edge_ids.into_iter().map(|idx| idx as u32).collect(),
+            node_ids,
+        }),
+        contraction_hierarchy: None,
+    }
+}
+
+/// Splits a graph into tiles and writes each one to `{x}_{y}.bin` in a directory, along with an
+/// `index.json` listing the tile size and every tile. Returns the number of tiles.
+pub fn write_tiles(
+    map: &RouteSnapperMap,
+    size_degrees: f64,
+    compression: Compression,
+    dir: &Path,
+) -> Result<usize> {
+    let tiles = split(map, size_degrees)?;
+    std::fs::create_dir_all(dir)?;
+    let mut index = Vec::new();
+    for tile in &tiles {
+        let info = tile.tile.as_ref().unwrap();
+        std::fs::write(
+            dir.join(format!("{}_{}.bin", info.x, info.y)),
+            tile.to_bytes_with(compression)?,
+        )?;
+        index.push(format!("[{}, {}]", info.x, info.y));
+    }
+    std::fs::write(
+        dir.join("index.json"),
+        format!(
+            "{{\"tile_size_degrees\": {size_degrees}, \"tiles\": [{}]}}\n",
+            index.join(", ")
+        ),
+    )?;
+    Ok(tiles.len())
+}
+
+/// Combines tiles into one graph as they're loaded. Nodes, edges, and turns in more than one tile
+/// are only added once. IDs in the combined graph don't change as more tiles are added.
+pub struct TileSet {
+    size_degrees: f64,
+    loaded: HashSet<(i32, i32)>,
+    // From IDs in the whole graph to IDs in the combined graph
+    nodes: HashMap<u32, NodeID>,
+    edges: HashMap<u32, EdgeID>,
+    turns: HashSet<(EdgeID, NodeID, EdgeID)>,
+}
+
+impl TileSet {
+    /// Starts a combined graph from its first tile.
+    pub fn start(tile: RouteSnapperMap) -> Result<(TileSet, RouteSnapperMap)> {
+        let Some(info) = &tile.tile else {
+            bail!("This graph isn't a tile");
+        };
+        let mut tiles = TileSet {
+            size_degrees: info.size_degrees,
+            loaded: HashSet::new(),
+            nodes: HashMap::new(),
+            edges: HashMap::new(),
+            turns: HashSet::new(),
+        };
+        let mut map = RouteSnapperMap {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            override_forward_costs: Vec::new(),
+            override_backward_costs: Vec::new(),
+            attributes: AttributeTable::default(),
+            cost_profiles: tile
+                .cost_profiles
+                .iter()
+                .map(|profile| CostProfile {
+                    name: profile.name.clone(),
+                    forward_costs: Vec::new(),
+                    backward_costs: Vec::new(),
+                })
+                .collect(),
+            turns: Vec::new(),
+            u_turn_cost: tile.u_turn_cost,
+            costs_in_seconds: tile.costs_in_seconds,
+            node_elevations: Vec::new(),
+            osm_way_ids: Vec::new(),
+            osm_node_ids: Vec::new(),
+            tile: None,
+            contraction_hierarchy: None,
+        };
+        tiles.add(&mut map, tile)?;
+        Ok((tiles, map))
+    }
+
+    pub fn size_degrees(&self) -> f64 {
+        self.size_degrees
+    }
+
+    pub fn is_loaded(&self, x: i32, y: i32) -> bool {
+        self.loaded.contains(&(x, y))
+    }
+
+    /// Adds a tile's nodes, edges, and turns to `map`, skipping anything already there. Returns
+    /// false if this tile was already added. Every tile must come from the same split graph. The
+    /// whole tile is checked first, so if this fails, nothing changes.
+    pub fn add(&mut self, map: &mut RouteSnapperMap, mut tile: RouteSnapperMap) -> Result<bool> {
+        let Some(info) = tile.tile.take() else {
+            bail!("This graph isn't a tile");
+        };
+        if let Err(err) = self.check(map, &tile, &info) {
+            bail!(
+                "Tile ({}, {}) doesn't match the other tiles: {err}",
+                info.x,
+                info.y
+            );
+        }
+        if !self.loaded.insert((info.x, info.y)) {
+            return Ok(false);
+        }
+
+        let mut node_ids = Vec::new();
+        for (local, global) in info.node_ids.iter().enumerate() {
+            let id = *self.nodes.entry(*global).or_insert_with(|| {
+                map.nodes.push(tile.nodes[local]);
+                if let Some(elevation) = tile.node_elevations.get(local) {
+                    map.node_elevations.push(*elevation);
+                }
+                if let Some(osm_id) = tile.osm_node_ids.get(local) {
+                    map.osm_node_ids.push(*osm_id);
+                }
+                NodeID(map.nodes.len() as u32 - 1)
+            });
+            node_ids.push(id);
+        }
+
+        let mut edge_ids = Vec::new();
+        for (local, (global, edge)) in info.edge_ids.iter().zip(tile.edges).enumerate() {
+            if let Some(id) = self.edges.get(global) {
+                edge_ids.push(*id);
+                continue;
+            }
+            let id = EdgeID(map.edges.len() as u32);
+            self.edges.insert(*global, id);
+            edge_ids.push(id);
+
+            map.edges.push(Edge {
+                node1: node_ids[edge.node1.0 as usize],
+                node2: node_ids[edge.node2.0 as usize],
+                ..edge
+            });
+            if let Some(cost) = tile.override_forward_costs.get(local) {
+                map.override_forward_costs.push(*cost);
+            }
+            if let Some(cost) = tile.override_backward_costs.get(local) {
+                map.override_backward_costs.push(*cost);
+            }
+            for (profile, tile_profile) in map.cost_profiles.iter_mut().zip(&tile.cost_profiles) {
+                if let Some(cost) = tile_profile.forward_costs.get(local) {
+                    profile.forward_costs.push(*cost);
+                }
+                if let Some(cost) = tile_profile.backward_costs.get(local) {
+                    profile.backward_costs.push(*cost);
+                }
+            }
+            for (key, value) in tile.attributes.get(EdgeID(local as u32)) {
+                map.attributes.set(id, key, value);
+            }
+            if let Some(osm_id) = tile.osm_way_ids.get(local) {
+                map.osm_way_ids.push(*osm_id);
+            }
+        }
+
+        for turn in tile.turns {
+            let turn = Turn {
+                from: edge_ids[turn.from.0 as usize],
+                via: node_ids[turn.via.0 as usize],
+                to: edge_ids[turn.to.0 as usize],
+                cost: turn.cost,
+            };
+            if self.turns.insert((turn.from, turn.via, turn.to)) {
+                map.turns.push(turn);
+            }
+        }
+        Ok(true)
+    }
+
+    // Makes sure everything in the tile lines up, so adding it can't panic or leave the lists in
+    // `map` out of sync with its nodes and edges
+    fn check(&self, map: &RouteSnapperMap, tile: &RouteSnapperMap, info: &TileInfo) -> Result<()> {
+        if info.size_degrees != self.size_degrees {
+            bail!("it has a different size");
+        }
+        if info.node_ids.len() != tile.nodes.len() || info.edge_ids.len() != tile.edges.len() {
+            bail!("it doesn't have an ID for every node and edge");
+        }
+        if tile.cost_profiles.len() != map.cost_profiles.len()
+            || tile
+                .cost_profiles
+                .iter()
+                .zip(&map.cost_profiles)
+                .any(|(a, b)| a.name != b.name)
+        {
+            bail!("it has different cost profiles");
+        }
+
+        // Optional lists are either used by every tile or none. When used, they have a value for
+        // every node or edge.
+        let first = self.loaded.is_empty();
+        let list_matches = |name: &str, map_len: usize, tile_len: usize, expected: usize| {
+            let used = tile_len == expected && (first || map_len > 0 || expected == 0);
+            let unused = tile_len == 0 && (first || map_len == 0);
+            if used || unused {
+                Ok(())
+            } else {
+                Err(anyhow!("it has {tile_len} {name} for {expected}"))
+            }
+        };
+        let num_edges = tile.edges.len();
+        for ((map_forward, map_backward), (tile_forward, tile_backward)) in
+            map.cost_tables().zip(tile.cost_tables())
+        {
+            list_matches(
+                "forward costs",
+                map_forward.len(),
+                tile_forward.len(),
+                num_edges,
+            )?;
+            list_matches(
+                "backward costs",
+                map_backward.len(),
+                tile_backward.len(),
+                num_edges,
+            )?;
+        }
+        list_matches(
+            "OSM way IDs",
+            map.osm_way_ids.len(),
+            tile.osm_way_ids.len(),
+            num_edges,
+        )?;
+        let num_nodes = tile.nodes.len();
+        list_matches(
+            "elevations",
+            map.node_elevations.len(),
+            tile.node_elevations.len(),
+            num_nodes,
+        )?;
+        list_matches(
+            "OSM node IDs",
+            map.osm_node_ids.len(),
+            tile.osm_node_ids.len(),
+            num_nodes,
+        )?;
+        tile.attributes.check_indices()?;
+
+        let node_exists = |id: NodeID| (id.0 as usize) < num_nodes;
+        let edge_exists = |id: EdgeID| (id.0 as usize) < num_edges;
+        if !tile
+            .edges
+            .iter()
+            .all(|e| node_exists(e.node1) && node_exists(e.node2))
+        {
+            bail!("an edge uses a node that doesn't exist");
+        }
+        if !tile
+            .turns
+            .iter()
+            .all(|t| edge_exists(t.from) && node_exists(t.via) && edge_exists(t.to))
+        {
+            bail!("a turn uses an edge or node that doesn't exist");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_merge() {
+        let map = RouteSnapperMap::from_bytes(&std::fs::read("../examples/southwark.bin").unwrap())
+            .unwrap();
+        assert!(split(&map, 0.0).is_err());
+        let tiles = split(&map, 0.005).unwrap();
+        assert!(tiles.len() > 1);
+        assert!(split(&tiles[0], 0.005).is_err());
+
+        // Tiles keep where they came from through serialization
+        let mut tiles: Vec<RouteSnapperMap> = tiles
+            .into_iter()
+            .map(|tile| {
+                let copy = RouteSnapperMap::from_bytes(&tile.to_bytes().unwrap()).unwrap();
+                assert_eq!(copy.tile, tile.tile);
+                copy
+            })
+            .collect();
+
+        // Load them in any order
+        let first = tiles.pop().unwrap();
+        let info = first.tile.clone().unwrap();
+        let first_bytes = first.to_bytes().unwrap();
+        let (mut tile_set, mut combined) = TileSet::start(first).unwrap();
+        assert!(tile_set.is_loaded(info.x, info.y));
+        for tile in tiles.into_iter().rev() {
+            assert!(tile_set.add(&mut combined, tile).unwrap());
+        }
+        let again = RouteSnapperMap::from_bytes(&first_bytes).unwrap();
+        assert!(!tile_set.add(&mut combined, again).unwrap());
+
+        // Edges crossing tiles are only added once
+        assert_eq!(combined.nodes.len(), map.nodes.len());
+        assert_eq!(combined.edges.len(), map.edges.len());
+        // The same edges connect the same nodes
+        let sorted_edges = |map: &RouteSnapperMap| {
+            let mut edges: Vec<String> = map
+                .edges
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{:?} {:?} {:?} {:?}",
+                        e.geometry,
+                        e.name,
+                        map.node(e.node1),
+                        map.node(e.node2)
+                    )
+                })
+                .collect();
+            edges.sort();
+            edges
+        };
+        assert_eq!(sorted_edges(&combined), sorted_edges(&map));
+    }
+
+    #[test]
+    fn test_bad_tiles() {
+        let map = RouteSnapperMap::from_bytes(&std::fs::read("../examples/southwark.bin").unwrap())
+            .unwrap();
+        let mut tiles = split(&map, 0.005).unwrap();
+        let good = tiles.pop().unwrap().to_bytes().unwrap();
+        let (mut tile_set, mut combined) = TileSet::start(tiles.pop().unwrap()).unwrap();
+        let num_edges = combined.edges.len();
+
+        let tile = || RouteSnapperMap::from_bytes(&good).unwrap();
+        let mut costs_for_some_edges = tile();
+        costs_for_some_edges.override_forward_costs = vec![Some(1.0)];
+        let mut missing_node = tile();
+        missing_node.edges[0].node2 = NodeID(u32::MAX);
+        let mut missing_edge = tile();
+        missing_edge.turns.push(Turn {
+            from: EdgeID(u32::MAX),
+            via: NodeID(0),
+            to: EdgeID(0),
+            cost: None,
+        });
+        for bad in [costs_for_some_edges, missing_node, missing_edge] {
+            assert!(tile_set.add(&mut combined, bad).is_err());
+            assert_eq!(combined.edges.len(), num_edges);
+        }
+
+        // Nothing was marked as loaded, so the real tile can still be added
+        assert!(tile_set.add(&mut combined, tile()).unwrap());
+        assert!(combined.edges.len() > num_edges);
+    }
+}
@@ -0,0 +1,506 @@
+//! Checking a graph for problems that would make routing misbehave, and summarizing it.
+
+use std::fmt;
+
+use geo::{BoundingRect, Coord, HaversineDistance, HaversineLength, Point, Rect};
+
+use crate::components::find_components;
+use crate::{EdgeID, RouteSnapperMap};
+
+/// Edge geometry can be this far from its nodes before the endpoints count as mismatched. The
+/// GeoJSON importer merges points closer than a micro-degree, about 10cm.
+const ENDPOINT_TOLERANCE_METERS: f64 = 0.5;
+
+/// Only this many example edges are listed for each problem.
+const MAX_EXAMPLES: usize = 10;
+
+pub struct Report {
+    pub num_nodes: usize,
+    pub num_edges: usize,
+    pub bounds: Option<Rect>,
+    /// Number of edges in each connected component, largest first. Empty if the graph has broken
+    /// node references.
+    pub component_sizes: Vec<usize>,
+
+    /// Edges referring to nodes that don't exist
+    pub bad_node_references: Vec<EdgeID>,
+    /// Edges whose geometry doesn't start at `node1` or end at `node2`
+    pub mismatched_endpoints: Vec<EdgeID>,
+    /// Edges with fewer than 2 points, non-finite coordinates, or no length
+    pub degenerate_geometry: Vec<EdgeID>,
+    /// For the default costs (`None`) and every cost profile, edges that can't be used in either
+    /// direction
+    pub unroutable_edges: Vec<(Option<String>, Vec<EdgeID>)>,
+    /// Other problems, like cost lists or turns that don't match the edges
+    pub other_errors: Vec<String>,
+}
+
+impl Report {
+    /// True if any problem was found. Disconnected components alone don't count.
+    pub fn has_problems(&self) -> bool {
+        !self.bad_node_references.is_empty()
+            || !self.mismatched_endpoints.is_empty()
+            || !self.degenerate_geometry.is_empty()
+            || self.unroutable_edges.iter().any(|(_, x)| !x.is_empty())
+            || !self.other_errors.is_empty()
+    }
+}
+
+/// Checks the graph and gathers statistics about it.
+pub fn validate(map: &RouteSnapperMap) -> Report {
+    let mut report = Report {
+        num_nodes: map.nodes.len(),
+        num_edges: map.edges.len(),
+        bounds: None,
+        component_sizes: Vec::new(),
+        bad_node_references: Vec::new(),
+        mismatched_endpoints: Vec::new(),
+        degenerate_geometry: Vec::new(),
+        unroutable_edges: Vec::new(),
+        other_errors: Vec::new(),
+    };
+
+    for (idx, edge) in map.edges.iter().enumerate() {
+        let id = EdgeID(idx as u32);
+        let pts = &edge.geometry.0;
+
+        if pts.len() < 2
+            || pts.iter().any(|pt| !pt.x.is_finite() || !pt.y.is_finite())
+            || edge.geometry.haversine_length() == 0.0
+        {
+            report.degenerate_geometry.push(id);
+        }
+
+        let (Some(node1), Some(node2)) = (
+            map.nodes.get(edge.node1.0 as usize),
+            map.nodes.get(edge.node2.0 as usize),
+        ) else {
+            report.bad_node_references.push(id);
+            continue;
+        };
+        let (Some(first), Some(last)) = (pts.first(), pts.last()) else {
+            continue;
+        };
+        if distance(*first, *node1) > ENDPOINT_TOLERANCE_METERS
+            || distance(*last, *node2) > ENDPOINT_TOLERANCE_METERS
+        {
+            report.mismatched_endpoints.push(id);
+        }
+    }
+
+    report.bounds = map
+        .nodes
+        .iter()
+        .filter(|pt| pt.x.is_finite() && pt.y.is_finite())
+        .map(|pt| Point::from(*pt))
+        .collect::<geo::MultiPoint>()
+        .bounding_rect();
+    if report.bad_node_references.is_empty() {
+        report.component_sizes = find_components(map)
+            .into_iter()
+            .map(|c| c.edges.len())
+            .collect();
+    }
+
+    let mut costs = vec![(
+        None,
+        &map.override_forward_costs,
+        &map.override_backward_costs,
+    )];
+    for profile in &map.cost_profiles {
+        costs.push((
+            Some(profile.name.clone()),
+            &profile.forward_costs,
+            &profile.backward_costs,
+        ));
+    }
+    for (name, forward, backward) in costs {
+        let label = match name {
+            Some(ref name) => format!("cost profile {name}"),
+            None => "override costs".to_string(),
+        };
+        let mut wrong_length = false;
+        for (direction, list) in [("forward", forward), ("backward", backward)] {
+            if !list.is_empty() && list.len() != map.edges.len() {
+                report.other_errors.push(format!(
+                    "{label} has {} {direction} costs for {} edges",
+                    list.len(),
+                    map.edges.len()
+                ));
+                wrong_length = true;
+            }
+        }
+        if wrong_length {
+            continue;
+        }
+
+        // Routing assumes costs can only add up
+        for (direction, list) in [("forward", forward), ("backward", backward)] {
+            let invalid: Vec<usize> = (0..list.len())
+                .filter(|idx| list[*idx].is_some_and(|x| !x.is_finite() || x < 0.0))
+                .collect();
+            if let Some(idx) = invalid.first() {
+                report.other_errors.push(format!(
+                    "{label} has {} negative or non-finite {direction} costs, like {:?} for \
+                     edge {idx}",
+                    invalid.len(),
+                    list[*idx].unwrap()
+                ));
+            }
+        }
+
+        // An empty list means every edge costs its length
+        let unroutable = (0..map.edges.len())
+            .filter(|idx| {
+                forward.get(*idx).is_some_and(|x| x.is_none())
+                    && backward.get(*idx).is_some_and(|x| x.is_none())
+            })
+            .map(|idx| EdgeID(idx as u32))
+            .collect();
+        report.unroutable_edges.push((name, unroutable));
+    }
+
+    for turn in &map.turns {
+        if turn.from.0 as usize >= map.edges.len()
+            || turn.to.0 as usize >= map.edges.len()
+            || turn.via.0 as usize >= map.nodes.len()
+        {
+            report.other_errors.push(format!(
+                "Turn from {:?} via {:?} to {:?} refers to something that doesn't exist",
+                turn.from, turn.via, turn.to
+            ));
+        }
+    }
+    if let Err(err) = map.attributes.check_indices() {
+        report.other_errors.push(err.to_string());
+    }
+    if !map.node_elevations.is_empty() && map.node_elevations.len() != map.nodes.len() {
+        report.other_errors.push(format!(
+            "There are {} node elevations for {} nodes",
+            map.node_elevations.len(),
+            map.nodes.len()
+        ));
+    }
+    if !map.osm_way_ids.is_empty() && map.osm_way_ids.len() != map.edges.len() {
+        report.other_errors.push(format!(
+            "There are {} OSM way IDs for {} edges",
+            map.osm_way_ids.len(),
+            map.edges.len()
+        ));
+    }
+    if !map.osm_node_ids.is_empty() && map.osm_node_ids.len() != map.nodes.len() {
+        report.other_errors.push(format!(
+            "There are {} OSM node IDs for {} nodes",
+            map.osm_node_ids.len(),
+            map.nodes.len()
+        ));
+    }
+    if let Some(tile) = &map.tile {
+        if tile.node_ids.len() != map.nodes.len() || tile.edge_ids.len() != map.edges.len() {
+            report.other_errors.push(format!(
+                "Tile ({}, {}) has IDs for {} nodes and {} edges, but holds {} nodes and {} edges",
+                tile.x,
+                tile.y,
+                tile.node_ids.len(),
+                tile.edge_ids.len(),
+                map.nodes.len(),
+                map.edges.len()
+            ));
+        }
+    }
+    // The hierarchy can only be checked against edges that point to real nodes
+    if let (Some(ch), true) = (
+        &map.contraction_hierarchy,
+        report.bad_node_references.is_empty(),
+    ) {
+        if let Err(err) = ch.prepare(map) {
+            report
+                .other_errors
+                .push(format!("The contraction hierarchy is broken: {err}"));
+        }
+    }
+
+    report
+}
+
+fn distance(pt1: Coord, pt2: Coord) -> f64 {
+    Point::from(pt1).haversine_distance(&Point::from(pt2))
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} nodes, {} edges", self.num_nodes, self.num_edges)?;
+        if let Some(bounds) = self.bounds {
+            writeln!(
+                f,
+                "Bounding box: {}, {} to {}, {}",
+                bounds.min().x,
+                bounds.min().y,
+                bounds.max().x,
+                bounds.max().y
+            )?;
+        }
+        match self.component_sizes.len() {
+            0 => {}
+            1 => writeln!(f, "The graph is fully connected")?,
+            n => {
+                let sizes: Vec<String> = self
+                    .component_sizes
+                    .iter()
+                    .take(MAX_EXAMPLES)
+                    .map(|x| x.to_string())
+                    .collect();
+                let more = if n > MAX_EXAMPLES { ", ..." } else { "" };
+                writeln!(
+                    f,
+                    "{n} connected components, with these many edges: {}{more}",
+                    sizes.join(", ")
+                )?;
+            }
+        }
+
+        write_problem(
+            f,
+            "edges refer to nodes that don't exist",
+            &self.bad_node_references,
+        )?;
+        write_problem(
+            f,
+            "edges have geometry that doesn't match their nodes",
+            &self.mismatched_endpoints,
+        )?;
+        write_problem(
+            f,
+            "edges have degenerate geometry",
+            &self.degenerate_geometry,
+        )?;
+        for (name, edges) in &self.unroutable_edges {
+            let label = match name {
+                Some(name) => format!("edges can't be used in either direction with {name} costs"),
+                None => "edges can't be used in either direction".to_string(),
+            };
+            write_problem(f, &label, edges)?;
+        }
+        for error in &self.other_errors {
+            writeln!(f, "Problem: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_problem(f: &mut fmt::Formatter<'_>, label: &str, edges: &[EdgeID]) -> fmt::Result {
+    if edges.is_empty() {
+        return Ok(());
+    }
+    let examples: Vec<String> = edges
+        .iter()
+        .take(MAX_EXAMPLES)
+        .map(|e| e.0.to_string())
+        .collect();
+    let more = if edges.len() > MAX_EXAMPLES {
+        ", ..."
+    } else {
+        ""
+    };
+    writeln!(
+        f,
+        "Problem: {} {label}: {}{more}",
+        edges.len(),
+        examples.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::LineString;
+
+    use super::*;
+    use crate::test_util::make_map;
+    use crate::tiles::TileInfo;
+    use crate::{CostProfile, NodeID, Turn};
+
+    // Two edges in a line
+    fn line() -> RouteSnapperMap {
+        make_map(
+            vec![
+                Coord { x: 0.0, y: 51.5 },
+                Coord { x: 0.001, y: 51.5 },
+                Coord { x: 0.002, y: 51.5 },
+            ],
+            &[(0, 1), (1, 2)],
+        )
+    }
+
+    #[test]
+    fn test_valid() {
+        let report = validate(&line());
+        assert!(!report.has_problems());
+        assert_eq!(report.component_sizes, vec![2]);
+        assert!(report.to_string().contains("The graph is fully connected"));
+    }
+
+    #[test]
+    fn test_mismatched_endpoints() {
+        let mut map = line();
+        map.edges[0].geometry = LineString::from(vec![(0.0, 51.6), (0.001, 51.5)]);
+        let report = validate(&map);
+        assert_eq!(report.mismatched_endpoints, vec![EdgeID(0)]);
+        assert!(report
+            .to_string()
+            .contains("Problem: 1 edges have geometry that doesn't match their nodes: 0"));
+    }
+
+    #[test]
+    fn test_degenerate_geometry() {
+        let mut map = line();
+        map.edges[1].geometry = LineString::from(vec![(0.001, 51.5), (0.001, 51.5)]);
+        let report = validate(&map);
+        assert_eq!(report.degenerate_geometry, vec![EdgeID(1)]);
+        assert!(report.has_problems());
+    }
+
+    #[test]
+    fn test_unroutable_edges() {
+        let mut map = line();
+        map.override_forward_costs = vec![Some(1.0), None];
+        map.override_backward_costs = vec![None, None];
+        map.cost_profiles.push(CostProfile {
+            name: "closed".to_string(),
+            forward_costs: vec![None, None],
+            backward_costs: vec![None, None],
+        });
+        let report = validate(&map);
+        assert_eq!(
+            report.unroutable_edges,
+            vec![
+                (None, vec![EdgeID(1)]),
+                (Some("closed".to_string()), vec![EdgeID(0), EdgeID(1)])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cost_lengths() {
+        let mut map = line();
+        map.override_forward_costs = vec![Some(1.0)];
+        map.override_backward_costs = vec![Some(1.0), Some(1.0)];
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec!["override costs has 1 forward costs for 2 edges"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_costs() {
+        let mut map = line();
+        map.override_forward_costs = vec![Some(f64::NAN), Some(1.0)];
+        map.override_backward_costs = vec![Some(1.0), Some(-1.0)];
+        map.cost_profiles.push(CostProfile {
+            name: "bike".to_string(),
+            forward_costs: vec![Some(1.0), Some(f64::INFINITY)],
+            backward_costs: Vec::new(),
+        });
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec![
+                "override costs has 1 negative or non-finite forward costs, like NaN for edge 0",
+                "override costs has 1 negative or non-finite backward costs, like -1.0 for edge 1",
+                "cost profile bike has 1 negative or non-finite forward costs, like inf for edge 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_turn() {
+        let mut map = line();
+        map.turns.push(Turn {
+            from: EdgeID(0),
+            via: NodeID(1),
+            to: EdgeID(2),
+            cost: None,
+        });
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec!["Turn from EdgeID(0) via NodeID(1) to EdgeID(2) refers to something that doesn't exist"]
+        );
+    }
+
+    #[test]
+    fn test_attribute_indices() {
+        let mut map = line();
+        map.attributes.set(EdgeID(1), "surface", "gravel");
+        assert!(!validate(&map).has_problems());
+
+        map.attributes.per_edge[1][0].0 = 9;
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec!["Attributes for edge 1 refer to string 9, but there are only 2 strings"]
+        );
+    }
+
+    #[test]
+    fn test_per_node_and_edge_lists() {
+        let mut map = line();
+        map.node_elevations = vec![1.0];
+        map.osm_way_ids = vec![1, 2, 3];
+        map.osm_node_ids = vec![Some(1)];
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec![
+                "There are 1 node elevations for 3 nodes",
+                "There are 3 OSM way IDs for 2 edges",
+                "There are 1 OSM node IDs for 3 nodes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tile_ids() {
+        let mut map = line();
+        map.tile = Some(TileInfo {
+            size_degrees: 0.01,
+            x: 0,
+            y: 5150,
+            node_ids: vec![0, 1, 2],
+            edge_ids: vec![0],
+        });
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec!["Tile (0, 5150) has IDs for 3 nodes and 1 edges, but holds 3 nodes and 2 edges"]
+        );
+    }
+
+    #[test]
+    fn test_broken_hierarchy() {
+        let mut map = line();
+        map.contraction_hierarchy = Some(crate::ch::build(&map));
+        map.nodes.push(Coord { x: 0.003, y: 51.5 });
+        let report = validate(&map);
+        assert_eq!(
+            report.other_errors,
+            vec!["The contraction hierarchy is broken: the hierarchy has 3 nodes, but the graph has 4"]
+        );
+    }
+
+    #[test]
+    fn test_bad_node_reference_with_hierarchy() {
+        let mut map = make_map(
+            vec![Coord { x: 0.0, y: 51.5 }, Coord { x: 0.001, y: 51.5 }],
+            &[(0, 1)],
+        );
+        map.contraction_hierarchy = Some(crate::ch::build(&map));
+        assert!(!validate(&map).has_problems());
+
+        map.edges[0].node2 = NodeID(5);
+        let report = validate(&map);
+        assert_eq!(report.bad_node_references, vec![EdgeID(0)]);
+        // The hierarchy isn't checked against the broken edge
+        assert!(report.other_errors.is_empty());
+    }
+}
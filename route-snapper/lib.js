@@ -161,6 +161,66 @@ export class RouteSnapper {
     console.timeEnd("Deserialize and setup JsRouteSnapper with new graph");
   }
 
+  // If the graph was created from one tile of a graph split with
+  // `--tile-size`, add another tile from the same graph. Returns false if the
+  // tile was already added. The current route is recalculated.
+  addTile(tileBytes) {
+    let added = this.inner.addTile(tileBytes);
+    if (added) {
+      this.#redraw();
+    }
+    return added;
+  }
+
+  // Add tiles as they come into view. `baseUrl` is the directory written by
+  // an importer with `--tile-size`, holding `index.json` and `{x}_{y}.bin`
+  // files, and the graph must have been created from one of those tiles.
+  // Nothing is loaded when more than `maxTiles` tiles are in view, so zooming
+  // far out doesn't download the whole graph.
+  async followTiles(baseUrl, maxTiles = 16) {
+    let index = await (await fetch(`${baseUrl}/index.json`)).json();
+    let size = index.tile_size_degrees;
+    let available = new Set(index.tiles.map(([x, y]) => `${x}_${y}`));
+    let requested = new Set();
+
+    let loadVisible = async () => {
+      let bounds = this.map.getBounds();
+      let x1 = Math.floor(bounds.getWest() / size);
+      let x2 = Math.floor(bounds.getEast() / size);
+      let y1 = Math.floor(bounds.getSouth() / size);
+      let y2 = Math.floor(bounds.getNorth() / size);
+      if ((x2 - x1 + 1) * (y2 - y1 + 1) > maxTiles) {
+        return;
+      }
+
+      let names = [];
+      for (let x = x1; x <= x2; x++) {
+        for (let y = y1; y <= y2; y++) {
+          let name = `${x}_${y}`;
+          if (available.has(name) && !requested.has(name)) {
+            requested.add(name);
+            names.push(name);
+          }
+        }
+      }
+      await Promise.all(
+        names.map(async (name) => {
+          try {
+            let resp = await fetch(`${baseUrl}/${name}.bin`);
+            this.addTile(new Uint8Array(await resp.arrayBuffer()));
+          } catch (err) {
+            console.error(`Couldn't load tile ${name}: ${err}`);
+            // Try again next time the map moves
+            requested.delete(name);
+          }
+        })
+      );
+    };
+
+    this.map.on("moveend", loadVisible);
+    await loadVisible();
+  }
+
   isActive() {
     return this.active;
   }
@@ -270,6 +330,12 @@ export class RouteSnapper {
         Extend the route
       </label>
     </div>
+    <div>
+      <label>
+        <input type="checkbox" id="snapToEdges" />
+        Snap anywhere along roads
+      </label>
+    </div>
     <div>
       <label>
         <input type="checkbox" id="areaMode" />
@@ -314,23 +380,28 @@ export class RouteSnapper {
     let avoidDoublingBack = document.getElementById("avoidDoublingBack");
     let areaMode = document.getElementById("areaMode");
     let extendRoute = document.getElementById("extendRoute");
+    let snapToEdges = document.getElementById("snapToEdges");
     avoidDoublingBack.onclick = () => {
       this.inner.setRouteConfig({
         avoid_doubling_back: avoidDoublingBack.checked,
         extend_route: extendRoute.checked,
+        snap_to_edges: snapToEdges.checked,
       });
       this.#redraw();
     };
     extendRoute.onclick = avoidDoublingBack.onclick;
+    snapToEdges.onclick = avoidDoublingBack.onclick;
     areaMode.onclick = () => {
       if (areaMode.checked) {
         avoidDoublingBack.checked = true;
         extendRoute.checked = true;
+        snapToEdges.checked = false;
         this.inner.setAreaMode();
       } else {
         this.inner.setRouteConfig({
           avoid_doubling_back: avoidDoublingBack.checked,
           extend_route: extendRoute.checked,
+          snap_to_edges: snapToEdges.checked,
         });
       }
       this.#redraw();
@@ -355,9 +426,17 @@ export class RouteSnapper {
     let config = JSON.parse(this.inner.getConfig());
     avoidDoublingBack.checked = config.avoid_doubling_back;
     extendRoute.checked = config.extend_route;
+    snapToEdges.checked = config.snap_to_edges;
     areaMode.checked = config.area_mode;
   }
 
+  // Switch to one of the named cost profiles in the graph, or pass null to use
+  // the default costs. The current route is recalculated.
+  setCostProfile(name) {
+    this.inner.setCostProfile(name);
+    this.#redraw();
+  }
+
   // Render the graph as GeoJSON points and line-strings, for debugging.
   debugRenderGraph() {
     return this.inner.debugRenderGraph();
@@ -1,33 +1,48 @@
 #[macro_use]
 extern crate log;
 
+mod routing;
 #[cfg(test)]
 mod tests;
+mod turns;
 
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Write;
 use std::sync::Once;
 
-use geo::{Coord, HaversineDistance, HaversineLength, LineString, Point, Polygon};
+use geo::{
+    Coord, EuclideanLength, HaversineDistance, HaversineLength, LineInterpolatePoint,
+    LineLocatePoint, LineString, Point, Polygon,
+};
 use geojson::{Feature, FeatureCollection, Geometry};
 use petgraph::graphmap::DiGraphMap;
-use rstar::primitives::GeomWithData;
+use petgraph::{Incoming, Outgoing};
+use rstar::primitives::{GeomWithData, Line};
 use rstar::RTree;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+use route_snapper_graph::ch::PreparedHierarchy;
+use route_snapper_graph::tiles::TileSet;
 use route_snapper_graph::{EdgeID, NodeID, RouteSnapperMap};
 
+use crate::routing::{AStar, Pathfinder};
+
 static START: Once = Once::new();
 
 const MAX_PREVIOUS_STATES: usize = 100;
 
 type Graph = DiGraphMap<NodeID, DirectedEdge>;
+type NodeTree = RTree<GeomWithData<[f64; 2], NodeID>>;
+type EdgeTree = RTree<GeomWithData<Line<[f64; 2]>, EdgeID>>;
 
 #[wasm_bindgen]
 pub struct JsRouteSnapper {
     router: Router,
-    snap_to_nodes: RTree<GeomWithData<[f64; 2], NodeID>>,
+    snap_to_nodes: NodeTree,
+    snap_to_edges: EdgeTree,
+    // Only used if the graph was split into tiles
+    tiles: Option<TileSet>,
     route: Route,
     mode: Mode,
     snap_mode: bool,
@@ -43,6 +58,10 @@ struct Config {
     /// If false, the user can only drag waypoints after specifying the start and end of the route.
     /// If true, they can keep clicking to extend the end of the route.
     extend_route: bool,
+    /// Let snapped waypoints be placed anywhere along an edge, not just at nodes. Nodes close to
+    /// the cursor are still preferred.
+    #[serde(default)]
+    snap_to_edges: bool,
 
     /// Generate a route that starts and ends in the same place. Has to be set using `setAreaMode`,
     /// but `getConfig` will show this.
@@ -55,6 +74,14 @@ struct Router {
     map: RouteSnapperMap,
     graph: Graph,
     config: Config,
+    // The name of the cost profile currently in use, or None for the default costs
+    cost_profile: Option<String>,
+    // (from, via, to) => cost, or None if the turn is forbidden
+    turns: HashMap<(EdgeID, NodeID, EdgeID), Option<f64>>,
+    // Only if the importer built one
+    contraction_hierarchy: Option<PreparedHierarchy>,
+    // Scales straight-line distance into a lower bound on cost, for the A* heuristic
+    min_cost_per_meter: f64,
 }
 
 // TODO It's impossible for a waypoint to be an Edge, but the code might be simpler if this and
@@ -63,6 +90,8 @@ struct Router {
 enum Waypoint {
     Snapped(NodeID),
     Free(Coord),
+    // Snapped somewhere in the middle of an edge
+    OnEdge(EdgePosition),
 }
 
 impl Waypoint {
@@ -70,22 +99,39 @@ impl Waypoint {
         match self {
             Waypoint::Snapped(x) => PathEntry::SnappedPoint(x),
             Waypoint::Free(x) => PathEntry::FreePoint(x),
+            Waypoint::OnEdge(x) => PathEntry::EdgePoint(x),
         }
     }
 
     fn to_color_name(self) -> &'static str {
         match self {
-            Waypoint::Snapped(_) => "snapped-waypoint",
+            Waypoint::Snapped(_) | Waypoint::OnEdge(_) => "snapped-waypoint",
             Waypoint::Free(_) => "free-waypoint",
         }
     }
 }
 
+/// A point partway along an edge. The fraction is 0 at `node1` and 1 at `node2`, measured by
+/// Euclidean length along the geometry.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct EdgePosition {
+    edge: EdgeID,
+    fraction: f64,
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum PathEntry {
     SnappedPoint(NodeID),
     FreePoint(Coord),
+    EdgePoint(EdgePosition),
     Edge(DirectedEdge),
+    // Part of an edge, starting or ending at an EdgePoint. The fractions are in the same order as
+    // the direction of travel.
+    PartialEdge {
+        dir_edge: DirectedEdge,
+        from: f64,
+        to: f64,
+    },
     // Note we don't need to represent a straight line between snapped or free points here. As we
     // build up the line-string, they'll happen anyway.
 }
@@ -95,7 +141,8 @@ impl PathEntry {
         match self {
             PathEntry::SnappedPoint(x) => Some(Waypoint::Snapped(x)),
             PathEntry::FreePoint(x) => Some(Waypoint::Free(x)),
-            PathEntry::Edge(_) => None,
+            PathEntry::EdgePoint(x) => Some(Waypoint::OnEdge(x)),
+            PathEntry::Edge(_) | PathEntry::PartialEdge { .. } => None,
         }
     }
 }
@@ -113,7 +160,7 @@ type Direction = bool;
 const FORWARDS: Direction = true;
 const BACKWARDS: Direction = false;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 struct DirectedEdge(EdgeID, Direction);
 
 #[derive(Clone, Debug, PartialEq)]
@@ -142,64 +189,43 @@ impl JsRouteSnapper {
 
         info!("Got {} bytes, deserializing", map_bytes.len());
 
-        let mut map: RouteSnapperMap = bincode::deserialize(map_bytes).map_err(err_to_js)?;
-
-        if !map.override_forward_costs.is_empty()
-            && map.override_forward_costs.len() != map.edges.len()
-        {
-            return Err(JsValue::from_str(
-                "override_forward_costs length doesn't match edges length",
-            ));
-        }
-        if !map.override_backward_costs.is_empty()
-            && map.override_backward_costs.len() != map.edges.len()
-        {
-            return Err(JsValue::from_str(
-                "override_backward_costs length doesn't match edges length",
-            ));
-        }
-
-        for (idx, edge) in map.edges.iter_mut().enumerate() {
-            edge.length_meters = edge.geometry.haversine_length();
-
-            if map.override_forward_costs.is_empty() {
-                edge.forward_cost = Some(edge.length_meters);
-            } else {
-                edge.forward_cost = map.override_forward_costs[idx];
-            }
+        let map = RouteSnapperMap::from_bytes(map_bytes).map_err(err_to_js)?;
+        // A tile starts a graph that grows as more tiles are added
+        let (tiles, mut map) = if map.tile.is_some() {
+            let (tiles, map) = TileSet::start(map).map_err(err_to_js)?;
+            (Some(tiles), map)
+        } else {
+            (None, map)
+        };
 
-            if map.override_backward_costs.is_empty() {
-                edge.backward_cost = Some(edge.length_meters);
-            } else {
-                edge.backward_cost = map.override_backward_costs[idx];
-            }
-        }
+        prepare_edges(&mut map, 0, None).map_err(err_to_js)?;
 
         info!("Finalizing JsRouteSnapper");
 
-        let mut graph: Graph = DiGraphMap::new();
-        for (idx, e) in map.edges.iter().enumerate() {
-            let id = EdgeID(idx as u32);
-            graph.add_edge(e.node1, e.node2, DirectedEdge(id, FORWARDS));
-            graph.add_edge(e.node2, e.node1, DirectedEdge(id, BACKWARDS));
-        }
-
-        // Euclidean distance on WGS84 coordinates works because we're just finding the closest
-        // point to the cursor, and always in a pretty small area. Using GeodesicDistance as a
-        // distance function is an alternative.
-        let mut nodes = Vec::new();
-        for (idx, pt) in map.nodes.iter().enumerate() {
-            nodes.push(GeomWithData::new([pt.x, pt.y], NodeID(idx as u32)));
-        }
-        let snap_to_nodes = RTree::bulk_load(nodes);
+        let graph = build_graph(&map);
+        let min_cost_per_meter = min_cost_per_meter(&map);
+        let turns = build_turns(&map);
+        let (snap_to_nodes, snap_to_edges) = build_rtrees(&map);
+        let contraction_hierarchy = map
+            .contraction_hierarchy
+            .as_ref()
+            .map(|ch| ch.prepare(&map))
+            .transpose()
+            .map_err(err_to_js)?;
 
         Ok(Self {
             router: Router {
                 map,
                 graph,
                 config: Config::default(),
+                cost_profile: None,
+                turns,
+                contraction_hierarchy,
+                min_cost_per_meter,
             },
             snap_to_nodes,
+            snap_to_edges,
+            tiles,
             route: Route::new(),
             mode: Mode::Neutral,
             snap_mode: true,
@@ -229,6 +255,7 @@ impl JsRouteSnapper {
         self.router.config = Config {
             avoid_doubling_back: true,
             extend_route: true,
+            snap_to_edges: false,
             area_mode: true,
         };
         self.route.recalculate_full_path(&self.router);
@@ -240,6 +267,67 @@ impl JsRouteSnapper {
         serde_json::to_string_pretty(&self.router.config).unwrap()
     }
 
+    /// Switches to one of the named cost profiles in the graph, or the default costs if `name` is
+    /// null, and recalculates paths. The caller should redraw.
+    #[wasm_bindgen(js_name = setCostProfile)]
+    pub fn set_cost_profile(&mut self, name: Option<String>) -> Result<(), JsValue> {
+        set_edge_costs(&mut self.router.map, name.as_deref()).map_err(err_to_js)?;
+        self.router.graph = build_graph(&self.router.map);
+        self.router.min_cost_per_meter = min_cost_per_meter(&self.router.map);
+        self.router.cost_profile = name;
+        self.route.recalculate_full_path(&self.router);
+        Ok(())
+    }
+
+    /// Adds another tile of a graph split with `--tile-size`, if the snapper was created from a
+    /// tile of the same graph. Returns false if this tile was already added. Existing waypoints
+    /// stay put, and paths are recalculated in case the new tile offers a better one. The caller
+    /// should redraw.
+    #[wasm_bindgen(js_name = addTile)]
+    pub fn add_tile(&mut self, tile_bytes: &[u8]) -> Result<bool, JsValue> {
+        let Some(tiles) = &mut self.tiles else {
+            return Err(err_to_js("This graph isn't split into tiles"));
+        };
+        let tile = RouteSnapperMap::from_bytes(tile_bytes).map_err(err_to_js)?;
+        // Check everything before changing the map, so a bad tile leaves the snapper as it was.
+        // TileSet::add checks the rest of the tile before adding anything.
+        check_all_cost_lengths(&tile).map_err(err_to_js)?;
+        let first_new_edge = self.router.map.edges.len();
+        if !tiles.add(&mut self.router.map, tile).map_err(err_to_js)? {
+            return Ok(false);
+        }
+
+        // The tile's costs line up with its edges and its profiles match the map's, so this
+        // can't fail
+        let map = &mut self.router.map;
+        set_edge_lengths(map, first_new_edge);
+        set_edge_costs(map, self.router.cost_profile.as_deref()).map_err(err_to_js)?;
+        self.router.graph = build_graph(map);
+        self.router.min_cost_per_meter = min_cost_per_meter(map);
+        self.router.turns = build_turns(map);
+        (self.snap_to_nodes, self.snap_to_edges) = build_rtrees(map);
+        self.route.recalculate_full_path(&self.router);
+        Ok(true)
+    }
+
+    /// Returns a JSON object with the `available` cost profile names and the `current` one (null
+    /// for the default costs).
+    #[wasm_bindgen(js_name = getCostProfiles)]
+    pub fn get_cost_profiles(&self) -> String {
+        let available: Vec<&str> = self
+            .router
+            .map
+            .cost_profiles
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        serde_json::to_string_pretty(&serde_json::json!({
+            "available": available,
+            "current": self.router.cost_profile,
+        }))
+        .unwrap()
+    }
+
     #[wasm_bindgen(js_name = toFinalFeature)]
     pub fn to_final_feature(&self) -> Option<String> {
         let mut feature = if self.router.config.area_mode {
@@ -253,6 +341,23 @@ impl JsRouteSnapper {
             let length = linestring.haversine_length();
             let mut f = Feature::from(Geometry::from(&linestring));
             f.set_property("length_meters", length);
+            if let Some(duration) = self.route_duration() {
+                f.set_property("duration_seconds", duration);
+            }
+            if let Some(profile) = self.elevation_profile() {
+                let (mut ascent, mut descent) = (0.0, 0.0);
+                for pair in profile.windows(2) {
+                    let change = pair[1][1] - pair[0][1];
+                    if change > 0.0 {
+                        ascent += change;
+                    } else {
+                        descent -= change;
+                    }
+                }
+                f.set_property("ascent_meters", ascent);
+                f.set_property("descent_meters", descent);
+                f.set_property("elevation_profile", profile);
+            }
 
             let from_name = self.name_waypoint(&self.route.waypoints[0]);
             let to_name = self.name_waypoint(self.route.waypoints.last().as_ref().unwrap());
@@ -265,17 +370,33 @@ impl JsRouteSnapper {
         let mut waypoints = Vec::new();
         for waypt in &self.route.waypoints {
             let pt = unhash_pt(self.to_pt(*waypt));
+            let pos = match waypt {
+                Waypoint::OnEdge(pos) => Some(pos),
+                _ => None,
+            };
             waypoints.push(
                 serde_json::to_value(&RouteWaypoint {
                     lon: trim_lon_lat(pt.x),
                     lat: trim_lon_lat(pt.y),
-                    snapped: matches!(waypt, Waypoint::Snapped(_)),
+                    snapped: !matches!(waypt, Waypoint::Free(_)),
+                    on_edge: pos.is_some(),
+                    edge: pos.map(|pos| pos.edge.0),
+                    fraction: pos.map(|pos| pos.fraction),
                 })
                 .unwrap(),
             );
         }
         feature.set_property("waypoints", serde_json::Value::Array(waypoints));
 
+        let attributes = self.attribute_lengths();
+        if !attributes.is_empty() {
+            feature.set_property("attributes", serde_json::Value::Object(attributes));
+        }
+        if let Some((way_ids, node_ids)) = self.osm_ids() {
+            feature.set_property("osm_way_ids", way_ids);
+            feature.set_property("osm_node_ids", node_ids);
+        }
+
         Some(serde_json::to_string_pretty(&feature).unwrap())
     }
 
@@ -317,48 +438,32 @@ impl JsRouteSnapper {
                 (hover.to_color_name(), Some(self.name_waypoint(&hover))),
             );
 
-            if let (Some(last), Waypoint::Snapped(current)) = (self.route.waypoints.last(), hover) {
+            if let Some(last) = self.route.waypoints.last() {
                 // If we're trying to drag a point or it's a closed area, don't show this preview
-                if !self.route.is_closed_area()
-                    && !self
-                        .route
-                        .full_path
-                        .contains(&PathEntry::SnappedPoint(current))
+                if !matches!(hover, Waypoint::Free(_))
+                    && !self.route.is_closed_area()
+                    && !self.route.full_path.contains(&hover.to_path_entry())
                 {
-                    match last {
-                        Waypoint::Snapped(last) => {
-                            if let Some(entries) =
-                                self.router.pathfind(*last, current, &self.route.full_path)
-                            {
-                                for entry in entries {
-                                    // Just preview the lines, not the circles
-                                    if let PathEntry::Edge(dir_edge) = entry {
-                                        let mut f =
-                                            Feature::from(Geometry::from(&LineString::new(
-                                                edge_geometry(&self.router.map, dir_edge),
-                                            )));
-                                        f.set_property("snapped", true);
-                                        result.push(f);
-                                    }
-                                }
-                            } else {
-                                // It'll be a straight line
-                                let mut f = Feature::from(Geometry::from(&LineString::new(vec![
-                                    self.router.map.node(*last),
-                                    self.router.map.node(current),
-                                ])));
-                                f.set_property("snapped", false);
+                    if let Some(entries) =
+                        self.router
+                            .pathfind_waypoints(*last, hover, &self.route.full_path)
+                    {
+                        for entry in entries {
+                            // Just preview the lines, not the circles
+                            if let Some(pts) = self.router.entry_geometry(entry) {
+                                let mut f = Feature::from(Geometry::from(&LineString::new(pts)));
+                                f.set_property("snapped", true);
                                 result.push(f);
                             }
                         }
-                        Waypoint::Free(pt) => {
-                            let mut f = Feature::from(Geometry::from(&LineString::new(vec![
-                                *pt,
-                                self.router.map.node(current),
-                            ])));
-                            f.set_property("snapped", false);
-                            result.push(f);
-                        }
+                    } else {
+                        // It'll be a straight line
+                        let mut f = Feature::from(Geometry::from(&LineString::new(vec![
+                            self.router.waypoint_pt(*last),
+                            self.router.waypoint_pt(hover),
+                        ])));
+                        f.set_property("snapped", false);
+                        result.push(f);
                     }
                 }
             }
@@ -373,10 +478,7 @@ impl JsRouteSnapper {
             draw_circles.insert(hash_pt(pt), ("free-waypoint", None));
 
             if let Some(last) = self.route.waypoints.last() {
-                let last_pt = match *last {
-                    Waypoint::Snapped(node) => self.router.map.node(node),
-                    Waypoint::Free(pt) => pt,
-                };
+                let last_pt = self.router.waypoint_pt(*last);
                 let mut f = Feature::from(Geometry::from(&LineString::new(vec![last_pt, pt])));
                 f.set_property("snapped", false);
                 result.push(f);
@@ -469,7 +571,9 @@ impl JsRouteSnapper {
             }
             Mode::Dragging { at, idx } => {
                 let new_waypt = match at {
-                    Waypoint::Snapped(node) => Waypoint::Free(self.router.map.node(node)),
+                    Waypoint::Snapped(_) | Waypoint::OnEdge(_) => {
+                        Waypoint::Free(self.router.waypoint_pt(at))
+                    }
                     Waypoint::Free(pt) => {
                         if let Some(node) = self.mouseover_node(pt) {
                             Waypoint::Snapped(node)
@@ -531,7 +635,9 @@ impl JsRouteSnapper {
                 // Keep the same snapped/free type here. Toggling will change this current
                 // waypoint.
                 let new_waypt = match at {
-                    Waypoint::Snapped(_) => self.mouseover_node(pt).map(Waypoint::Snapped),
+                    Waypoint::Snapped(_) | Waypoint::OnEdge(_) => {
+                        self.mouseover_snapped(pt, circle_radius_meters)
+                    }
                     Waypoint::Free(_) => Some(Waypoint::Free(pt)),
                 };
                 if let Some(new_waypt) = new_waypt {
@@ -642,7 +748,7 @@ impl JsRouteSnapper {
                 // TODO Only do this for the first actual bit of drag?
                 self.before_update();
                 self.mode = Mode::Dragging { idx, at };
-                self.snap_mode = matches!(at, Waypoint::Snapped(_));
+                self.snap_mode = !matches!(at, Waypoint::Free(_));
                 return true;
             }
         }
@@ -674,19 +780,10 @@ impl JsRouteSnapper {
         let waypoints: Vec<RouteWaypoint> = serde_wasm_bindgen::from_value(raw_waypoints)?;
 
         for waypt in waypoints {
-            let pt = Coord {
-                x: waypt.lon,
-                y: waypt.lat,
-            };
-            if waypt.snapped {
-                if let Some(node) = self.mouseover_node(pt) {
-                    self.route
-                        .add_waypoint(&self.router, Waypoint::Snapped(node));
-                } else {
-                    return Err(JsValue::from_str("A waypoint didn't snap"));
-                }
+            if let Some(waypt) = self.restore_waypoint(&waypt) {
+                self.route.add_waypoint(&self.router, waypt);
             } else {
-                self.route.add_waypoint(&self.router, Waypoint::Free(pt));
+                return Err(JsValue::from_str("A waypoint didn't snap"));
             }
         }
 
@@ -706,11 +803,32 @@ impl JsRouteSnapper {
             f.set_property("forward_cost", edge.forward_cost);
             f.set_property("backward_cost", edge.backward_cost);
             f.set_property("name", edge.name.clone());
+            if let Some(id) = self.router.map.osm_way_ids.get(idx) {
+                f.set_property("osm_way_id", *id);
+            }
+            let attributes: serde_json::Map<String, serde_json::Value> = self
+                .router
+                .map
+                .attributes
+                .get(EdgeID(idx as u32))
+                .map(|(k, v)| (k.to_string(), v.into()))
+                .collect();
+            if !attributes.is_empty() {
+                f.set_property("attributes", serde_json::Value::Object(attributes));
+            }
             features.push(f);
         }
         for (idx, pt) in self.router.map.nodes.iter().enumerate() {
             let mut f = Feature::from(Geometry::from(&Point::from(*pt)));
             f.set_property("node_id", idx);
+            if let Some(elevation) = self.router.map.node_elevations.get(idx) {
+                if !elevation.is_nan() {
+                    f.set_property("elevation", *elevation);
+                }
+            }
+            if let Some(Some(id)) = self.router.map.osm_node_ids.get(idx) {
+                f.set_property("osm_node_id", *id);
+            }
             features.push(f);
         }
         let gj =
@@ -757,12 +875,8 @@ impl JsRouteSnapper {
             return Ok("???".to_string());
         }
 
-        let pt = Coord {
-            x: waypoint.lon,
-            y: waypoint.lat,
-        };
-        if let Some(node) = self.mouseover_node(pt) {
-            Ok(self.name_waypoint(&Waypoint::Snapped(node)))
+        if let Some(waypt) = self.restore_waypoint(waypoint) {
+            Ok(self.name_waypoint(&waypt))
         } else {
             return Err(JsValue::from_str("A waypoint didn't snap"));
         }
@@ -778,37 +892,91 @@ impl JsRouteSnapper {
 }
 
 impl JsRouteSnapper {
-    // Snaps first to free-drawn points, then nodes
+    // Snaps first to free-drawn points and waypoints on edges, then nodes, then edges
     fn mouseover_something(&self, pt: Coord, circle_radius_meters: f64) -> Option<Waypoint> {
         // TODO For very long routes, this'll get slow
         for waypt in &self.route.waypoints {
-            if let Waypoint::Free(x) = waypt {
-                if Point::from(*x).haversine_distance(&Point::from(pt)) < circle_radius_meters {
-                    return Some(*waypt);
-                }
+            if matches!(waypt, Waypoint::Free(_) | Waypoint::OnEdge(_))
+                && Point::from(self.router.waypoint_pt(*waypt)).haversine_distance(&Point::from(pt))
+                    < circle_radius_meters
+            {
+                return Some(*waypt);
             }
         }
 
-        let node = self.mouseover_node(pt)?;
+        let waypt = self.mouseover_snapped(pt, circle_radius_meters)?;
 
         // If we've closed off an area, don't snap to other nodes
-        if self.route.is_closed_area()
-            && !self
-                .route
-                .full_path
-                .contains(&PathEntry::SnappedPoint(node))
-        {
+        if self.route.is_closed_area() && !self.route.full_path.contains(&waypt.to_path_entry()) {
             return None;
         }
 
+        Some(waypt)
+    }
+
+    // Snaps to the nearest node. If snapping to edges is enabled and that node isn't within the
+    // radius, snap to the nearest point on an edge instead.
+    fn mouseover_snapped(&self, pt: Coord, circle_radius_meters: f64) -> Option<Waypoint> {
+        let node = self.mouseover_node(pt)?;
+        if self.router.config.snap_to_edges
+            && !self.route.is_closed_area()
+            && Point::from(self.router.map.node(node)).haversine_distance(&Point::from(pt))
+                > circle_radius_meters
+        {
+            if let Some(pos) = self.mouseover_edge(pt) {
+                return Some(Waypoint::OnEdge(pos));
+            }
+        }
         Some(Waypoint::Snapped(node))
     }
+
     fn mouseover_node(&self, pt: Coord) -> Option<NodeID> {
         let pt = [pt.x, pt.y];
         let node = self.snap_to_nodes.nearest_neighbor(&pt)?;
         Some(node.data)
     }
 
+    fn mouseover_edge(&self, pt: Coord) -> Option<EdgePosition> {
+        let edge = self.snap_to_edges.nearest_neighbor(&[pt.x, pt.y])?.data;
+        let fraction = self
+            .router
+            .map
+            .edge(edge)
+            .geometry
+            .line_locate_point(&Point::from(pt))?;
+        Some(EdgePosition { edge, fraction })
+    }
+
+    // Turns a waypoint from toFinalFeature back into a Waypoint, re-snapping it to the graph
+    fn restore_waypoint(&self, waypt: &RouteWaypoint) -> Option<Waypoint> {
+        let pt = Coord {
+            x: waypt.lon,
+            y: waypt.lat,
+        };
+        if !waypt.snapped {
+            Some(Waypoint::Free(pt))
+        } else if waypt.on_edge {
+            // Edge IDs differ between graphs, and between tiles loaded in a different order, so
+            // only use the exact position if it's where the waypoint was
+            if let (Some(edge), Some(fraction)) = (waypt.edge, waypt.fraction) {
+                if (edge as usize) < self.router.map.edges.len() && (0.0..=1.0).contains(&fraction)
+                {
+                    let pos = EdgePosition {
+                        edge: EdgeID(edge),
+                        fraction,
+                    };
+                    let actual = Point::from(self.router.edge_position_pt(pos));
+                    if actual.haversine_distance(&Point::from(pt)) < 1.0 {
+                        return Some(Waypoint::OnEdge(pos));
+                    }
+                }
+            }
+            self.mouseover_edge(pt).map(Waypoint::OnEdge)
+        } else {
+            self.mouseover_node(pt).map(Waypoint::Snapped)
+        }
+    }
+
     fn entire_line_string(&self) -> Option<LineString> {
         if self.route.full_path.is_empty() {
             return None;
@@ -825,8 +993,11 @@ impl JsRouteSnapper {
                 PathEntry::FreePoint(pt) => {
                     pts.push(*pt);
                 }
-                PathEntry::Edge(dir_edge) => {
-                    pts.extend(edge_geometry(&self.router.map, *dir_edge));
+                PathEntry::EdgePoint(pos) => {
+                    pts.push(self.router.edge_position_pt(*pos));
+                }
+                PathEntry::Edge(_) | PathEntry::PartialEdge { .. } => {
+                    pts.extend(self.router.entry_geometry(*entry).unwrap());
                 }
             }
         }
@@ -860,8 +1031,9 @@ impl JsRouteSnapper {
             let pt = match entry {
                 PathEntry::SnappedPoint(node) => self.router.map.node(*node),
                 PathEntry::FreePoint(pt) => *pt,
-                PathEntry::Edge(dir_edge) => {
-                    pts.extend(edge_geometry(&self.router.map, *dir_edge));
+                PathEntry::EdgePoint(pos) => self.router.edge_position_pt(*pos),
+                PathEntry::Edge(_) | PathEntry::PartialEdge { .. } => {
+                    pts.extend(self.router.entry_geometry(*entry).unwrap());
                     continue;
                 }
             };
@@ -890,6 +1062,146 @@ impl JsRouteSnapper {
         result
     }
 
+    // For every edge attribute key, sums how many meters of the route have each value. Freehand
+    // portions don't count.
+    fn attribute_lengths(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut lengths: BTreeMap<&str, BTreeMap<&str, f64>> = BTreeMap::new();
+        for entry in &self.route.full_path {
+            let (edge, fraction) = match entry {
+                PathEntry::Edge(dir_edge) => (dir_edge.0, 1.0),
+                PathEntry::PartialEdge { dir_edge, from, to } => (dir_edge.0, (to - from).abs()),
+                _ => continue,
+            };
+            let length = fraction * self.router.map.edge(edge).length_meters;
+            for (key, value) in self.router.map.attributes.get(edge) {
+                *lengths.entry(key).or_default().entry(value).or_insert(0.0) += length;
+            }
+        }
+        lengths
+            .into_iter()
+            .map(|(key, per_value)| {
+                let per_value = per_value
+                    .into_iter()
+                    .map(|(value, length)| (value.to_string(), length.into()))
+                    .collect();
+                (key.to_string(), serde_json::Value::Object(per_value))
+            })
+            .collect()
+    }
+
+    // If the graph has OSM IDs, returns the ways and nodes the route passes through, in order and
+    // without repeating consecutive entries. Freehand portions and nodes added by the importer are
+    // skipped.
+    fn osm_ids(&self) -> Option<(Vec<i64>, Vec<i64>)> {
+        let map = &self.router.map;
+        if map.osm_way_ids.is_empty() || map.osm_node_ids.is_empty() {
+            return None;
+        }
+        let mut way_ids = Vec::new();
+        let mut node_ids = Vec::new();
+        for entry in &self.route.full_path {
+            match entry {
+                PathEntry::Edge(dir_edge) | PathEntry::PartialEdge { dir_edge, .. } => {
+                    let id = map.osm_way_ids[dir_edge.0 .0 as usize];
+                    if way_ids.last() != Some(&id) {
+                        way_ids.push(id);
+                    }
+                }
+                PathEntry::SnappedPoint(node) => {
+                    if let Some(id) = map.osm_node_ids[node.0 as usize] {
+                        if node_ids.last() != Some(&id) {
+                            node_ids.push(id);
+                        }
+                    }
+                }
+                PathEntry::FreePoint(_) | PathEntry::EdgePoint(_) => {}
+            }
+        }
+        Some((way_ids, node_ids))
+    }
+
+    // If the graph's default costs are travel times, sums them along the route. This ignores any
+    // cost profile in use. Freehand portions don't count.
+    fn route_duration(&self) -> Option<f64> {
+        let map = &self.router.map;
+        if !map.costs_in_seconds {
+            return None;
+        }
+        let mut total = 0.0;
+        for entry in &self.route.full_path {
+            let (dir_edge, fraction) = match entry {
+                PathEntry::Edge(dir_edge) => (*dir_edge, 1.0),
+                PathEntry::PartialEdge { dir_edge, from, to } => (*dir_edge, (to - from).abs()),
+                _ => continue,
+            };
+            let costs = if dir_edge.1 == FORWARDS {
+                &map.override_forward_costs
+            } else {
+                &map.override_backward_costs
+            };
+            if let Some(Some(cost)) = costs.get(dir_edge.0 .0 as usize) {
+                total += fraction * cost;
+            }
+        }
+        Some(total)
+    }
+
+    // If the graph has elevation data, returns [distance along the route, height] in meters at
+    // every node and snapped waypoint. Freehand points and places with unknown heights are
+    // skipped, but still count towards the distance.
+    fn elevation_profile(&self) -> Option<Vec<[f64; 2]>> {
+        let map = &self.router.map;
+        if map.node_elevations.is_empty() {
+            return None;
+        }
+        let mut profile: Vec<[f64; 2]> = Vec::new();
+        let mut distance = 0.0;
+        let mut prev_pt: Option<Coord> = None;
+        let height = |node: NodeID| {
+            map.node_elevations
+                .get(node.0 as usize)
+                .filter(|x| !x.is_nan())
+                .copied()
+        };
+        for entry in &self.route.full_path {
+            let elevation = match entry {
+                PathEntry::Edge(dir_edge) => {
+                    distance += map.edge(dir_edge.0).length_meters;
+                    prev_pt = None;
+                    continue;
+                }
+                PathEntry::PartialEdge { dir_edge, from, to } => {
+                    distance += (to - from).abs() * map.edge(dir_edge.0).length_meters;
+                    prev_pt = None;
+                    continue;
+                }
+                PathEntry::SnappedPoint(node) => height(*node),
+                PathEntry::EdgePoint(pos) => {
+                    let edge = map.edge(pos.edge);
+                    match (height(edge.node1), height(edge.node2)) {
+                        (Some(e1), Some(e2)) => Some(e1 + (e2 - e1) * pos.fraction as f32),
+                        _ => None,
+                    }
+                }
+                PathEntry::FreePoint(_) => None,
+            };
+
+            // Points right after each other are connected by a straight line
+            let pt = self.router.waypoint_pt(entry.to_waypt().unwrap());
+            if let Some(prev) = prev_pt {
+                distance += Point::from(prev).haversine_distance(&Point::from(pt));
+            }
+            prev_pt = Some(pt);
+
+            if let Some(elevation) = elevation {
+                if profile.last().map(|x| x[0]) != Some(distance) {
+                    profile.push([distance, elevation as f64]);
+                }
+            }
+        }
+        Some(profile)
+    }
+
     fn into_polygon_area(&self) -> Option<Geometry> {
         if !self.route.is_closed_area() {
             return None;
@@ -899,28 +1211,35 @@ impl JsRouteSnapper {
     }
 
     fn to_pt(&self, waypt: Waypoint) -> HashedPoint {
-        match waypt {
-            Waypoint::Snapped(node) => hash_pt(self.router.map.node(node)),
-            Waypoint::Free(pt) => hash_pt(pt),
-        }
+        hash_pt(self.router.waypoint_pt(waypt))
     }
 
     fn name_waypoint(&self, waypt: &Waypoint) -> String {
         match waypt {
             Waypoint::Snapped(node) => {
+                // Some edges might only be routable in one direction. Unnamed edges are only
+                // mentioned if nothing else is named.
                 let edge_names = self
                     .router
                     .graph
-                    .edges(*node)
-                    .map(|(_, _, edge)| {
-                        self.router.map.edges[edge.0 .0 as usize]
-                            .name
-                            .clone()
-                            .unwrap_or_else(|| "???".to_string())
+                    .edges_directed(*node, Outgoing)
+                    .chain(self.router.graph.edges_directed(*node, Incoming))
+                    .filter_map(|(_, _, edge)| {
+                        self.router.map.edges[edge.0 .0 as usize].name.clone()
                     })
                     .collect::<BTreeSet<_>>();
+                if edge_names.is_empty() {
+                    return "???".to_string();
+                }
                 plain_list_names(edge_names)
             }
+            Waypoint::OnEdge(pos) => self
+                .router
+                .map
+                .edge(pos.edge)
+                .name
+                .clone()
+                .unwrap_or_else(|| "???".to_string()),
             Waypoint::Free(_) => "???".to_string(),
         }
     }
@@ -1004,16 +1323,14 @@ impl Route {
             // Always add every waypoint
             self.full_path.push(pair[0].to_path_entry());
 
-            if let [Waypoint::Snapped(node1), Waypoint::Snapped(node2)] = pair {
-                if let Some(entries) = router.pathfind(*node1, *node2, &self.full_path) {
-                    // Don't repeat that snapped point
-                    assert_eq!(self.full_path.pop(), Some(PathEntry::SnappedPoint(*node1)));
-                    self.full_path.extend(entries);
-                }
-                // If the points are disconnected in the graph, just act like there's a freehand
-                // line between them. It's better than breaking.
-                // (We don't need to do anything here -- the other point will get added)
+            if let Some(entries) = router.pathfind_waypoints(pair[0], pair[1], &self.full_path) {
+                // Don't repeat that snapped point
+                assert_eq!(self.full_path.pop(), Some(pair[0].to_path_entry()));
+                self.full_path.extend(entries);
             }
+            // If the points are disconnected in the graph or one is freehand, just act like
+            // there's a freehand line between them. It's better than breaking.
+            // (We don't need to do anything here -- the other point will get added)
         }
         // Always add the last if it's different
         if let Some(last) = self.waypoints.last() {
@@ -1032,11 +1349,170 @@ impl Route {
 }
 
 impl Router {
-    // Returns a sequence of (SnappedPoint, Edge, SnappedPoint, Edge..., SnappedPoint)
+    // Returns a sequence of entries starting and ending with the two waypoints, or None if either
+    // waypoint is freehand or there's no path. Waypoints on an edge can leave and arrive through
+    // either endpoint of the edge, if that direction is routable, so this tries every combination
+    // and keeps the cheapest, including turns onto and off of the partial edges.
+    fn pathfind_waypoints(
+        &self,
+        waypt1: Waypoint,
+        waypt2: Waypoint,
+        prev_path: &Vec<PathEntry>,
+    ) -> Option<Vec<PathEntry>> {
+        let mut best: Option<(f64, Vec<PathEntry>)> = None;
+        let mut consider = |cost: f64, entries: Vec<PathEntry>| {
+            if !best
+                .as_ref()
+                .is_some_and(|(best_cost, _)| *best_cost <= cost)
+            {
+                best = Some((cost, entries));
+            }
+        };
+
+        // Both points on the same edge can go directly, without visiting any nodes
+        if let (Waypoint::OnEdge(pos1), Waypoint::OnEdge(pos2)) = (waypt1, waypt2) {
+            if pos1.edge == pos2.edge {
+                let dir = pos2.fraction >= pos1.fraction;
+                let dir_edge = DirectedEdge(pos1.edge, dir);
+                if let Some(cost) = self.edge_cost(dir_edge) {
+                    consider(
+                        (pos2.fraction - pos1.fraction).abs() * cost,
+                        vec![
+                            PathEntry::EdgePoint(pos1),
+                            PathEntry::PartialEdge {
+                                dir_edge,
+                                from: pos1.fraction,
+                                to: pos2.fraction,
+                            },
+                            PathEntry::EdgePoint(pos2),
+                        ],
+                    );
+                }
+            }
+        }
+
+        let partial_edge = |entries: &[PathEntry]| {
+            entries.iter().find_map(|entry| match entry {
+                PathEntry::PartialEdge { dir_edge, .. } => Some(*dir_edge),
+                _ => None,
+            })
+        };
+        for (node1, start_cost, prefix) in self.departures(waypt1) {
+            for (node2, end_cost, suffix) in self.arrivals(waypt2) {
+                let (before, after) = (partial_edge(&prefix), partial_edge(&suffix));
+                if let Some(path) = self.pathfind(node1, node2, before, after, prev_path) {
+                    let mut cost = start_cost + end_cost;
+                    for entry in &path {
+                        if let PathEntry::Edge(dir_edge) = entry {
+                            cost += self.edge_cost(*dir_edge).unwrap();
+                        }
+                    }
+                    let mut entries = prefix.clone();
+                    entries.extend(path);
+                    entries.extend(suffix);
+                    if let Some(turns_cost) = self.turns_cost(&entries) {
+                        consider(cost + turns_cost, entries);
+                    }
+                }
+            }
+        }
+
+        best.map(|(_, entries)| entries)
+    }
+
+    // Sums the cost of every turn between edges along the entries, or None if one is forbidden
+    fn turns_cost(&self, entries: &[PathEntry]) -> Option<f64> {
+        let mut total = 0.0;
+        let mut prev_edge = None;
+        let mut via = None;
+        for entry in entries {
+            match entry {
+                PathEntry::Edge(dir_edge) | PathEntry::PartialEdge { dir_edge, .. } => {
+                    if let (Some(from), Some(via)) = (prev_edge, via) {
+                        total += turns::turn_cost(self, from, via, *dir_edge)?;
+                    }
+                    prev_edge = Some(*dir_edge);
+                }
+                PathEntry::SnappedPoint(node) => {
+                    via = Some(*node);
+                }
+                PathEntry::FreePoint(_) | PathEntry::EdgePoint(_) => {}
+            }
+        }
+        Some(total)
+    }
+
+    // The ways to leave a waypoint and reach a node: (node, cost to get there, entries before the
+    // path from that node)
+    fn departures(&self, waypt: Waypoint) -> Vec<(NodeID, f64, Vec<PathEntry>)> {
+        match waypt {
+            Waypoint::Snapped(node) => vec![(node, 0.0, Vec::new())],
+            Waypoint::Free(_) => Vec::new(),
+            Waypoint::OnEdge(pos) => {
+                let edge = self.map.edge(pos.edge);
+                let mut results = Vec::new();
+                for (dir, node, to) in [(FORWARDS, edge.node2, 1.0), (BACKWARDS, edge.node1, 0.0)] {
+                    let dir_edge = DirectedEdge(pos.edge, dir);
+                    if let Some(cost) = self.edge_cost(dir_edge) {
+                        results.push((
+                            node,
+                            (to - pos.fraction).abs() * cost,
+                            vec![
+                                PathEntry::EdgePoint(pos),
+                                PathEntry::PartialEdge {
+                                    dir_edge,
+                                    from: pos.fraction,
+                                    to,
+                                },
+                            ],
+                        ));
+                    }
+                }
+                results
+            }
+        }
+    }
+
+    // The ways to arrive at a waypoint from a node: (node, cost from there, entries after the path
+    // to that node)
+    fn arrivals(&self, waypt: Waypoint) -> Vec<(NodeID, f64, Vec<PathEntry>)> {
+        match waypt {
+            Waypoint::Snapped(node) => vec![(node, 0.0, Vec::new())],
+            Waypoint::Free(_) => Vec::new(),
+            Waypoint::OnEdge(pos) => {
+                let edge = self.map.edge(pos.edge);
+                let mut results = Vec::new();
+                for (dir, node, from) in [(FORWARDS, edge.node1, 0.0), (BACKWARDS, edge.node2, 1.0)]
+                {
+                    let dir_edge = DirectedEdge(pos.edge, dir);
+                    if let Some(cost) = self.edge_cost(dir_edge) {
+                        results.push((
+                            node,
+                            (pos.fraction - from).abs() * cost,
+                            vec![
+                                PathEntry::PartialEdge {
+                                    dir_edge,
+                                    from,
+                                    to: pos.fraction,
+                                },
+                                PathEntry::EdgePoint(pos),
+                            ],
+                        ));
+                    }
+                }
+                results
+            }
+        }
+    }
+
+    // Returns a sequence of (SnappedPoint, Edge, SnappedPoint, Edge..., SnappedPoint). `before` and
+    // `after` are the partial edges leading into `node1` and out of `node2`, if any.
     fn pathfind(
         &self,
         node1: NodeID,
         node2: NodeID,
+        before: Option<DirectedEdge>,
+        after: Option<DirectedEdge>,
         prev_path: &Vec<PathEntry>,
     ) -> Option<Vec<PathEntry>> {
         // Penalize visiting edges we've been to before, so that waypoints don't cause us to double
@@ -1045,47 +1521,228 @@ impl Router {
         let mut avoid = HashSet::new();
         if self.config.avoid_doubling_back {
             for entry in prev_path {
-                if let PathEntry::Edge(e) = entry {
+                if let PathEntry::Edge(e) | PathEntry::PartialEdge { dir_edge: e, .. } = entry {
                     avoid.insert(e.0);
                 }
             }
         }
 
-        let node2_pt = self.map.node(node2);
-
-        let (_, path) = petgraph::algo::astar(
-            &self.graph,
-            node1,
-            |i| i == node2,
-            |(_, _, dir_edge)| {
-                let penalty = if avoid.contains(&dir_edge.0) {
-                    2.0
-                } else {
-                    1.0
-                };
-                let edge = self.map.edge(dir_edge.0);
-                let cost = if dir_edge.1 == FORWARDS {
-                    edge.forward_cost.unwrap()
-                } else {
-                    edge.backward_cost.unwrap()
-                };
-                penalty * cost
-            },
-            |i| Point::from(self.map.node(i)).haversine_distance(&Point::from(node2_pt)),
-        )?;
-
-        let mut entries = Vec::new();
-        for pair in path.windows(2) {
-            entries.push(PathEntry::SnappedPoint(pair[0]));
-            entries.push(PathEntry::Edge(
-                *self.graph.edge_weight(pair[0], pair[1]).unwrap(),
-            ));
+        let mut entries = vec![PathEntry::SnappedPoint(node1)];
+        for (dir_edge, node) in self
+            .pathfinder()
+            .pathfind(self, node1, node2, before, after, &avoid)?
+        {
+            entries.push(PathEntry::Edge(dir_edge));
+            entries.push(PathEntry::SnappedPoint(node));
         }
-        entries.push(PathEntry::SnappedPoint(*path.last().unwrap()));
         assert!(entries[0] == PathEntry::SnappedPoint(node1));
         assert!(*entries.last().unwrap() == PathEntry::SnappedPoint(node2));
         Some(entries)
     }
+
+    // The fastest way to find paths with the current settings
+    fn pathfinder(&self) -> &dyn Pathfinder {
+        match &self.contraction_hierarchy {
+            Some(ch) if ch.supports(self) => ch,
+            _ => &AStar,
+        }
+    }
+
+    // None if this direction isn't routable
+    fn edge_cost(&self, dir_edge: DirectedEdge) -> Option<f64> {
+        let edge = self.map.edge(dir_edge.0);
+        if dir_edge.1 == FORWARDS {
+            edge.forward_cost
+        } else {
+            edge.backward_cost
+        }
+    }
+
+    fn waypoint_pt(&self, waypt: Waypoint) -> Coord {
+        match waypt {
+            Waypoint::Snapped(node) => self.map.node(node),
+            Waypoint::Free(pt) => pt,
+            Waypoint::OnEdge(pos) => self.edge_position_pt(pos),
+        }
+    }
+
+    fn edge_position_pt(&self, pos: EdgePosition) -> Coord {
+        self.map
+            .edge(pos.edge)
+            .geometry
+            .line_interpolate_point(pos.fraction)
+            .unwrap()
+            .into()
+    }
+
+    // The geometry for Edge and PartialEdge entries, in the direction of travel
+    fn entry_geometry(&self, entry: PathEntry) -> Option<Vec<Coord>> {
+        match entry {
+            PathEntry::Edge(dir_edge) => Some(edge_geometry(&self.map, dir_edge)),
+            PathEntry::PartialEdge { dir_edge, from, to } => {
+                let mut pts = slice_line_string(
+                    &self.map.edge(dir_edge.0).geometry,
+                    from.min(to),
+                    from.max(to),
+                );
+                if from > to {
+                    pts.reverse();
+                }
+                Some(pts)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Calculates the length and cost of edges starting from `first_edge`, checking every cost profile
+fn prepare_edges(
+    map: &mut RouteSnapperMap,
+    first_edge: usize,
+    profile: Option<&str>,
+) -> Result<(), String> {
+    set_edge_lengths(map, first_edge);
+    check_all_cost_lengths(map)?;
+    set_edge_costs(map, profile)
+}
+
+fn set_edge_lengths(map: &mut RouteSnapperMap, first_edge: usize) {
+    for edge in &mut map.edges[first_edge..] {
+        edge.length_meters = edge.geometry.haversine_length();
+    }
+}
+
+fn check_all_cost_lengths(map: &RouteSnapperMap) -> Result<(), String> {
+    check_cost_lengths(map, None)?;
+    for profile in &map.cost_profiles {
+        check_cost_lengths(map, Some(&profile.name))?;
+    }
+    Ok(())
+}
+
+fn build_turns(map: &RouteSnapperMap) -> HashMap<(EdgeID, NodeID, EdgeID), Option<f64>> {
+    map.turns
+        .iter()
+        .map(|turn| ((turn.from, turn.via, turn.to), turn.cost))
+        .collect()
+}
+
+// Euclidean distance on WGS84 coordinates works because we're just finding the closest point to
+// the cursor, and always in a pretty small area. Using GeodesicDistance as a distance function is
+// an alternative.
+fn build_rtrees(map: &RouteSnapperMap) -> (NodeTree, EdgeTree) {
+    let mut nodes = Vec::new();
+    for (idx, pt) in map.nodes.iter().enumerate() {
+        nodes.push(GeomWithData::new([pt.x, pt.y], NodeID(idx as u32)));
+    }
+    let mut lines = Vec::new();
+    for (idx, edge) in map.edges.iter().enumerate() {
+        for line in edge.geometry.lines() {
+            lines.push(GeomWithData::new(
+                Line::new([line.start.x, line.start.y], [line.end.x, line.end.y]),
+                EdgeID(idx as u32),
+            ));
+        }
+    }
+    (RTree::bulk_load(nodes), RTree::bulk_load(lines))
+}
+
+// Only directions with a cost are routable
+fn build_graph(map: &RouteSnapperMap) -> Graph {
+    let mut graph: Graph = DiGraphMap::new();
+    for (idx, e) in map.edges.iter().enumerate() {
+        let id = EdgeID(idx as u32);
+        if e.forward_cost.is_some() {
+            graph.add_edge(e.node1, e.node2, DirectedEdge(id, FORWARDS));
+        }
+        if e.backward_cost.is_some() {
+            graph.add_edge(e.node2, e.node1, DirectedEdge(id, BACKWARDS));
+        }
+    }
+    graph
+}
+
+// The lowest cost per meter of any routable direction. Straight-line distance times this never
+// overestimates the cost between two nodes, even when costs are travel times or overridden.
+fn min_cost_per_meter(map: &RouteSnapperMap) -> f64 {
+    let mut min = f64::INFINITY;
+    for edge in &map.edges {
+        if edge.length_meters > 0.0 {
+            for cost in [edge.forward_cost, edge.backward_cost]
+                .into_iter()
+                .flatten()
+            {
+                min = min.min(cost / edge.length_meters);
+            }
+        }
+    }
+    if min.is_finite() {
+        min.max(0.0)
+    } else {
+        0.0
+    }
+}
+
+// Returns the [forward, backward] costs from a named profile or the defaults, making sure they
+// have the right length.
+fn check_cost_lengths<'a>(
+    map: &'a RouteSnapperMap,
+    profile: Option<&str>,
+) -> Result<[&'a [Option<f64>]; 2], String> {
+    let (forward, backward, label) = match profile {
+        Some(name) => {
+            let profile = map
+                .cost_profile(name)
+                .ok_or_else(|| format!("No cost profile named {name}"))?;
+            (
+                &profile.forward_costs,
+                &profile.backward_costs,
+                format!("cost profile {name}"),
+            )
+        }
+        None => (
+            &map.override_forward_costs,
+            &map.override_backward_costs,
+            "override".to_string(),
+        ),
+    };
+    if !forward.is_empty() && forward.len() != map.edges.len() {
+        return Err(format!(
+            "{label} forward costs length doesn't match edges length"
+        ));
+    }
+    if !backward.is_empty() && backward.len() != map.edges.len() {
+        return Err(format!(
+            "{label} backward costs length doesn't match edges length"
+        ));
+    }
+    Ok([forward, backward])
+}
+
+// Fills out the cost of every edge from a named profile or the defaults. `length_meters` must
+// already be set. If the costs for a direction are empty, use the length.
+fn set_edge_costs(map: &mut RouteSnapperMap, profile: Option<&str>) -> Result<(), String> {
+    let [forward, backward] = check_cost_lengths(map, profile)?;
+    let costs: Vec<(Option<f64>, Option<f64>)> = map
+        .edges
+        .iter()
+        .enumerate()
+        .map(|(idx, edge)| {
+            let pick = |costs: &[Option<f64>]| {
+                if costs.is_empty() {
+                    Some(edge.length_meters)
+                } else {
+                    costs[idx]
+                }
+            };
+            (pick(forward), pick(backward))
+        })
+        .collect();
+    for (edge, (forward_cost, backward_cost)) in map.edges.iter_mut().zip(costs) {
+        edge.forward_cost = forward_cost;
+        edge.backward_cost = backward_cost;
+    }
+    Ok(())
 }
 
 fn edge_geometry(map: &RouteSnapperMap, dir_edge: DirectedEdge) -> Vec<Coord> {
@@ -1096,6 +1753,29 @@ fn edge_geometry(map: &RouteSnapperMap, dir_edge: DirectedEdge) -> Vec<Coord> {
     pts
 }
 
+// Returns the part of a line-string between two fractions of its Euclidean length
+fn slice_line_string(linestring: &LineString, start: f64, end: f64) -> Vec<Coord> {
+    let total = linestring.euclidean_length();
+    let interpolate =
+        |fraction: f64| -> Coord { linestring.line_interpolate_point(fraction).unwrap().into() };
+
+    let mut pts = vec![interpolate(start)];
+    let mut dist_so_far = 0.0;
+    for line in linestring.lines() {
+        dist_so_far += line.euclidean_length();
+        if total == 0.0 {
+            break;
+        }
+        let fraction = dist_so_far / total;
+        if fraction > start && fraction < end {
+            pts.push(line.end);
+        }
+    }
+    pts.push(interpolate(end));
+    pts.dedup();
+    pts
+}
+
 fn err_to_js<E: std::fmt::Display>(err: E) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
@@ -1106,6 +1786,14 @@ struct RouteWaypoint {
     lon: f64,
     lat: f64,
     snapped: bool,
+    // Only set for snapped waypoints partway along an edge
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    on_edge: bool,
+    // The exact position of an on_edge waypoint. Only trusted if it still matches lon and lat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    edge: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fraction: Option<f64>,
 }
 
 // Per https://datatracker.ietf.org/doc/html/rfc7946#section-11.2, 6 decimal places (10cm) is
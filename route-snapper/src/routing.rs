@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+
+use geo::{HaversineDistance, Point};
+use route_snapper_graph::ch::PreparedHierarchy;
+use route_snapper_graph::{EdgeID, NodeID};
+
+use crate::{turns, DirectedEdge, Router, BACKWARDS, FORWARDS};
+
+/// A way to find the cheapest path between two nodes. `Router::pathfinder` picks the first one
+/// that supports the current settings.
+pub trait Pathfinder {
+    /// False if this can't respect the router's current cost profile, turns, or config
+    fn supports(&self, router: &Router) -> bool;
+
+    /// Returns each edge along the path and the node it ends at. Edges in `avoid` cost double. If
+    /// the path continues from part of an edge into `node1`, or out of `node2` onto part of an
+    /// edge, `before` and `after` are those edges, so turns onto and off of them count.
+    fn pathfind(
+        &self,
+        router: &Router,
+        node1: NodeID,
+        node2: NodeID,
+        before: Option<DirectedEdge>,
+        after: Option<DirectedEdge>,
+        avoid: &HashSet<EdgeID>,
+    ) -> Option<Vec<(DirectedEdge, NodeID)>>;
+}
+
+/// A* over the whole graph. This supports everything, but gets slow on big graphs.
+pub struct AStar;
+
+impl Pathfinder for AStar {
+    fn supports(&self, _: &Router) -> bool {
+        true
+    }
+
+    fn pathfind(
+        &self,
+        router: &Router,
+        node1: NodeID,
+        node2: NodeID,
+        before: Option<DirectedEdge>,
+        after: Option<DirectedEdge>,
+        avoid: &HashSet<EdgeID>,
+    ) -> Option<Vec<(DirectedEdge, NodeID)>> {
+        let node2_pt = router.map.node(node2);
+        let edge_cost = |dir_edge: DirectedEdge| {
+            let penalty = if avoid.contains(&dir_edge.0) {
+                2.0
+            } else {
+                1.0
+            };
+            penalty * router.edge_cost(dir_edge).unwrap()
+        };
+        // Costs might be travel times, so scale distance to make sure it's never an overestimate
+        let heuristic = |i: NodeID| {
+            router.min_cost_per_meter
+                * Point::from(router.map.node(i)).haversine_distance(&Point::from(node2_pt))
+        };
+
+        if !router.turns.is_empty() || router.map.u_turn_cost != 0.0 {
+            // Turns need a slower edge-based search
+            return turns::pathfind_with_turns(
+                router, node1, node2, before, after, edge_cost, heuristic,
+            );
+        }
+
+        // Without turn costs, `before` and `after` don't matter
+        let (_, path) = petgraph::algo::astar(
+            &router.graph,
+            node1,
+            |i| i == node2,
+            |(_, _, dir_edge)| edge_cost(*dir_edge),
+            heuristic,
+        )?;
+        Some(
+            path.windows(2)
+                .map(|pair| {
+                    (
+                        *router.graph.edge_weight(pair[0], pair[1]).unwrap(),
+                        pair[1],
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A contraction hierarchy built by the importer. It only knows the default costs, so it can't
+/// be used with cost profiles, turns, or `avoid_doubling_back`.
+impl Pathfinder for PreparedHierarchy {
+    fn supports(&self, router: &Router) -> bool {
+        router.cost_profile.is_none()
+            && router.turns.is_empty()
+            && router.map.u_turn_cost == 0.0
+            && !router.config.avoid_doubling_back
+    }
+
+    fn pathfind(
+        &self,
+        router: &Router,
+        node1: NodeID,
+        node2: NodeID,
+        _: Option<DirectedEdge>,
+        _: Option<DirectedEdge>,
+        _: &HashSet<EdgeID>,
+    ) -> Option<Vec<(DirectedEdge, NodeID)>> {
+        let (_, path) = PreparedHierarchy::pathfind(self, node1, node2)?;
+        Some(
+            path.into_iter()
+                .map(|(edge, forwards)| {
+                    let e = router.map.edge(edge);
+                    if forwards {
+                        (DirectedEdge(edge, FORWARDS), e.node2)
+                    } else {
+                        (DirectedEdge(edge, BACKWARDS), e.node1)
+                    }
+                })
+                .collect(),
+        )
+    }
+}
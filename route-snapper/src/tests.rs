@@ -1,3 +1,5 @@
+use route_snapper_graph::test_util::make_map;
+
 use crate::*;
 
 // The NodeIDs depend on the real southwark.bin graph! If the path between two nodes happens to
@@ -225,3 +227,468 @@ fn drag(snapper: &mut JsRouteSnapper, from: Waypoint, to: Waypoint) {
     optionally_mouseover_waypt(snapper, to);
     snapper.on_mouse_up();
 }
+
+#[test]
+fn test_attribute_lengths() {
+    let mut map = southwark();
+    for idx in 0..map.edges.len() {
+        let value = if idx % 2 == 0 { "even" } else { "odd" };
+        map.attributes.set(EdgeID(idx as u32), "parity", value);
+    }
+    let snapper = route_waypt1_to_waypt2(&map);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let length = feature.property("length_meters").unwrap().as_f64().unwrap();
+    let per_value = feature.property("attributes").unwrap()["parity"]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|x| x.as_f64().unwrap())
+        .sum::<f64>();
+    assert!((length - per_value).abs() < 0.1);
+}
+
+#[test]
+fn test_cost_profiles() {
+    let mut map = southwark();
+    map.cost_profiles.push(route_snapper_graph::CostProfile {
+        name: "closed".to_string(),
+        forward_costs: vec![None; map.edges.len()],
+        backward_costs: vec![None; map.edges.len()],
+    });
+    let mut snapper = route_waypt1_to_waypt2(&map);
+    let default_path = snapper.route.full_path.clone();
+    assert!(default_path.len() > 2);
+
+    // Nothing is routable, so we get a straight line
+    snapper
+        .set_cost_profile(Some("closed".to_string()))
+        .unwrap();
+    assert_eq!(
+        snapper.route.full_path,
+        vec![WAYPT1.to_path_entry(), WAYPT2.to_path_entry()]
+    );
+
+    snapper.set_cost_profile(None).unwrap();
+    assert_eq!(snapper.route.full_path, default_path);
+}
+
+#[test]
+fn test_turn_restrictions() {
+    let mut map = southwark();
+    let snapper = route_waypt1_to_waypt2(&map);
+
+    // Find the first turn along the route
+    let (from, via, to) = match snapper.route.full_path[1..4] {
+        [PathEntry::Edge(from), PathEntry::SnappedPoint(via), PathEntry::Edge(to)] => {
+            (from, via, to)
+        }
+        _ => panic!("route is too short"),
+    };
+
+    // Forbid it, and make sure the new route avoids it
+    map.turns.push(route_snapper_graph::Turn {
+        from: from.0,
+        via,
+        to: to.0,
+        cost: None,
+    });
+    let snapper = route_waypt1_to_waypt2(&map);
+    assert!(snapper.route.full_path.len() > 2);
+    for window in snapper.route.full_path.windows(3) {
+        assert_ne!(
+            window,
+            [
+                PathEntry::Edge(from),
+                PathEntry::SnappedPoint(via),
+                PathEntry::Edge(to)
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_u_turn_cost() {
+    // A road from a to w through v, with a branch from v to b. Going straight from a to b is
+    // forbidden, so the route can turn around at w, or take a long way round through c.
+    let [a, v, w, b, c] = [0, 1, 2, 3, 4].map(NodeID);
+    let mut map = make_map(
+        vec![
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 0.001, y: 0.0 },
+            Coord { x: 0.002, y: 0.0 },
+            Coord { x: 0.001, y: 0.001 },
+            Coord { x: 0.0, y: 0.01 },
+        ],
+        &[(a.0, v.0), (v.0, w.0), (v.0, b.0), (a.0, c.0), (c.0, b.0)],
+    );
+    map.turns.push(route_snapper_graph::Turn {
+        from: EdgeID(0),
+        via: v,
+        to: EdgeID(2),
+        cost: None,
+    });
+    let nodes_visited = |map: &RouteSnapperMap| -> Vec<NodeID> {
+        let snapper = JsRouteSnapper::new(&map.to_bytes().unwrap()).unwrap();
+        snapper
+            .router
+            .pathfind(a, b, None, None, &Vec::new())
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                PathEntry::SnappedPoint(node) => Some(node),
+                _ => None,
+            })
+            .collect()
+    };
+
+    assert_eq!(nodes_visited(&map), vec![a, v, w, v, b]);
+    map.u_turn_cost = 10_000.0;
+    assert_eq!(nodes_visited(&map), vec![a, c, b]);
+}
+
+#[test]
+fn test_snap_to_edges() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.snap_to_edges = true;
+
+    // The middle of the longest edge is far from any node
+    let (idx, _) = snapper
+        .router
+        .map
+        .edges
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.length_meters.total_cmp(&b.length_meters))
+        .unwrap();
+    let pt = snapper.router.edge_position_pt(EdgePosition {
+        edge: EdgeID(idx as u32),
+        fraction: 0.5,
+    });
+
+    must_mouseover_waypt(&mut snapper, WAYPT1);
+    snapper.on_click();
+    snapper.on_mouse_move(pt.x, pt.y, 1.0);
+    let Mode::Hovering(on_edge @ Waypoint::OnEdge(mouseover)) = snapper.mode else {
+        panic!("not hovering on the edge: {:?}", snapper.mode);
+    };
+    assert_eq!(mouseover.edge, EdgeID(idx as u32));
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, on_edge]);
+    let n = snapper.route.full_path.len();
+    assert!(matches!(
+        snapper.route.full_path[n - 2],
+        PathEntry::PartialEdge { .. }
+    ));
+    assert_eq!(snapper.route.full_path[n - 1], on_edge.to_path_entry());
+
+    // The waypoint survives a round-trip through toFinalFeature
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let waypoints: Vec<RouteWaypoint> =
+        serde_json::from_value(feature.property("waypoints").unwrap().clone()).unwrap();
+    assert!(!waypoints[0].on_edge);
+    assert!(waypoints[1].on_edge);
+    assert_eq!(snapper.restore_waypoint(&waypoints[0]), Some(WAYPT1));
+    assert_eq!(snapper.restore_waypoint(&waypoints[1]), Some(on_edge));
+
+    // If the edge ID doesn't match the position, snap to the closest edge instead
+    let mut moved = serde_json::to_value(&waypoints[1]).unwrap();
+    moved["edge"] = (mouseover.edge.0 + 1).into();
+    let moved: RouteWaypoint = serde_json::from_value(moved).unwrap();
+    match snapper.restore_waypoint(&moved) {
+        Some(Waypoint::OnEdge(pos)) => {
+            assert_eq!(pos.edge, mouseover.edge);
+            assert!((pos.fraction - mouseover.fraction).abs() < 0.01);
+        }
+        x => panic!("waypoint restored as {x:?}"),
+    }
+}
+
+#[test]
+fn test_turns_at_edge_waypoints() {
+    // A waypoint in the middle of the edge from p to q. Going to t is shortest through q, or it
+    // can go the long way round through r.
+    let [p, q, t, r] = [0, 1, 2, 3].map(NodeID);
+    let mut map = make_map(
+        vec![
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 0.002, y: 0.0 },
+            Coord { x: 0.003, y: 0.001 },
+            Coord { x: 0.0, y: 0.01 },
+        ],
+        &[(p.0, q.0), (q.0, t.0), (p.0, r.0), (r.0, t.0)],
+    );
+    let on_edge = Waypoint::OnEdge(EdgePosition {
+        edge: EdgeID(0),
+        fraction: 0.5,
+    });
+    let nodes_visited = |map: &RouteSnapperMap, waypt1, waypt2| -> Vec<NodeID> {
+        let snapper = JsRouteSnapper::new(&map.to_bytes().unwrap()).unwrap();
+        snapper
+            .router
+            .pathfind_waypoints(waypt1, waypt2, &Vec::new())
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                PathEntry::SnappedPoint(node) => Some(node),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(
+        nodes_visited(&map, on_edge, Waypoint::Snapped(t)),
+        vec![q, t]
+    );
+    assert_eq!(
+        nodes_visited(&map, Waypoint::Snapped(t), on_edge),
+        vec![t, q]
+    );
+
+    // Forbid turning between the waypoint's edge and the edge to t, in both directions
+    for (from, to) in [(0, 1), (1, 0)] {
+        map.turns.push(route_snapper_graph::Turn {
+            from: EdgeID(from),
+            via: q,
+            to: EdgeID(to),
+            cost: None,
+        });
+    }
+    assert_eq!(
+        nodes_visited(&map, on_edge, Waypoint::Snapped(t)),
+        vec![p, r, t]
+    );
+    assert_eq!(
+        nodes_visited(&map, Waypoint::Snapped(t), on_edge),
+        vec![t, r, p]
+    );
+}
+
+#[test]
+fn test_duration() {
+    let mut map = southwark();
+    // Everything moves at 10m/s
+    let costs: Vec<Option<f64>> = map
+        .edges
+        .iter()
+        .map(|e| Some(e.geometry.haversine_length() / 10.0))
+        .collect();
+    map.override_forward_costs = costs.clone();
+    map.override_backward_costs = costs;
+    map.costs_in_seconds = true;
+    let snapper = route_waypt1_to_waypt2(&map);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let length = feature.property("length_meters").unwrap().as_f64().unwrap();
+    let duration = feature
+        .property("duration_seconds")
+        .unwrap()
+        .as_f64()
+        .unwrap();
+    assert!((length / 10.0 - duration).abs() < 0.1);
+}
+
+#[test]
+fn test_travel_time_routing() {
+    let mut map = southwark();
+    let snapper = route_waypt1_to_waypt2(&map);
+    let shortest = snapper.route.full_path.clone();
+
+    // Everything moves at 10m/s, except the shortest route is a crawl
+    let mut costs: Vec<Option<f64>> = map
+        .edges
+        .iter()
+        .map(|e| Some(e.geometry.haversine_length() / 10.0))
+        .collect();
+    for entry in &shortest {
+        if let PathEntry::Edge(dir_edge) = entry {
+            let idx = dir_edge.0 .0 as usize;
+            costs[idx] = Some(map.edges[idx].geometry.haversine_length());
+        }
+    }
+    map.override_forward_costs = costs.clone();
+    map.override_backward_costs = costs;
+    map.costs_in_seconds = true;
+    let snapper = route_waypt1_to_waypt2(&map);
+    assert_ne!(snapper.route.full_path, shortest);
+
+    // The route is as fast as the best one Dijkstra finds
+    let (Waypoint::Snapped(start), Waypoint::Snapped(end)) = (WAYPT1, WAYPT2) else {
+        unreachable!()
+    };
+    let router = &snapper.router;
+    let fastest = petgraph::algo::dijkstra(&router.graph, start, Some(end), |(_, _, dir_edge)| {
+        router.edge_cost(*dir_edge).unwrap()
+    })[&end];
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let duration = feature
+        .property("duration_seconds")
+        .unwrap()
+        .as_f64()
+        .unwrap();
+    assert!((duration - fastest).abs() < 1e-6);
+}
+
+#[test]
+fn test_elevation_profile() {
+    let mut map = southwark();
+    map.node_elevations = (0..map.nodes.len()).map(|i| (i % 7) as f32).collect();
+    let snapper = route_waypt1_to_waypt2(&map);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let get = |key| feature.property(key).unwrap().as_f64().unwrap();
+    let (Waypoint::Snapped(start), Waypoint::Snapped(end)) = (WAYPT1, WAYPT2) else {
+        unreachable!()
+    };
+    let net_change = map.node_elevations[end.0 as usize] - map.node_elevations[start.0 as usize];
+    assert_eq!(
+        get("ascent_meters") - get("descent_meters"),
+        net_change as f64
+    );
+
+    let profile = feature
+        .property("elevation_profile")
+        .unwrap()
+        .as_array()
+        .unwrap();
+    let last_distance = profile.last().unwrap()[0].as_f64().unwrap();
+    assert!((last_distance - get("length_meters")).abs() < 0.1);
+    let num_points = profile.len();
+
+    // Unknown heights are left out, not written as null
+    map.node_elevations[start.0 as usize] = f32::NAN;
+    let snapper = route_waypt1_to_waypt2(&map);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let profile = feature
+        .property("elevation_profile")
+        .unwrap()
+        .as_array()
+        .unwrap();
+    assert_eq!(profile.len(), num_points - 1);
+    for point in profile {
+        assert!(point[1].as_f64().unwrap().is_finite());
+    }
+    for key in ["ascent_meters", "descent_meters"] {
+        assert!(feature.property(key).unwrap().as_f64().unwrap().is_finite());
+    }
+}
+
+#[test]
+fn test_osm_ids() {
+    let mut map = southwark();
+    map.osm_way_ids = (0..map.edges.len() as i64).map(|i| 1000 + i).collect();
+    map.osm_node_ids = (0..map.nodes.len() as i64).map(Some).collect();
+    let snapper = route_waypt1_to_waypt2(&map);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let get = |key| -> Vec<i64> {
+        feature
+            .property(key)
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_i64().unwrap())
+            .collect()
+    };
+    let (Waypoint::Snapped(start), Waypoint::Snapped(end)) = (WAYPT1, WAYPT2) else {
+        unreachable!()
+    };
+    let node_ids = get("osm_node_ids");
+    assert_eq!(node_ids[0], start.0 as i64);
+    assert_eq!(*node_ids.last().unwrap(), end.0 as i64);
+    let way_ids = get("osm_way_ids");
+    assert!(!way_ids.is_empty());
+    assert!(way_ids.windows(2).all(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn test_tiles() {
+    let map = southwark();
+    let full = route_waypt1_to_waypt2(&map);
+    let expected = full.entire_line_string().unwrap();
+    let start = unhash_pt(full.to_pt(WAYPT1));
+    let end = unhash_pt(full.to_pt(WAYPT2));
+
+    let size = 0.005;
+    let tiles = route_snapper_graph::tiles::split(&map, size).unwrap();
+    let tile_containing = |pt: Coord| {
+        let (x, y) = route_snapper_graph::tiles::tile_for(size, pt.x, pt.y);
+        tiles
+            .iter()
+            .position(|tile| {
+                let info = tile.tile.as_ref().unwrap();
+                (info.x, info.y) == (x, y)
+            })
+            .unwrap()
+    };
+    let (start_tile, end_tile) = (tile_containing(start), tile_containing(end));
+    assert_ne!(start_tile, end_tile);
+
+    // Start with only the tiles with each waypoint
+    let mut snapper = JsRouteSnapper::new(&tiles[start_tile].to_bytes().unwrap()).unwrap();
+    assert!(snapper
+        .add_tile(&tiles[end_tile].to_bytes().unwrap())
+        .unwrap());
+    for pt in [start, end] {
+        snapper.on_mouse_move(pt.x, pt.y, 1.0);
+        assert!(matches!(snapper.mode, Mode::Hovering(Waypoint::Snapped(_))));
+        snapper.on_click();
+    }
+
+    // Loading the tiles in between finds the same route as the full graph
+    for (idx, tile) in tiles.iter().enumerate() {
+        assert_eq!(
+            snapper.add_tile(&tile.to_bytes().unwrap()).unwrap(),
+            idx != start_tile && idx != end_tile
+        );
+    }
+    assert_eq!(snapper.entire_line_string().unwrap(), expected);
+}
+
+#[test]
+fn test_contraction_hierarchy() {
+    let mut map = southwark();
+    let astar = JsRouteSnapper::new(&map.to_bytes().unwrap()).unwrap();
+    map.contraction_hierarchy = Some(route_snapper_graph::ch::build(&map));
+    let mut snapper = JsRouteSnapper::new(&map.to_bytes().unwrap()).unwrap();
+    assert!(snapper.router.contraction_hierarchy.is_some());
+
+    // The router uses the hierarchy, finding a path at least as good as A*. It can do better,
+    // because A* only uses one edge between each pair of nodes.
+    let (Waypoint::Snapped(node1), Waypoint::Snapped(node2)) = (WAYPT1, WAYPT2) else {
+        unreachable!()
+    };
+    let cost = |router: &Router| {
+        router
+            .pathfind(node1, node2, None, None, &Vec::new())
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                PathEntry::Edge(dir_edge) => router.edge_cost(dir_edge),
+                _ => None,
+            })
+            .sum::<f64>()
+    };
+    assert!(cost(&snapper.router) <= cost(&astar.router) + 1e-6);
+
+    // Settings the hierarchy doesn't know about fall back to A*
+    snapper.router.config.avoid_doubling_back = true;
+    assert!(!snapper
+        .router
+        .contraction_hierarchy
+        .as_ref()
+        .unwrap()
+        .supports(&snapper.router));
+}
+
+// The real graph the waypoints refer to
+fn southwark() -> RouteSnapperMap {
+    RouteSnapperMap::from_bytes(&std::fs::read("../examples/southwark.bin").unwrap()).unwrap()
+}
+
+// Draws a route from WAYPT1 to WAYPT2 on a graph
+fn route_waypt1_to_waypt2(map: &RouteSnapperMap) -> JsRouteSnapper {
+    let mut snapper = JsRouteSnapper::new(&map.to_bytes().unwrap()).unwrap();
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    snapper
+}
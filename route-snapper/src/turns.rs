@@ -0,0 +1,163 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use route_snapper_graph::NodeID;
+
+use crate::{DirectedEdge, Router};
+
+/// A* search where the state is the directed edge we arrived on, so turn restrictions and costs
+/// can be applied. Returns each edge along the path and the node it ends at. Turns at `node1` and
+/// `node2` are only checked from `before` and onto `after`, the parts of an edge that a waypoint
+/// partway along it leaves or arrives on. Otherwise those nodes are the user's waypoints.
+pub fn pathfind_with_turns(
+    router: &Router,
+    node1: NodeID,
+    node2: NodeID,
+    before: Option<DirectedEdge>,
+    after: Option<DirectedEdge>,
+    edge_cost: impl Fn(DirectedEdge) -> f64,
+    heuristic: impl Fn(NodeID) -> f64,
+) -> Option<Vec<(DirectedEdge, NodeID)>> {
+    if node1 == node2
+        && match (before, after) {
+            (Some(before), Some(after)) => turn_cost(router, before, node1, after).is_some(),
+            _ => true,
+        }
+    {
+        return Some(Vec::new());
+    }
+
+    let mut best_cost: HashMap<DirectedEdge, f64> = HashMap::new();
+    let mut came_from: HashMap<DirectedEdge, DirectedEdge> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    for (_, to, dir_edge) in router.graph.edges(node1) {
+        let turn_cost = match before {
+            Some(before) => match turn_cost(router, before, node1, *dir_edge) {
+                Some(cost) => cost,
+                None => continue,
+            },
+            None => 0.0,
+        };
+        let cost = turn_cost + edge_cost(*dir_edge);
+        if best_cost.get(dir_edge).is_some_and(|x| *x <= cost) {
+            continue;
+        }
+        best_cost.insert(*dir_edge, cost);
+        queue.push(Item {
+            priority: cost + heuristic(to),
+            cost,
+            dir_edge: *dir_edge,
+            node: to,
+            finished: false,
+        });
+    }
+
+    while let Some(current) = queue.pop() {
+        if current.finished {
+            let mut path = vec![(current.dir_edge, current.node)];
+            let mut at = current.dir_edge;
+            while let Some(prev) = came_from.get(&at) {
+                path.push((*prev, start_node(router, at)));
+                at = *prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        // Skip stale entries
+        if current.cost > best_cost[&current.dir_edge] {
+            continue;
+        }
+        if current.node == node2 {
+            // The path is only done once the turn onto `after` is allowed. Otherwise keep
+            // searching, in case the path can come back to node2 another way.
+            let turn_cost = match after {
+                Some(after) => turn_cost(router, current.dir_edge, node2, after),
+                None => Some(0.0),
+            };
+            if let Some(turn_cost) = turn_cost {
+                queue.push(Item {
+                    priority: current.cost + turn_cost,
+                    cost: current.cost + turn_cost,
+                    finished: true,
+                    ..current
+                });
+            }
+        }
+
+        for (_, to, next) in router.graph.edges(current.node) {
+            let Some(turn_cost) = turn_cost(router, current.dir_edge, current.node, *next) else {
+                continue;
+            };
+            let cost = current.cost + turn_cost + edge_cost(*next);
+            if best_cost.get(next).is_some_and(|x| *x <= cost) {
+                continue;
+            }
+            best_cost.insert(*next, cost);
+            came_from.insert(*next, current.dir_edge);
+            queue.push(Item {
+                priority: cost + heuristic(to),
+                cost,
+                dir_edge: *next,
+                node: to,
+                finished: false,
+            });
+        }
+    }
+    None
+}
+
+/// None if the turn is forbidden
+pub fn turn_cost(
+    router: &Router,
+    from: DirectedEdge,
+    via: NodeID,
+    to: DirectedEdge,
+) -> Option<f64> {
+    let mut cost = 0.0;
+    if from.0 == to.0 && from.1 != to.1 {
+        cost += router.map.u_turn_cost;
+    }
+    match router.turns.get(&(from.0, via, to.0)) {
+        Some(None) => None,
+        Some(Some(extra)) => Some(cost + extra),
+        None => Some(cost),
+    }
+}
+
+// The node a directed edge starts from
+fn start_node(router: &Router, dir_edge: DirectedEdge) -> NodeID {
+    let edge = router.map.edge(dir_edge.0);
+    if dir_edge.1 == crate::FORWARDS {
+        edge.node1
+    } else {
+        edge.node2
+    }
+}
+
+struct Item {
+    priority: f64,
+    cost: f64,
+    dir_edge: DirectedEdge,
+    node: NodeID,
+    // Reached the end, including the turn onto the last edge
+    finished: bool,
+}
+
+// BinaryHeap is a max-heap, so order by lowest priority first
+impl Ord for Item {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Item {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+impl Eq for Item {}